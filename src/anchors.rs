@@ -0,0 +1,407 @@
+use crate::links::{html_ranges, mdbook_options};
+use codespan::{FileId, Span};
+use linkcheck::Link;
+use pulldown_cmark::{Event, Parser, Tag};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    ops::Range,
+};
+
+/// Find all the anchors in a chapter's markdown source.
+///
+/// This mirrors the way `mdbook` generates an `id` for each heading
+/// (including ids set explicitly with `## Title {#custom-id}`) and footnote
+/// definition, so a `#fragment` is valid if and only if it is in the returned
+/// set. Any `id` or `name` attributes in raw HTML (e.g. `<a id="x"></a>`) are
+/// anchors too.
+pub fn collect_anchors(src: &str) -> HashSet<String> {
+    headings(src)
+        .into_iter()
+        .map(|heading| heading.id)
+        .chain(footnote_definitions(src))
+        .chain(
+            html_ranges(src)
+                .into_iter()
+                .flat_map(|range| collect_html_anchors(&src[range])),
+        )
+        .collect()
+}
+
+/// The names of every footnote definition (e.g. the `note` in
+/// `[^note]: ...`).
+///
+/// `mdbook` renders each definition as `<div class="footnote-definition"
+/// id="note">`, and the `[^note]` reference links to `#note`. The references
+/// themselves don't get an `id`.
+fn footnote_definitions(src: &str) -> Vec<String> {
+    Parser::new_ext(src, mdbook_options())
+        .filter_map(|event| match event {
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                Some(name.to_string())
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find headings which have the same text as an earlier heading in the
+/// chapter.
+///
+/// `mdbook` gives them a unique `id` (e.g. `introduction-1`), so a link to
+/// `#introduction` will always go to the first one.
+pub(crate) fn find_duplicate_headings(
+    file: FileId,
+    src: &str,
+) -> Vec<DuplicateHeading> {
+    headings(src)
+        .into_iter()
+        .filter(|heading| heading.id != heading.original_id)
+        .map(|heading| DuplicateHeading {
+            text: heading.text,
+            original_id: heading.original_id,
+            id: heading.id,
+            file,
+            span: Span::new(
+                u32::try_from(heading.range.start).unwrap(),
+                u32::try_from(heading.range.end).unwrap(),
+            ),
+            links: Vec::new(),
+        })
+        .collect()
+}
+
+/// A heading with the same text (and therefore `id`) as an earlier heading in
+/// the same chapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateHeading {
+    /// The heading's text.
+    pub text: String,
+    /// The `id` of the first heading with this text.
+    pub original_id: String,
+    /// The `id` this heading was given instead (e.g. `introduction-1`).
+    pub id: String,
+    /// Which file was the heading found in?
+    pub file: FileId,
+    /// Where the heading occurred in the source text.
+    pub span: Span,
+    /// Links to [`DuplicateHeading::original_id`] in this chapter, which go
+    /// to the first heading.
+    pub links: Vec<Link>,
+}
+
+struct Heading {
+    text: String,
+    /// The `id` we would have used if the text was unique.
+    original_id: String,
+    id: String,
+    range: Range<usize>,
+}
+
+fn headings(src: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut id_counter = HashMap::new();
+    let mut heading: Option<String> = None;
+
+    for (event, range) in
+        Parser::new_ext(src, mdbook_options()).into_offset_iter()
+    {
+        match event {
+            Event::Start(Tag::Heading(_)) => heading = Some(String::new()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(ref mut heading) = heading {
+                    heading.push_str(&text);
+                }
+            },
+            Event::End(Tag::Heading(_)) => {
+                if let Some(text) = heading.take() {
+                    let (text, custom_id) = split_custom_id(&text);
+                    let (original_id, id) = match custom_id {
+                        Some(id) => (id.to_string(), id.to_string()),
+                        None => unique_id(text, &mut id_counter),
+                    };
+                    headings.push(Heading {
+                        text: text.trim().to_string(),
+                        original_id,
+                        id,
+                        range,
+                    });
+                }
+            },
+            _ => {},
+        }
+    }
+
+    headings
+}
+
+/// Find the `id` and `name` attributes of every element in a HTML page, which
+/// are what a `#fragment` can point to.
+///
+/// This doesn't try to fully parse the HTML, it just looks at the attributes
+/// inside each tag.
+pub(crate) fn collect_html_anchors(html: &str) -> HashSet<String> {
+    let attribute = Regex::new(
+        r#"(?i)\s(?:id|name)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>"']+))"#,
+    )
+    .unwrap();
+    let mut anchors = HashSet::new();
+
+    for tag in html.split('<').skip(1) {
+        let tag = match tag.find('>') {
+            Some(end) => &tag[..end],
+            None => tag,
+        };
+
+        for caps in attribute.captures_iter(tag) {
+            if let Some(value) = caps.get(1).or(caps.get(2)).or(caps.get(3)) {
+                anchors.insert(value.as_str().to_string());
+            }
+        }
+    }
+
+    anchors
+}
+
+/// Find the anchor a broken `#fragment` was probably meant to be, if any of
+/// them are close enough.
+///
+/// An anchor is close when it is only a couple of typos away (at most one
+/// edit for every three characters, where swapping two neighbouring
+/// characters counts as one edit), or when the fragment is an abbreviation of
+/// it (e.g. `#intro` for `#introduction`).
+pub(crate) fn closest_anchor<'a, I>(
+    fragment: &str,
+    anchors: I,
+) -> Option<String>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let max_edits = (fragment.chars().count() / 3).max(1);
+
+    anchors
+        .into_iter()
+        .filter(|anchor| !anchor.is_empty() && anchor.as_str() != fragment)
+        .filter_map(|anchor| {
+            let edits = strsim::osa_distance(fragment, anchor);
+            let is_abbreviation =
+                fragment.chars().count() >= 3 && anchor.starts_with(fragment);
+
+            if edits <= max_edits || is_abbreviation {
+                Some((edits, anchor))
+            } else {
+                None
+            }
+        })
+        // prefer the fewest edits, then alphabetical order so the suggestion
+        // doesn't depend on the HashSet's iteration order
+        .min()
+        .map(|(_, anchor)| anchor.clone())
+}
+
+/// Split a heading like `Title {#my-id .class}` into its text and the `id`
+/// set explicitly with a trailing attribute block, if there is one.
+fn split_custom_id(text: &str) -> (&str, Option<&str>) {
+    let trimmed = text.trim_end();
+
+    let attributes = match trimmed.rfind('{') {
+        Some(start) if trimmed.ends_with('}') => start,
+        _ => return (text, None),
+    };
+
+    let id = trimmed[attributes + 1..trimmed.len() - 1]
+        .split_whitespace()
+        .find_map(|attribute| attribute.strip_prefix('#'))
+        .filter(|id| !id.is_empty());
+
+    match id {
+        Some(id) => (&trimmed[..attributes], Some(id)),
+        None => (text, None),
+    }
+}
+
+/// Turn a heading's text into an `id`, appending a counter when the same `id`
+/// has already been used (e.g. `introduction`, `introduction-1`, ...).
+///
+/// This returns both the `id` and the one it was derived from.
+fn unique_id(
+    text: &str,
+    id_counter: &mut HashMap<String, usize>,
+) -> (String, String) {
+    let id = mdbook::utils::normalize_id(text.trim());
+    let count = id_counter.entry(id.clone()).or_insert(0);
+    let unique = match *count {
+        0 => id.clone(),
+        n => format!("{}-{}", id, n),
+    };
+    *count += 1;
+
+    (id, unique)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_the_same_ids_as_mdbook() {
+        let src = r#"
+# Chapter 1
+
+## My Section
+
+## `Code` and *emphasis* & punctuation!
+
+## My Section
+
+Not a heading
+"#;
+        let should_be: HashSet<_> = vec![
+            "chapter-1",
+            "my-section",
+            "code-and-emphasis--punctuation",
+            "my-section-1",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+        let got = collect_anchors(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn raw_html_in_a_chapter_can_add_anchors() {
+        let src = r#"# Chapter 1
+
+Jump to <a id="inline"></a>here.
+
+<div>
+  <a name = 'named'
+     href="./other.md"></a>
+</div>
+
+```html
+<a id="in-a-code-block"></a>
+```
+"#;
+        let should_be: HashSet<_> = vec!["chapter-1", "inline", "named"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let got = collect_anchors(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn headings_can_set_their_own_id() {
+        let src = r#"
+## Title {#my-id}
+
+## Other {#other .class}
+
+## Title
+
+## No id {.class}
+"#;
+        let should_be: HashSet<_> =
+            vec!["my-id", "other", "title", "no-id-class"]
+                .into_iter()
+                .map(String::from)
+                .collect();
+
+        let got = collect_anchors(src);
+
+        assert_eq!(got, should_be);
+        assert_eq!(
+            split_custom_id("Title {#my-id}"),
+            ("Title ", Some("my-id"))
+        );
+        assert_eq!(split_custom_id("Set {#}"), ("Set {#}", None));
+    }
+
+    #[test]
+    fn footnote_definitions_are_anchors() {
+        let src = r#"# Chapter 1
+
+Some text with a footnote[^note] and [a link to it](#note).
+
+[^note]: The footnote.
+"#;
+        let should_be: HashSet<_> = vec!["chapter-1", "note"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let got = collect_anchors(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn detect_duplicate_headings() {
+        let src = "# Chapter 1\n\n## Introduction\n\n## Introduction\n";
+        let mut files = codespan::Files::new();
+        let file = files.add("chapter_1.md", src.to_string());
+
+        let got = find_duplicate_headings(file, src);
+
+        assert_eq!(
+            got,
+            vec![DuplicateHeading {
+                text: String::from("Introduction"),
+                original_id: String::from("introduction"),
+                id: String::from("introduction-1"),
+                file,
+                span: Span::new(30, 46),
+                links: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn find_anchors_in_a_html_page() {
+        let html = r#"<html>
+<body>
+  <h1 id="title">Title</h1>
+  <a name='legacy-anchor'></a>
+  <DIV ID=shouting class="x">Text with id="not-an-attribute"</DIV>
+  <p data-id="ignored">Paragraph</p>
+</body>
+</html>"#;
+        let should_be: HashSet<_> = vec!["title", "legacy-anchor", "shouting"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let got = collect_html_anchors(html);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn suggest_anchors_with_a_similar_name() {
+        let anchors: HashSet<_> =
+            vec!["introduction", "installation", "usage", "faq"]
+                .into_iter()
+                .map(String::from)
+                .collect();
+        let inputs = vec![
+            ("intro", Some("introduction")),
+            ("instalation", Some("installation")),
+            ("usgae", Some("usage")),
+            ("fqa", Some("faq")),
+            ("contributing", None),
+            ("in", None),
+        ];
+
+        for (fragment, should_be) in inputs {
+            let got = closest_anchor(fragment, &anchors);
+            assert_eq!(got.as_deref(), should_be, "{}", fragment);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicPtr, Ordering},
+    Arc,
+};
+
+/// The flag belonging to the [`CancellationToken`] which Ctrl-C should
+/// cancel, if a [`CtrlCHandler`] is installed. A signal handler can't do much
+/// more than touch an atomic, so this points straight at the token's flag.
+static ON_INTERRUPT: AtomicPtr<AtomicBool> =
+    AtomicPtr::new(std::ptr::null_mut());
+
+/// A flag for stopping the link checker early (e.g. because the user pressed
+/// Ctrl-C).
+///
+/// Once it has been cancelled no new web links are checked, and the ones
+/// which weren't checked are skipped so everything else can still be
+/// reported. Clones share the same flag, but separate tokens are completely
+/// independent.
+#[derive(Debug, Default, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a new [`CancellationToken`] which hasn't been cancelled.
+    pub fn new() -> Self { CancellationToken::default() }
+
+    /// Ask the link checker to stop.
+    pub fn cancel(&self) { self.cancelled.store(true, Ordering::SeqCst); }
+
+    /// Has [`CancellationToken::cancel()`] been called?
+    pub fn is_cancelled(&self) -> bool { self.cancelled.load(Ordering::SeqCst) }
+}
+
+/// Turns the first Ctrl-C into a cancellation of `token` instead of killing
+/// the process, restoring the previous handler when dropped. Pressing Ctrl-C a
+/// second time kills the process as normal.
+///
+/// This only works on Unix. Everywhere else Ctrl-C stops the process
+/// straight away.
+pub(crate) struct CtrlCHandler {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+    /// Keeps the flag [`ON_INTERRUPT`] points to alive.
+    #[cfg(unix)]
+    _token: CancellationToken,
+}
+
+impl CtrlCHandler {
+    pub(crate) fn install(token: &CancellationToken) -> Self {
+        #[cfg(unix)]
+        {
+            let flag = Arc::as_ptr(&token.cancelled) as *mut AtomicBool;
+            ON_INTERRUPT.store(flag, Ordering::SeqCst);
+
+            let handler = on_interrupt as extern "C" fn(libc::c_int);
+            // Safety: the handler only touches an atomic and calls signal(),
+            // both of which are async-signal-safe
+            let previous = unsafe {
+                libc::signal(libc::SIGINT, handler as libc::sighandler_t)
+            };
+            CtrlCHandler {
+                previous,
+                _token: token.clone(),
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = token;
+            CtrlCHandler {}
+        }
+    }
+}
+
+impl Drop for CtrlCHandler {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+            // the handler is gone, so nothing else uses the pointer before
+            // the token is dropped
+            ON_INTERRUPT.store(std::ptr::null_mut(), Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    let flag = ON_INTERRUPT.load(Ordering::SeqCst);
+
+    // Safety: the pointer is only set while the CtrlCHandler keeps the token
+    // (and therefore the flag) alive
+    unsafe {
+        if let Some(flag) = flag.as_ref() {
+            flag.store(true, Ordering::SeqCst);
+        }
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_independent() {
+        let first = CancellationToken::new();
+        let second = CancellationToken::new();
+
+        first.clone().cancel();
+
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+    }
+}
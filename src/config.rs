@@ -1,15 +1,20 @@
 use crate::hashed_regex::HashedRegex;
-use anyhow::Error;
+use anyhow::{Context as _, Error};
+use codespan_reporting::diagnostic::Severity;
+use glob::{MatchOptions, Pattern};
 use http::header::{HeaderName, HeaderValue};
 use log::Level;
-use reqwest::Client;
+use reqwest::{Client, ClientBuilder, StatusCode, Url};
+use serde::de::{self, Deserializer, Visitor};
 use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     convert::TryFrom,
     fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
     str::FromStr,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 /// The configuration options available with this backend.
@@ -17,27 +22,316 @@ use std::{
 #[serde(default, rename_all = "kebab-case")]
 pub struct Config {
     /// If a link on the internet is encountered, should we still try to check
-    /// if it's valid? Defaults to `false` because this has a big performance
-    /// impact.
-    pub follow_web_links: bool,
+    /// if it's valid, and how thoroughly? Defaults to [`WebLinkMode::Off`]
+    /// because this has a big performance impact.
+    pub follow_web_links: WebLinkMode,
+    /// Skip every web link without sending any requests, no matter what
+    /// [`Config::follow_web_links`] says. Links to other chapters are still
+    /// checked as normal.
+    ///
+    /// This can also be turned on by setting the [`Config::OFFLINE_ENV_VAR`]
+    /// environment variable.
+    #[serde(default)]
+    pub offline: bool,
     /// Are we allowed to link to files outside of the book's source directory?
     pub traverse_parent_directories: bool,
-    /// A list of URL patterns to ignore when checking remote links.
+    /// Regular expressions for links which shouldn't be checked.
+    ///
+    /// Each pattern is matched against the link's href (searching anywhere
+    /// within it, so anchor the pattern with `^` if necessary), meaning it
+    /// can be used to skip both web and filesystem links.
     #[serde(default)]
     pub exclude: Vec<HashedRegex>,
+    /// Glob patterns (e.g. `api/**`) for chapters whose links shouldn't be
+    /// checked, matched against the chapter's path relative to the source
+    /// directory.
+    ///
+    /// Links to these chapters are still checked as normal.
+    #[serde(default, with = "glob_patterns")]
+    pub ignore_chapters: Vec<Pattern>,
     /// The user-agent used whenever any web requests are made.
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
     /// The number of seconds a cached result is valid for.
     #[serde(default = "default_cache_timeout")]
     pub cache_timeout: u64,
+    /// The number of seconds a web link which was found to be broken stays
+    /// broken in the cache. This should be shorter than
+    /// [`Config::cache_timeout`] so links which have recovered are noticed,
+    /// and a value of `0` (the default) means broken links are always checked
+    /// again.
+    #[serde(default)]
+    pub negative_cache_timeout: u64,
+    /// The number of seconds to wait for a web request before giving up. A
+    /// value of `0` means requests never time out.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Should we check web links with a `HEAD` request (falling back to `GET`
+    /// when the server doesn't support it), or always use `GET`?
+    #[serde(default = "default_use_head_requests")]
+    pub use_head_requests: bool,
+    /// The maximum number of links to check at the same time.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// How many times should a web request be retried after a connection
+    /// error or a [retryable status code](Config::retryable_status_codes)?
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// The number of milliseconds to wait before the first retry. This delay
+    /// is doubled for every subsequent attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// The longest we will wait before retrying a request, in seconds. If a
+    /// server's `Retry-After` header asks for longer than this the link fails
+    /// straight away instead of holding up the rest of the book.
+    #[serde(default = "default_max_retry_delay")]
+    pub max_retry_delay: u64,
+    /// HTTP status codes which indicate a temporary failure (e.g. `429 Too
+    /// Many Requests`) and should be retried.
+    #[serde(default = "default_retryable_status_codes")]
+    pub retryable_status_codes: Vec<u16>,
+    /// Unsuccessful HTTP status codes which should still be treated as valid
+    /// (e.g. sites which respond to bots with `403 Forbidden`).
+    #[serde(default)]
+    pub accepted_status_codes: Vec<u16>,
+    /// The maximum number of requests per second to send to any one host, if
+    /// set.
+    #[serde(default)]
+    pub requests_per_host_per_second: Option<f64>,
+    /// The minimum number of milliseconds between any two web requests, no
+    /// matter which host they are sent to. A value of `0` (the default)
+    /// means there is no delay.
+    #[serde(default)]
+    pub global_delay_ms: u64,
+    /// The maximum number of redirects to follow before giving up on a web
+    /// link.
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: usize,
+    /// The proxy used for all web requests, overriding the `HTTP_PROXY` and
+    /// `HTTPS_PROXY` environment variables.
+    #[serde(default)]
+    pub proxy: Option<Url>,
+    /// Domains which should be accessed directly instead of going through
+    /// the [`Config::proxy`].
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    /// Hosts which are known to be fine but can't be reached from where the
+    /// links are checked (e.g. internal servers when running in CI). Links to
+    /// them are assumed to be valid without sending a request, unlike
+    /// [`Config::exclude`] which skips them entirely.
+    #[serde(default)]
+    pub assume_reachable_hosts: Vec<String>,
+    /// Accept any TLS certificate, even if it is self-signed, expired, or for
+    /// a different host.
+    ///
+    /// **This is dangerous.** It makes web requests vulnerable to
+    /// man-in-the-middle attacks, so only use it for internal servers you
+    /// trust.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// The oldest version of TLS web servers are allowed to use. Links to
+    /// servers which can't negotiate it are reported as broken. Defaults to
+    /// whatever `reqwest` allows.
+    #[serde(default)]
+    pub min_tls_version: Option<TlsVersion>,
+    /// Should the addresses in `mailto:` links be checked? This is purely
+    /// syntactic (i.e. `someone@example.com`) because we can't tell whether
+    /// an address exists without emailing it.
+    #[serde(default = "default_check_mailto")]
+    pub check_mailto: bool,
+    /// The schemes (e.g. `tel` or `data`) non-HTTP links may use. These links
+    /// can't be checked, so by default they are skipped, but a link using a
+    /// scheme which isn't in this list will be reported as broken.
+    #[serde(default)]
+    pub allowed_schemes: Option<Vec<String>>,
+    /// The file extensions (e.g. `md` or `png`) links to files in the book
+    /// may have for us to check them. Links to other files (e.g. downloads
+    /// which are generated elsewhere) are skipped, while links without an
+    /// extension are always checked. By default every link is checked.
+    #[serde(default)]
+    pub checked_extensions: Option<Vec<String>>,
+    /// Should broken links be printed as GitHub Actions annotations? This
+    /// happens automatically when the `GITHUB_ACTIONS` environment variable
+    /// is set.
+    #[serde(default)]
+    pub github_annotations: bool,
+    /// Where to save a JSON report of every link that was checked, relative to
+    /// the book's root directory.
+    #[serde(default)]
+    pub report_path: Option<PathBuf>,
+    /// Where to save a JUnit XML report of every link that was checked,
+    /// relative to the book's root directory.
+    #[serde(default)]
+    pub junit_report_path: Option<PathBuf>,
+    /// Where to save a Markdown table of the broken links (e.g. so it can be
+    /// included in the book as an appendix), relative to the book's root
+    /// directory. It is only rewritten when the report changes, because
+    /// saving it in the source directory makes `mdbook serve` rebuild the
+    /// book.
+    #[serde(default)]
+    pub markdown_report_path: Option<PathBuf>,
+    /// Where to save the graph of which chapters link to which, relative to
+    /// the book's root directory. It is saved in Graphviz's DOT language if
+    /// the file ends in `.dot` or `.gv`, otherwise as JSON.
+    #[serde(default)]
+    pub link_graph_path: Option<PathBuf>,
+    /// Print every link that was found (along with the chapter, line and
+    /// column it is on) instead of checking them.
+    #[serde(default)]
+    pub list_only: bool,
+    /// Make sure every chapter in `SUMMARY.md` points to a file which exists,
+    /// flagging draft chapters as well.
+    #[serde(default)]
+    pub check_summary: bool,
+    /// The maximum number of web links to check, to put an upper bound on how
+    /// long the link checker takes. Any links after that are skipped. Links to
+    /// other chapters are cheap to check so they are always checked.
+    #[serde(default)]
+    pub max_links: Option<usize>,
+    /// The maximum number of seconds to spend checking web links. Any which
+    /// haven't been checked by then are skipped rather than being reported as
+    /// broken.
+    #[serde(default)]
+    pub total_timeout: Option<u64>,
+    /// Fail the build if any links were skipped (e.g. because of
+    /// [`Config::exclude`], [`Config::offline`] mode, [`Config::max_links`]
+    /// or a scheme which can't be checked), so every link is known to
+    /// work.
+    #[serde(default)]
+    pub fail_on_skipped: bool,
+    /// Download web pages linked to with a `#fragment` and make sure they
+    /// contain an element with a matching `id` or `name`. This needs a `GET`
+    /// request for the whole page, so it is slower.
+    #[serde(default)]
+    pub check_fragments: bool,
+    /// Only check links in chapters which have changed since the last
+    /// successful run. Chapters with broken links or warnings are always
+    /// checked again. This is faster, but a link to another chapter or web
+    /// page which has since broken won't be noticed until the chapter
+    /// containing it changes.
+    #[serde(default)]
+    pub incremental: bool,
+    /// The path the book is served under (e.g. `/my-book/`). Root-relative
+    /// links starting with it are checked relative to the source directory,
+    /// and root-relative links to anywhere else on the website are skipped.
+    #[serde(default)]
+    pub site_root: Option<String>,
+    /// Other books (e.g. `../other-book`, relative to this book's root)
+    /// which are deployed next to this one, so links like
+    /// `../other-book/chapter.html` can be checked against their chapters.
+    #[serde(default)]
+    pub linked_books: Vec<PathBuf>,
+    /// Warn about web links which redirect somewhere else, so they can be
+    /// updated to point at the final URL.
+    #[serde(default)]
+    pub warn_on_redirect: bool,
+    /// Warn about valid web links which took more than this many
+    /// milliseconds to check, because slow sites are often the next ones to
+    /// break.
+    #[serde(default)]
+    pub slow_threshold_ms: Option<u64>,
+    /// Warn about links whose text is a web address which doesn't match
+    /// where the link goes (e.g. `[https://a.com](https://b.com)`), which is
+    /// usually a copy-paste mistake.
+    #[serde(default)]
+    pub lint_mismatched_urls: bool,
+    /// Warn about web links which use plain `http://` instead of `https://`.
+    #[serde(default)]
+    pub require_https: bool,
+    /// Warn about chapters which no other chapter links to, so the only way
+    /// to find them is the table of contents. The first chapter is the
+    /// book's landing page, so it is never reported.
+    #[serde(default)]
+    pub report_orphans: bool,
+    /// Warn about headings with the same text as an earlier heading in the
+    /// same chapter, but only when something links to them. `mdbook` gives
+    /// the second heading a different `id` (e.g. `introduction-1`), so the
+    /// link will always go to the first one.
+    #[serde(default)]
+    pub report_duplicate_headings: bool,
+    /// Don't log how many web links have been checked so far. Progress is
+    /// only ever shown when stderr is a terminal.
+    #[serde(default)]
+    pub quiet: bool,
     /// The policy to use when warnings are encountered.
     #[serde(default)]
     pub warning_policy: WarningPolicy,
+    /// How should broken links be reported? This defaults to
+    /// [`WarningPolicy::Error`], failing the build, but can be relaxed to get
+    /// a report without blocking anything.
+    #[serde(default = "default_broken_link_policy")]
+    pub broken_link_policy: WarningPolicy,
+    /// HTTP headers (e.g. `Accept-Language`) to send with every web request,
+    /// keyed by name. Values may refer to environment variables the same way
+    /// [`Config::http_headers`] do, and a header set for a particular site
+    /// in [`Config::http_headers`] takes precedence.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
     /// The map of regexes representing sets of web sites and
     /// the list of HTTP headers that must be sent to matching sites.
     #[serde(default)]
     pub http_headers: HashMap<HashedRegex, Vec<HttpHeader>>,
+    /// Credentials to use when checking links on a particular host (e.g.
+    /// `wiki.internal.example.com`).
+    #[serde(default)]
+    pub basic_auth: HashMap<String, BasicAuth>,
+    /// Tokens to send in an `Authorization: Bearer` header when checking
+    /// links on a particular host (e.g. `api.internal.example.com`).
+    #[serde(default)]
+    pub bearer_tokens: HashMap<String, BearerToken>,
+    /// Send requests for one host somewhere else (e.g. a staging mirror of
+    /// `production.example.com`), keyed by the original host. The scheme,
+    /// host, and port are replaced, but links are still reported using their
+    /// original URL.
+    #[serde(default)]
+    pub host_rewrites: HashMap<String, Url>,
+}
+
+/// A username and password for HTTP basic authentication.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct BasicAuth {
+    /// The username.
+    pub username: String,
+    /// The password, which may refer to environment variables (e.g.
+    /// `$WIKI_PASSWORD`) the same way [`Config::http_headers`] do.
+    pub password: String,
+}
+
+impl BasicAuth {
+    pub(crate) fn interpolate_password(&self) -> Result<String, Error> {
+        let value = interpolate_env(&self.password)?;
+        Ok(value.to_str()?.to_string())
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // make sure passwords never end up in the logs
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &"********")
+            .finish()
+    }
+}
+
+/// A token for HTTP bearer authentication, which may refer to environment
+/// variables (e.g. `$API_TOKEN`) the same way [`Config::http_headers`] do.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+#[serde(transparent)]
+pub struct BearerToken(pub String);
+
+impl BearerToken {
+    pub(crate) fn interpolate(&self) -> Result<String, Error> {
+        let value = interpolate_env(&self.0)?;
+        Ok(value.to_str()?.to_string())
+    }
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // make sure tokens never end up in the logs
+        f.debug_tuple("BearerToken").field(&"********").finish()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -63,9 +357,286 @@ impl Config {
     /// The default cache timeout (around 12 hours).
     pub const DEFAULT_CACHE_TIMEOUT: Duration =
         Duration::from_secs(60 * 60 * 12);
+    /// The default timeout for web requests.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
     /// The default user-agent.
     pub const DEFAULT_USER_AGENT: &'static str =
         concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION"));
+    /// The file next to `book.toml` which lists extra [`Config::exclude`]
+    /// patterns, one per line.
+    pub const IGNORE_FILE: &'static str = ".linkcheckignore";
+    /// The most times a request can be retried. The delay doubles with each
+    /// retry, so with the default delay the last of these retries would
+    /// already wait over 4 minutes.
+    pub const MAX_RETRIES: u32 = 10;
+    /// The slowest [`Config::requests_per_host_per_second`] which is allowed
+    /// (one request an hour). Anything slower would never finish.
+    pub const MIN_REQUESTS_PER_HOST_PER_SECOND: f64 = 1.0 / 3600.0;
+    /// The environment variable which forces [`Config::offline`] mode (e.g.
+    /// for air-gapped CI). Any value other than `0`, `false` or an empty
+    /// string counts.
+    pub const OFFLINE_ENV_VAR: &'static str = "MDBOOK_LINKCHECK_OFFLINE";
+
+    /// Set [`Config::follow_web_links`].
+    pub fn with_follow_web_links(mut self, mode: WebLinkMode) -> Self {
+        self.follow_web_links = mode;
+        self
+    }
+
+    /// Set [`Config::offline`].
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Turn on [`Config::offline`] mode if the [`Config::OFFLINE_ENV_VAR`]
+    /// environment variable is set.
+    pub fn with_offline_from_env(mut self) -> Self {
+        if let Some(value) = std::env::var_os(Config::OFFLINE_ENV_VAR) {
+            self.offline |= is_truthy(&value.to_string_lossy());
+        }
+        self
+    }
+
+    /// Set [`Config::traverse_parent_directories`].
+    pub fn with_traverse_parent_directories(mut self, allowed: bool) -> Self {
+        self.traverse_parent_directories = allowed;
+        self
+    }
+
+    /// Add a pattern to [`Config::exclude`].
+    pub fn with_exclude(mut self, pattern: HashedRegex) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    /// Set [`Config::user_agent`].
+    pub fn with_user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Set [`Config::timeout`], in seconds.
+    pub fn with_timeout(mut self, seconds: u64) -> Self {
+        self.timeout = seconds;
+        self
+    }
+
+    /// Set [`Config::max_retries`].
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set [`Config::retry_base_delay_ms`].
+    pub fn with_retry_base_delay_ms(mut self, milliseconds: u64) -> Self {
+        self.retry_base_delay_ms = milliseconds;
+        self
+    }
+
+    /// Set [`Config::max_retry_delay`], in seconds.
+    pub fn with_max_retry_delay(mut self, seconds: u64) -> Self {
+        self.max_retry_delay = seconds;
+        self
+    }
+
+    /// Set [`Config::concurrency`].
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Set [`Config::global_delay_ms`].
+    pub fn with_global_delay_ms(mut self, milliseconds: u64) -> Self {
+        self.global_delay_ms = milliseconds;
+        self
+    }
+
+    /// Set [`Config::warning_policy`].
+    pub fn with_warning_policy(mut self, policy: WarningPolicy) -> Self {
+        self.warning_policy = policy;
+        self
+    }
+
+    /// Set [`Config::broken_link_policy`].
+    pub fn with_broken_link_policy(mut self, policy: WarningPolicy) -> Self {
+        self.broken_link_policy = policy;
+        self
+    }
+
+    /// Make sure the options make sense together, returning an error which
+    /// names the offending option if they don't.
+    ///
+    /// Patterns like [`Config::exclude`] are already checked when they are
+    /// compiled, so this is for values which are valid on their own but
+    /// would stop the link checker from working (e.g. a
+    /// [`Config::concurrency`] of `0`).
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.concurrency == 0 {
+            anyhow::bail!("`concurrency` must be at least 1");
+        }
+
+        if self.max_retries > Config::MAX_RETRIES {
+            anyhow::bail!(
+                "`max-retries` can't be more than {} (got {})",
+                Config::MAX_RETRIES,
+                self.max_retries
+            );
+        }
+
+        if self.min_tls_version == Some(TlsVersion::V1_3) {
+            anyhow::bail!(
+                "`min-tls-version` can't be \"1.3\" because the TLS library we use doesn't support requiring it"
+            );
+        }
+
+        if self.total_timeout == Some(0) {
+            anyhow::bail!(
+                "`total-timeout` must be greater than 0, otherwise no web links would be checked"
+            );
+        }
+
+        if let Some(rate) = self.requests_per_host_per_second {
+            if !rate.is_finite() || rate <= 0.0 {
+                anyhow::bail!(
+                    "`requests-per-host-per-second` must be a positive number (got {})",
+                    rate
+                );
+            }
+            if rate < Config::MIN_REQUESTS_PER_HOST_PER_SECOND {
+                anyhow::bail!(
+                    "`requests-per-host-per-second` must be at least {} (one request an hour), got {}",
+                    Config::MIN_REQUESTS_PER_HOST_PER_SECOND,
+                    rate
+                );
+            }
+        }
+
+        if HeaderValue::from_str(&self.user_agent).is_err() {
+            anyhow::bail!(
+                "`user-agent` contains characters which aren't allowed in HTTP headers"
+            );
+        }
+
+        let status_codes = [
+            ("retryable-status-codes", &self.retryable_status_codes),
+            ("accepted-status-codes", &self.accepted_status_codes),
+        ];
+        for (option, codes) in status_codes.iter() {
+            for &code in codes.iter() {
+                if StatusCode::from_u16(code).is_err() {
+                    anyhow::bail!(
+                        "`{}` contains {}, which isn't a valid HTTP status code",
+                        option,
+                        code
+                    );
+                }
+            }
+        }
+
+        for (name, value) in &self.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                anyhow::bail!(
+                    "\"{}\" isn't a valid HTTP header name (see `headers`)",
+                    name
+                );
+            }
+            if HeaderValue::from_str(value).is_err() {
+                anyhow::bail!(
+                    "The \"{}\" header's value contains characters which aren't allowed in HTTP headers (see `headers`)",
+                    name
+                );
+            }
+        }
+
+        for (host, base) in &self.host_rewrites {
+            if base.host_str().is_none() {
+                anyhow::bail!(
+                    "Requests to \"{}\" can't be sent to \"{}\" because it doesn't have a host (see `host-rewrites`)",
+                    host,
+                    base
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the patterns from the [`Config::IGNORE_FILE`] in the book's root
+    /// directory (if there is one) to [`Config::exclude`].
+    pub fn with_ignore_file(
+        mut self,
+        book_root: &Path,
+    ) -> Result<Config, Error> {
+        let path = book_root.join(Config::IGNORE_FILE);
+
+        let src = match std::fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self),
+            Err(e) => {
+                return Err(Error::from(e)
+                    .context(format!("Unable to read \"{}\"", path.display())))
+            },
+        };
+
+        let patterns = parse_ignore_file(&src).with_context(|| {
+            format!("Unable to parse \"{}\"", path.display())
+        })?;
+        log::debug!(
+            "Loaded {} patterns from \"{}\"",
+            patterns.len(),
+            path.display()
+        );
+        self.exclude.extend(patterns);
+
+        Ok(self)
+    }
+
+    /// Find the [`BasicAuth`] credentials for a [`Url`]'s host, if any.
+    pub(crate) fn basic_auth_for(&self, url: &Url) -> Option<&BasicAuth> {
+        self.basic_auth
+            .iter()
+            .find(|(name, _)| crate::web::is_same_host(name, url))
+            .map(|(_, auth)| auth)
+    }
+
+    /// Find the [`BearerToken`] for a [`Url`]'s host, if any.
+    pub(crate) fn bearer_token_for(&self, url: &Url) -> Option<&BearerToken> {
+        self.bearer_tokens
+            .iter()
+            .find(|(name, _)| crate::web::is_same_host(name, url))
+            .map(|(_, token)| token)
+    }
+
+    /// Should links to this [`Url`] be assumed valid without sending a request
+    /// (see [`Config::assume_reachable_hosts`])?
+    pub(crate) fn assumes_reachable(&self, url: &Url) -> bool {
+        self.assume_reachable_hosts
+            .iter()
+            .any(|name| crate::web::is_same_host(name, url))
+    }
+
+    /// Where a request for this [`Url`] should actually be sent, according to
+    /// [`Config::host_rewrites`].
+    pub(crate) fn rewrite_host(&self, url: &Url) -> Option<Url> {
+        let (_, base) = self
+            .host_rewrites
+            .iter()
+            .find(|(name, _)| crate::web::is_same_host(name, url))?;
+
+        let mut rewritten = url.clone();
+        rewritten.set_scheme(base.scheme()).ok()?;
+        rewritten.set_host(base.host_str()).ok()?;
+        rewritten.set_port(base.port()).ok()?;
+
+        Some(rewritten)
+    }
+
+    /// Should web links be checked at all? This is never the case in
+    /// [`Config::offline`] mode.
+    pub fn checks_web_links(&self) -> bool {
+        !self.offline && self.follow_web_links.is_enabled()
+    }
 
     /// Checks [`Config::exclude`] to see if the provided link should be
     /// skipped.
@@ -73,11 +644,98 @@ impl Config {
         self.exclude.iter().any(|pat| pat.find(link).is_some())
     }
 
-    pub(crate) fn client(&self) -> Client {
+    /// Checks [`Config::checked_extensions`] to see if a link to a file in
+    /// the book should be skipped because of its extension.
+    pub fn should_skip_extension(&self, href: &str) -> bool {
+        let extensions = match self.checked_extensions {
+            Some(ref extensions) => extensions,
+            None => return false,
+        };
+
+        let path = href.split(['#', '?']).next().unwrap_or("");
+        let extension = match Path::new(path).extension() {
+            Some(ext) => ext.to_string_lossy(),
+            None => return false,
+        };
+
+        !extensions.iter().any(|allowed| {
+            allowed
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(&extension)
+        })
+    }
+
+    /// Checks [`Config::ignore_chapters`] to see if the links in a chapter
+    /// should be skipped.
+    pub fn should_skip_chapter(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        self.ignore_chapters
+            .iter()
+            .any(|pattern| pattern.matches_path_with(path, options))
+    }
+
+    /// When to stop checking web links if [`Config::total_timeout`] is set,
+    /// starting from now.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.total_timeout
+            .map(|secs| Instant::now() + Duration::from_secs(secs))
+    }
+
+    /// Build the [`Client`] used to check web links. Call
+    /// [`Config::validate()`] first, otherwise invalid options (e.g. a
+    /// [`Config::min_tls_version`] the TLS library doesn't support) are
+    /// reported as a confusing error from [`reqwest`].
+    pub(crate) fn client(&self) -> Result<Client, Error> {
+        self.client_builder()
+            .build()
+            .context("Unable to create the HTTP client")
+    }
+
+    fn client_builder(&self) -> ClientBuilder {
         let mut headers = http::HeaderMap::new();
-        headers
-            .insert(http::header::USER_AGENT, self.user_agent.parse().unwrap());
-        Client::builder().default_headers(headers).build().unwrap()
+        match HeaderValue::from_str(&self.user_agent) {
+            Ok(user_agent) => {
+                headers.insert(http::header::USER_AGENT, user_agent);
+            },
+            Err(_) => log::warn!(
+                "Using the default user-agent because \"{}\" isn't a valid header value",
+                self.user_agent
+            ),
+        }
+        // a redirect is enough to show the page exists
+        let redirects = match self.follow_web_links {
+            WebLinkMode::Existence => reqwest::redirect::Policy::none(),
+            _ => crate::web::redirect_policy(self.max_redirects),
+        };
+        let mut builder = Client::builder()
+            .default_headers(headers)
+            .redirect(redirects);
+
+        if self.timeout > 0 {
+            builder = builder.timeout(Duration::from_secs(self.timeout));
+        }
+
+        // reqwest already respects the HTTP_PROXY and HTTPS_PROXY environment
+        // variables, but setting a proxy explicitly will override them
+        if let Some(ref proxy) = self.proxy {
+            builder = builder
+                .proxy(crate::web::proxy(proxy.clone(), self.no_proxy.clone()));
+        }
+
+        if self.danger_accept_invalid_certs {
+            log::warn!("TLS certificate validation is disabled");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        if let Some(version) = self.min_tls_version {
+            builder = builder.min_tls_version(version.to_reqwest());
+        }
+
+        builder
     }
 
     pub(crate) fn interpolate_headers(
@@ -87,7 +745,24 @@ impl Config {
         let mut all_headers = Vec::new();
         let log_level = warning_policy.to_log_level();
 
-        for (pattern, headers) in &self.http_headers {
+        // headers for every request come first, so the ones for a particular
+        // site can override them
+        let everything =
+            HashedRegex::new("").expect("An empty pattern is always valid");
+        let global: Vec<HttpHeader> = self
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some(HttpHeader {
+                    name: HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    value: value.clone(),
+                })
+            })
+            .collect();
+
+        for (pattern, headers) in
+            std::iter::once((&everything, &global)).chain(&self.http_headers)
+        {
             let mut interpolated = Vec::new();
 
             for header in headers {
@@ -122,13 +797,61 @@ impl Config {
 impl Default for Config {
     fn default() -> Config {
         Config {
-            follow_web_links: false,
+            follow_web_links: WebLinkMode::Off,
+            offline: false,
             traverse_parent_directories: false,
             exclude: Vec::new(),
+            ignore_chapters: Vec::new(),
             user_agent: default_user_agent(),
+            headers: HashMap::new(),
             http_headers: HashMap::new(),
             warning_policy: WarningPolicy::Warn,
+            broken_link_policy: default_broken_link_policy(),
             cache_timeout: Config::DEFAULT_CACHE_TIMEOUT.as_secs(),
+            negative_cache_timeout: 0,
+            timeout: Config::DEFAULT_TIMEOUT.as_secs(),
+            use_head_requests: true,
+            concurrency: default_concurrency(),
+            max_retries: default_max_retries(),
+            retry_base_delay_ms: default_retry_base_delay_ms(),
+            max_retry_delay: default_max_retry_delay(),
+            retryable_status_codes: default_retryable_status_codes(),
+            accepted_status_codes: Vec::new(),
+            requests_per_host_per_second: None,
+            global_delay_ms: 0,
+            max_redirects: default_max_redirects(),
+            proxy: None,
+            no_proxy: Vec::new(),
+            assume_reachable_hosts: Vec::new(),
+            danger_accept_invalid_certs: false,
+            min_tls_version: None,
+            check_mailto: default_check_mailto(),
+            allowed_schemes: None,
+            checked_extensions: None,
+            github_annotations: false,
+            report_path: None,
+            junit_report_path: None,
+            markdown_report_path: None,
+            link_graph_path: None,
+            list_only: false,
+            check_summary: false,
+            max_links: None,
+            total_timeout: None,
+            fail_on_skipped: false,
+            check_fragments: false,
+            incremental: false,
+            site_root: None,
+            linked_books: Vec::new(),
+            warn_on_redirect: false,
+            slow_threshold_ms: None,
+            lint_mismatched_urls: false,
+            require_https: false,
+            report_orphans: false,
+            report_duplicate_headings: false,
+            quiet: false,
+            basic_auth: HashMap::new(),
+            bearer_tokens: HashMap::new(),
+            host_rewrites: HashMap::new(),
         }
     }
 }
@@ -169,16 +892,51 @@ impl TryFrom<String> for HttpHeader {
     }
 }
 
-impl Into<String> for HttpHeader {
-    fn into(self) -> String {
-        let HttpHeader { name, value, .. } = self;
+impl From<HttpHeader> for String {
+    fn from(header: HttpHeader) -> String {
+        let HttpHeader { name, value, .. } = header;
         format!("{}: {}", name, value)
     }
 }
 
 fn default_cache_timeout() -> u64 { Config::DEFAULT_CACHE_TIMEOUT.as_secs() }
+fn default_timeout() -> u64 { Config::DEFAULT_TIMEOUT.as_secs() }
+fn default_use_head_requests() -> bool { true }
+fn default_concurrency() -> usize { 4 * num_cpus::get() }
+fn default_max_retries() -> u32 { 3 }
+fn default_retry_base_delay_ms() -> u64 { 500 }
+fn default_max_retry_delay() -> u64 { 60 }
+fn default_retryable_status_codes() -> Vec<u16> { vec![429, 503] }
+fn default_max_redirects() -> usize { 10 }
+fn default_check_mailto() -> bool { true }
+fn default_broken_link_policy() -> WarningPolicy { WarningPolicy::Error }
 fn default_user_agent() -> String { Config::DEFAULT_USER_AGENT.to_string() }
 
+/// Parse the [`Config::IGNORE_FILE`], skipping blank lines and `#` comments.
+fn parse_ignore_file(src: &str) -> Result<Vec<HashedRegex>, Error> {
+    let mut patterns = Vec::new();
+
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let pattern = HashedRegex::new(line)
+            .with_context(|| format!("Invalid pattern on line {}", i + 1))?;
+        patterns.push(pattern);
+    }
+
+    Ok(patterns)
+}
+
+/// Does an environment variable's value (e.g. for
+/// [`Config::OFFLINE_ENV_VAR`]) mean "yes"?
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty() || value == "0" || value.eq_ignore_ascii_case("false"))
+}
+
 fn interpolate_env(value: &str) -> Result<HeaderValue, Error> {
     use std::{iter::Peekable, str::CharIndices};
 
@@ -245,6 +1003,119 @@ fn interpolate_env(value: &str) -> Result<HeaderValue, Error> {
     Ok(res.parse()?)
 }
 
+/// How thoroughly should web links be checked?
+///
+/// For backwards compatibility, `true` and `false` are accepted as aliases for
+/// [`WebLinkMode::Full`] and [`WebLinkMode::Off`].
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebLinkMode {
+    /// Don't check web links.
+    Off,
+    /// Only make sure the page exists by sending a `HEAD` request. Redirects
+    /// aren't followed and any `2xx` or `3xx` response is accepted.
+    Existence,
+    /// Follow redirects and (with [`Config::check_fragments`]) download the
+    /// page to look for the linked `#fragment`.
+    Full,
+}
+
+impl WebLinkMode {
+    /// Should web links be checked at all?
+    pub fn is_enabled(self) -> bool { self != WebLinkMode::Off }
+}
+
+impl Default for WebLinkMode {
+    fn default() -> WebLinkMode { WebLinkMode::Off }
+}
+
+impl<'de> de::Deserialize<'de> for WebLinkMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ModeVisitor;
+
+        impl<'de> Visitor<'de> for ModeVisitor {
+            type Value = WebLinkMode;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str(
+                    "a boolean or one of \"off\", \"existence\", or \"full\"",
+                )
+            }
+
+            fn visit_bool<E: de::Error>(
+                self,
+                value: bool,
+            ) -> Result<WebLinkMode, E> {
+                if value {
+                    Ok(WebLinkMode::Full)
+                } else {
+                    Ok(WebLinkMode::Off)
+                }
+            }
+
+            fn visit_str<E: de::Error>(
+                self,
+                value: &str,
+            ) -> Result<WebLinkMode, E> {
+                match value {
+                    "off" => Ok(WebLinkMode::Off),
+                    "existence" => Ok(WebLinkMode::Existence),
+                    "full" => Ok(WebLinkMode::Full),
+                    _ => Err(E::unknown_variant(
+                        value,
+                        &["off", "existence", "full"],
+                    )),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(ModeVisitor)
+    }
+}
+
+/// A version of the TLS protocol (see [`Config::min_tls_version`]).
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TlsVersion {
+    /// TLS 1.0.
+    #[serde(rename = "1.0")]
+    V1_0,
+    /// TLS 1.1.
+    #[serde(rename = "1.1")]
+    V1_1,
+    /// TLS 1.2.
+    #[serde(rename = "1.2")]
+    V1_2,
+    /// TLS 1.3.
+    #[serde(rename = "1.3")]
+    V1_3,
+}
+
+impl TlsVersion {
+    fn to_reqwest(self) -> reqwest::tls::Version {
+        match self {
+            TlsVersion::V1_0 => reqwest::tls::Version::TLS_1_0,
+            TlsVersion::V1_1 => reqwest::tls::Version::TLS_1_1,
+            TlsVersion::V1_2 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::V1_3 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+impl Display for TlsVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let version = match self {
+            TlsVersion::V1_0 => "1.0",
+            TlsVersion::V1_1 => "1.1",
+            TlsVersion::V1_2 => "1.2",
+            TlsVersion::V1_3 => "1.3",
+        };
+        write!(f, "TLS {}", version)
+    }
+}
+
 /// How should warnings be treated?
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -265,27 +1136,122 @@ impl WarningPolicy {
             WarningPolicy::Ignore => Level::Debug,
         }
     }
+
+    /// The [`Severity`] to report a problem with, or `None` if it should be
+    /// ignored.
+    pub(crate) fn severity(self) -> Option<Severity> {
+        match self {
+            WarningPolicy::Error => Some(Severity::Error),
+            WarningPolicy::Warn => Some(Severity::Warning),
+            WarningPolicy::Ignore => None,
+        }
+    }
 }
 
 impl Default for WarningPolicy {
     fn default() -> WarningPolicy { WarningPolicy::Warn }
 }
 
+/// (De)serializing [`Pattern`]s as their strings.
+mod glob_patterns {
+    use glob::Pattern;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(
+        patterns: &[Pattern],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(patterns.iter().map(Pattern::as_str))
+    }
+
+    pub(super) fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<Vec<Pattern>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|pattern| Pattern::new(pattern).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{convert::TryInto, iter::FromIterator};
-    use toml;
+    use crate::test_utils::{MockServer, Response};
+    use std::{convert::TryInto, iter::FromIterator, thread};
+    use tokio::runtime::Builder;
 
-    const CONFIG: &str = r#"follow-web-links = true
+    const CONFIG: &str = r#"follow-web-links = "full"
+offline = true
 traverse-parent-directories = true
 exclude = ["google\\.com"]
+ignore-chapters = ["api/**"]
 user-agent = "Internet Explorer"
 cache-timeout = 3600
+negative-cache-timeout = 600
+timeout = 10
+use-head-requests = false
+concurrency = 8
+max-retries = 5
+retry-base-delay-ms = 100
+max-retry-delay = 30
+retryable-status-codes = [429, 502, 503]
+accepted-status-codes = [403]
+requests-per-host-per-second = 2.5
+global-delay-ms = 250
+max-redirects = 3
+proxy = "http://proxy.example.com:8080/"
+no-proxy = ["localhost", ".internal"]
+assume-reachable-hosts = ["wiki.internal"]
+danger-accept-invalid-certs = true
+min-tls-version = "1.2"
+check-mailto = false
+allowed-schemes = ["tel"]
+checked-extensions = ["md", "png"]
+github-annotations = true
+report-path = "linkcheck.json"
+junit-report-path = "linkcheck.xml"
+markdown-report-path = "src/link-report.md"
+link-graph-path = "chapters.dot"
+list-only = true
+check-summary = true
+max-links = 500
+total-timeout = 300
+fail-on-skipped = true
+check-fragments = true
+incremental = true
+site-root = "/my-book/"
+linked-books = ["../other-book"]
+warn-on-redirect = true
+slow-threshold-ms = 2000
+lint-mismatched-urls = true
+require-https = true
+report-orphans = true
+report-duplicate-headings = true
+quiet = true
 warning-policy = "error"
+broken-link-policy = "warn"
+
+[headers]
+Accept-Language = "en-GB"
 
 [http-headers]
 https = ["accept: html/text", "authorization: Basic $TOKEN"]
+[basic-auth."wiki.internal"]
+username = "michael"
+password = "$WIKI_PASSWORD"
+
+[bearer-tokens]
+"api.internal" = "$API_TOKEN"
+
+[host-rewrites]
+"production.example.com" = "http://localhost:8080/"
 "#;
 
     #[test]
@@ -293,11 +1259,18 @@ https = ["accept: html/text", "authorization: Basic $TOKEN"]
         std::env::set_var("TOKEN", "QWxhZGRpbjpPcGVuU2VzYW1l");
 
         let should_be = Config {
-            follow_web_links: true,
+            follow_web_links: WebLinkMode::Full,
+            offline: true,
             warning_policy: WarningPolicy::Error,
+            broken_link_policy: WarningPolicy::Warn,
             traverse_parent_directories: true,
             exclude: vec![HashedRegex::new(r"google\.com").unwrap()],
+            ignore_chapters: vec![Pattern::new("api/**").unwrap()],
             user_agent: String::from("Internet Explorer"),
+            headers: HashMap::from_iter(vec![(
+                String::from("Accept-Language"),
+                String::from("en-GB"),
+            )]),
             http_headers: HashMap::from_iter(vec![(
                 HashedRegex::new("https").unwrap(),
                 vec![
@@ -306,6 +1279,68 @@ https = ["accept: html/text", "authorization: Basic $TOKEN"]
                 ],
             )]),
             cache_timeout: 3600,
+            negative_cache_timeout: 600,
+            timeout: 10,
+            use_head_requests: false,
+            concurrency: 8,
+            max_retries: 5,
+            retry_base_delay_ms: 100,
+            max_retry_delay: 30,
+            retryable_status_codes: vec![429, 502, 503],
+            accepted_status_codes: vec![403],
+            requests_per_host_per_second: Some(2.5),
+            global_delay_ms: 250,
+            max_redirects: 3,
+            proxy: Some("http://proxy.example.com:8080/".parse().unwrap()),
+            no_proxy: vec![
+                String::from("localhost"),
+                String::from(".internal"),
+            ],
+            assume_reachable_hosts: vec![String::from("wiki.internal")],
+            danger_accept_invalid_certs: true,
+            min_tls_version: Some(TlsVersion::V1_2),
+            check_mailto: false,
+            allowed_schemes: Some(vec![String::from("tel")]),
+            checked_extensions: Some(vec![
+                String::from("md"),
+                String::from("png"),
+            ]),
+            github_annotations: true,
+            report_path: Some(PathBuf::from("linkcheck.json")),
+            junit_report_path: Some(PathBuf::from("linkcheck.xml")),
+            markdown_report_path: Some(PathBuf::from("src/link-report.md")),
+            link_graph_path: Some(PathBuf::from("chapters.dot")),
+            list_only: true,
+            check_summary: true,
+            max_links: Some(500),
+            total_timeout: Some(300),
+            fail_on_skipped: true,
+            check_fragments: true,
+            incremental: true,
+            site_root: Some(String::from("/my-book/")),
+            linked_books: vec![PathBuf::from("../other-book")],
+            warn_on_redirect: true,
+            slow_threshold_ms: Some(2000),
+            lint_mismatched_urls: true,
+            require_https: true,
+            report_orphans: true,
+            report_duplicate_headings: true,
+            quiet: true,
+            basic_auth: HashMap::from_iter(vec![(
+                String::from("wiki.internal"),
+                BasicAuth {
+                    username: String::from("michael"),
+                    password: String::from("$WIKI_PASSWORD"),
+                },
+            )]),
+            bearer_tokens: HashMap::from_iter(vec![(
+                String::from("api.internal"),
+                BearerToken(String::from("$API_TOKEN")),
+            )]),
+            host_rewrites: HashMap::from_iter(vec![(
+                String::from("production.example.com"),
+                "http://localhost:8080/".parse().unwrap(),
+            )]),
         };
 
         let got: Config = toml::from_str(CONFIG).unwrap();
@@ -313,6 +1348,63 @@ https = ["accept: html/text", "authorization: Basic $TOKEN"]
         assert_eq!(got, should_be);
     }
 
+    #[test]
+    fn offline_mode_can_be_forced_from_the_environment() {
+        let inputs = vec![
+            ("1", true),
+            ("true", true),
+            ("yes", true),
+            ("", false),
+            ("0", false),
+            ("FALSE", false),
+        ];
+
+        for (value, should_be) in inputs {
+            assert_eq!(is_truthy(value), should_be, "{:?}", value);
+        }
+
+        let cfg = Config::default().with_follow_web_links(WebLinkMode::Full);
+        assert!(cfg.checks_web_links());
+        assert!(!cfg.with_offline(true).checks_web_links());
+    }
+
+    #[test]
+    fn follow_web_links_can_still_be_a_bool() {
+        let inputs = vec![
+            ("follow-web-links = true", WebLinkMode::Full),
+            ("follow-web-links = false", WebLinkMode::Off),
+            (r#"follow-web-links = "existence""#, WebLinkMode::Existence),
+            (r#"follow-web-links = "off""#, WebLinkMode::Off),
+        ];
+
+        for (src, should_be) in inputs {
+            let got: Config = toml::from_str(src).unwrap();
+            assert_eq!(got.follow_web_links, should_be, "{}", src);
+        }
+
+        assert!(
+            toml::from_str::<Config>(r#"follow-web-links = "yes""#).is_err()
+        );
+    }
+
+    #[test]
+    fn passwords_are_never_logged() {
+        let cfg: Config = toml::from_str(CONFIG).unwrap();
+        let auth = BasicAuth {
+            username: String::from("michael"),
+            password: String::from("hunter2"),
+        };
+
+        let token = BearerToken(String::from("s3cr3t"));
+
+        let got = format!("{:?} {:?} {:?}", cfg, auth, token);
+
+        assert!(!got.contains("hunter2"));
+        assert!(!got.contains("WIKI_PASSWORD"));
+        assert!(!got.contains("s3cr3t"));
+        assert!(!got.contains("API_TOKEN"));
+    }
+
     #[test]
     fn round_trip_config() {
         // A check that a value of an env var is not leaked in the
@@ -325,6 +1417,147 @@ https = ["accept: html/text", "authorization: Basic $TOKEN"]
         assert_eq!(reserialized, CONFIG);
     }
 
+    #[test]
+    fn build_a_valid_config() {
+        let got = Config::default()
+            .with_follow_web_links(WebLinkMode::Existence)
+            .with_exclude(HashedRegex::new(r"^https://internal\.").unwrap())
+            .with_user_agent("my-tool")
+            .with_timeout(5)
+            .with_max_retries(Config::MAX_RETRIES)
+            .with_concurrency(1)
+            .with_broken_link_policy(WarningPolicy::Warn);
+
+        got.validate().unwrap();
+        assert_eq!(got.follow_web_links, WebLinkMode::Existence);
+        assert!(got.should_skip("https://internal.example.com/"));
+        assert_eq!(got.user_agent, "my-tool");
+        assert_eq!(got.timeout, 5);
+        assert_eq!(got.broken_link_policy, WarningPolicy::Warn);
+        Config::default().validate().unwrap();
+        toml::from_str::<Config>(CONFIG)
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn nonsensical_configs_are_rejected() {
+        let no_host: Url = "unix:/var/run/docs.sock".parse().unwrap();
+        let inputs = vec![
+            (Config::default().with_concurrency(0), "`concurrency`"),
+            (Config::default().with_max_retries(100), "`max-retries`"),
+            (
+                Config {
+                    total_timeout: Some(0),
+                    ..Default::default()
+                },
+                "`total-timeout`",
+            ),
+            (
+                Config {
+                    min_tls_version: Some(TlsVersion::V1_3),
+                    ..Default::default()
+                },
+                "`min-tls-version`",
+            ),
+            (
+                Config {
+                    requests_per_host_per_second: Some(-1.0),
+                    ..Default::default()
+                },
+                "`requests-per-host-per-second`",
+            ),
+            (
+                Config {
+                    requests_per_host_per_second: Some(1e-20),
+                    ..Default::default()
+                },
+                "`requests-per-host-per-second`",
+            ),
+            (
+                Config::default().with_user_agent("my-tool\r\nX-Injected: 1"),
+                "`user-agent`",
+            ),
+            (
+                Config {
+                    requests_per_host_per_second: Some(f64::NAN),
+                    ..Default::default()
+                },
+                "`requests-per-host-per-second`",
+            ),
+            (
+                Config {
+                    accepted_status_codes: vec![403, 42],
+                    ..Default::default()
+                },
+                "`accepted-status-codes` contains 42",
+            ),
+            (
+                Config {
+                    headers: HashMap::from_iter(vec![(
+                        String::from("Not A Name"),
+                        String::from("value"),
+                    )]),
+                    ..Default::default()
+                },
+                "`headers`",
+            ),
+            (
+                Config {
+                    headers: HashMap::from_iter(vec![(
+                        String::from("X-Injected"),
+                        String::from("a\r\nb"),
+                    )]),
+                    ..Default::default()
+                },
+                "`headers`",
+            ),
+            (
+                Config {
+                    host_rewrites: HashMap::from_iter(vec![(
+                        String::from("example.com"),
+                        no_host,
+                    )]),
+                    ..Default::default()
+                },
+                "`host-rewrites`",
+            ),
+        ];
+
+        for (cfg, should_mention) in inputs {
+            let err = cfg.validate().unwrap_err().to_string();
+
+            assert!(err.contains(should_mention), "{}", err);
+        }
+    }
+
+    #[test]
+    fn invalid_exclude_patterns_are_rejected() {
+        let src = r#"exclude = ["(unclosed"]"#;
+
+        let got = toml::from_str::<Config>(src).unwrap_err();
+
+        assert!(got.to_string().contains("regex parse error"), "{}", got);
+    }
+
+    #[test]
+    fn parse_an_ignore_file() {
+        let src = "# internal sites\n\n^https://intranet\\.\n  wip\\.md$  \n";
+
+        let got = parse_ignore_file(src).unwrap();
+
+        assert_eq!(
+            got,
+            vec![
+                HashedRegex::new(r"^https://intranet\.").unwrap(),
+                HashedRegex::new(r"wip\.md$").unwrap(),
+            ]
+        );
+        let err = parse_ignore_file("ok\n(unclosed").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern on line 2");
+    }
+
     #[test]
     fn interpolation() {
         std::env::set_var("SUPER_SECRET_TOKEN", "abcdefg123456");
@@ -338,4 +1571,164 @@ https = ["accept: html/text", "authorization: Basic $TOKEN"]
 
         assert_eq!(got, should_be);
     }
+
+    #[test]
+    fn slow_web_requests_time_out() {
+        let server = MockServer::start(|_| {
+            thread::sleep(Duration::from_secs(5));
+            Response::new(200)
+        });
+        let cfg = Config {
+            timeout: 1,
+            ..Default::default()
+        };
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got = runtime
+            .block_on(async {
+                cfg.client().unwrap().head(server.url("/")).send().await
+            })
+            .unwrap_err();
+
+        assert!(got.is_timeout());
+    }
+
+    #[test]
+    fn send_the_configured_user_agent() {
+        let server = MockServer::start(|_| Response::new(200));
+        let cfg = Config {
+            user_agent: String::from("my-custom-agent/1.0"),
+            ..Default::default()
+        };
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime
+            .block_on(async {
+                cfg.client().unwrap().get(server.url("/")).send().await
+            })
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].header("User-Agent"),
+            Some("my-custom-agent/1.0")
+        );
+    }
+
+    #[test]
+    fn certificates_are_validated_by_default() {
+        let cfg = Config::default();
+
+        let got = format!("{:?}", cfg.client_builder());
+
+        assert!(!got.contains("danger_accept_invalid_certs"), "{}", got);
+    }
+
+    #[test]
+    fn optionally_accept_invalid_certificates() {
+        let cfg = Config {
+            danger_accept_invalid_certs: true,
+            ..Default::default()
+        };
+
+        let got = format!("{:?}", cfg.client_builder());
+
+        assert!(got.contains("danger_accept_invalid_certs: true"), "{}", got);
+    }
+
+    #[test]
+    fn the_minimum_tls_version_is_passed_to_the_client() {
+        let got = format!("{:?}", Config::default().client_builder());
+        assert!(!got.contains("min_tls_version"), "{}", got);
+
+        let cfg = Config {
+            min_tls_version: Some(TlsVersion::V1_2),
+            ..Default::default()
+        };
+
+        let got = format!("{:?}", cfg.client_builder());
+
+        assert!(got.contains("min_tls_version"), "{}", got);
+        cfg.client().unwrap();
+    }
+
+    #[test]
+    fn rewrite_the_host_of_matching_urls() {
+        let cfg = Config {
+            host_rewrites: HashMap::from_iter(vec![(
+                String::from("Production.Example.com"),
+                "http://localhost:8080/".parse().unwrap(),
+            )]),
+            ..Default::default()
+        };
+        let url: Url = "https://production.example.com/docs?page=2#intro"
+            .parse()
+            .unwrap();
+        let other: Url = "https://example.com/docs".parse().unwrap();
+
+        let got = cfg.rewrite_host(&url).unwrap();
+
+        assert_eq!(got.as_str(), "http://localhost:8080/docs?page=2#intro");
+        assert!(cfg.rewrite_host(&other).is_none());
+    }
+
+    #[test]
+    fn skip_links_with_unchecked_extensions() {
+        let cfg = Config {
+            checked_extensions: Some(vec![
+                String::from("md"),
+                String::from(".png"),
+            ]),
+            ..Default::default()
+        };
+        let inputs = vec![
+            ("downloads/examples.zip", true),
+            ("./chapter_1.md#heading", false),
+            ("images/FLOW.PNG", false),
+            ("archive.zip?version=2", true),
+            ("nested/", false),
+            ("LICENSE", false),
+        ];
+
+        for (href, should_be) in inputs {
+            assert_eq!(cfg.should_skip_extension(href), should_be, "{}", href);
+        }
+        assert!(!Config::default().should_skip_extension("examples.zip"));
+    }
+
+    #[test]
+    fn rewrite_ipv6_hosts() {
+        let ipv6: Url = "http://[::1]:8080/page".parse().unwrap();
+        let production: Url =
+            "https://production.example.com/page".parse().unwrap();
+
+        for name in &["::1", "[::1]"] {
+            let cfg = Config {
+                host_rewrites: HashMap::from_iter(vec![(
+                    name.to_string(),
+                    "http://localhost:3000/".parse().unwrap(),
+                )]),
+                ..Default::default()
+            };
+
+            let got = cfg.rewrite_host(&ipv6).unwrap();
+
+            assert_eq!(got.as_str(), "http://localhost:3000/page", "{}", name);
+        }
+
+        let cfg = Config {
+            host_rewrites: HashMap::from_iter(vec![(
+                String::from("production.example.com"),
+                "http://[::1]:8080/".parse().unwrap(),
+            )]),
+            ..Default::default()
+        };
+        cfg.validate().unwrap();
+
+        let got = cfg.rewrite_host(&production).unwrap();
+
+        assert_eq!(got.as_str(), "http://[::1]:8080/page");
+    }
 }
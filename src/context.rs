@@ -1,43 +1,85 @@
-use crate::{Config, HashedRegex};
-use codespan::Files;
+use crate::{
+    web::{LinkFetcher, RateLimiter},
+    CancellationToken, Config, HashedRegex,
+};
+use codespan::{FileId, Span};
 use http::header::{HeaderMap, HeaderName, HeaderValue};
 use linkcheck::{
     validation::{Cache, Options},
     Link,
 };
+use once_cell::sync::OnceCell;
 use reqwest::{Client, Url};
 use std::{
-    path::Path,
-    sync::{Mutex, MutexGuard},
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 
 /// The [`linkcheck::validation::Context`].
 #[derive(Debug)]
 pub struct Context<'a> {
     pub(crate) cfg: &'a Config,
-    pub(crate) src_dir: &'a Path,
     pub(crate) cache: Mutex<Cache>,
-    pub(crate) files: &'a Files<String>,
-    pub(crate) client: Client,
+    /// Only needed because [`linkcheck::validation::Context`] asks for one.
+    /// linkcheck only uses it for web links, which we check ourselves using
+    /// the [`LinkFetcher`], so in practice it is never built.
+    pub(crate) client: OnceCell<Client>,
     pub(crate) filesystem_options: Options,
     pub(crate) interpolated_headers:
         Vec<(HashedRegex, Vec<(HeaderName, HeaderValue)>)>,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) fetcher: Arc<dyn LinkFetcher>,
+    /// When to give up on any web links which haven't been checked yet (see
+    /// [`Config::total_timeout`]).
+    pub(crate) deadline: Option<Instant>,
+    /// Web links which redirected somewhere else, and where they ended up
+    /// (see [`Config::warn_on_redirect`]).
+    pub(crate) redirects: Mutex<HashMap<Url, Url>>,
+    /// How long it took to check each link, keyed by where the link is so
+    /// it doesn't matter if the `href` gets rewritten.
+    pub(crate) timings: Mutex<HashMap<(FileId, Span), Duration>>,
+    /// Stop checking web links once this is cancelled, skipping the ones
+    /// which haven't been checked yet.
+    pub(crate) cancellation: CancellationToken,
+}
+
+impl<'a> Context<'a> {
+    /// Remember how long it took to check a [`Link`].
+    pub(crate) fn record_timing(&self, link: &Link, elapsed: Duration) {
+        self.timings
+            .lock()
+            .expect("Lock was poisoned")
+            .insert((link.file, link.span), elapsed);
+    }
 }
 
 impl<'a> linkcheck::validation::Context for Context<'a> {
-    fn client(&self) -> &Client { &self.client }
+    fn client(&self) -> &Client { self.client.get_or_init(Client::new) }
 
     fn filesystem_options(&self) -> &Options { &self.filesystem_options }
 
-    fn cache(&self) -> Option<MutexGuard<Cache>> {
+    fn cache(&self) -> Option<MutexGuard<'_, Cache>> {
         Some(self.cache.lock().expect("Lock was poisoned"))
     }
 
+    fn cache_timeout(&self) -> Duration {
+        Duration::from_secs(self.cfg.cache_timeout)
+    }
+
+    fn concurrency(&self) -> usize { self.cfg.concurrency.max(1) }
+
     fn should_ignore(&self, link: &Link) -> bool {
-        if !self.cfg.follow_web_links {
-            if let Ok(_) = link.href.parse::<Url>() {
-                return true;
-            }
+        let url = crate::web::parse_url(&link.href);
+        let is_http_link =
+            matches!(url, Some(ref url) if crate::web::is_http(url));
+
+        if !self.cfg.checks_web_links() && is_http_link {
+            return true;
+        }
+
+        if url.is_none() && self.cfg.should_skip_extension(&link.href) {
+            return true;
         }
 
         self.cfg
@@ -0,0 +1,177 @@
+use crate::HashedRegex;
+use anyhow::{Context as _, Error};
+
+/// Per-chapter overrides read from the YAML frontmatter at the top of a
+/// chapter.
+///
+/// ```yaml
+/// ---
+/// linkcheck: skip
+/// linkcheck-exclude:
+///   - example\.com
+///   - '^\./downloads/'
+/// ---
+/// ```
+///
+/// Only these keys are understood, so rather than pulling in a YAML parser we
+/// just look for them line by line. Anything else in the frontmatter is left
+/// alone.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Overrides {
+    /// Don't check any of the links in this chapter.
+    pub(crate) skip: bool,
+    /// Links in this chapter matching any of these patterns are skipped, on
+    /// top of the ones in [`crate::Config::exclude`].
+    pub(crate) exclude: Vec<HashedRegex>,
+}
+
+impl Overrides {
+    /// Read the overrides from a chapter's frontmatter, if it has any.
+    pub(crate) fn from_chapter(content: &str) -> Result<Self, Error> {
+        let mut overrides = Overrides::default();
+        let mut in_exclude_list = false;
+
+        for line in frontmatter(content).unwrap_or_default() {
+            if let Some(item) = line.trim_start().strip_prefix("- ") {
+                if in_exclude_list {
+                    overrides.add_exclude(item)?;
+                }
+                continue;
+            }
+            in_exclude_list = false;
+
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+
+            match key {
+                "linkcheck" if unquote(value) == "skip" => {
+                    overrides.skip = true
+                },
+                "linkcheck" => {
+                    return Err(Error::msg(format!(
+                        "Expected \"linkcheck: skip\", found \"{}\"",
+                        line.trim()
+                    )))
+                },
+                "linkcheck-exclude" if value.is_empty() => {
+                    in_exclude_list = true
+                },
+                "linkcheck-exclude" => overrides.add_exclude(value)?,
+                _ => {},
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Should the link be skipped because of [`Overrides::exclude`]?
+    pub(crate) fn excludes(&self, href: &str) -> bool {
+        self.exclude.iter().any(|re| re.find(href).is_some())
+    }
+
+    fn add_exclude(&mut self, pattern: &str) -> Result<(), Error> {
+        let pattern = unquote(pattern.trim());
+        let re = HashedRegex::new(pattern).with_context(|| {
+            format!("\"{}\" isn't a valid regular expression", pattern)
+        })?;
+        self.exclude.push(re);
+        Ok(())
+    }
+}
+
+/// The lines between the `---` at the very start of a chapter and the next
+/// `---`.
+fn frontmatter(content: &str) -> Option<Vec<&str>> {
+    let mut lines = content.lines();
+
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let mut frontmatter = Vec::new();
+
+    for line in lines {
+        if line.trim_end() == "---" {
+            return Some(frontmatter);
+        }
+        frontmatter.push(line);
+    }
+
+    // it was just a horizontal rule
+    None
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in &['"', '\''] {
+        if value.len() >= 2
+            && value.starts_with(*quote)
+            && value.ends_with(*quote)
+        {
+            return &value[1..value.len() - 1];
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_overrides_from_the_frontmatter() {
+        let src = "---\ntitle: Appendix\nlinkcheck: skip\nlinkcheck-exclude:\n  - example\\.com\n  - '^\\./downloads/'\nauthors:\n  - Someone\n---\n\n# Appendix\n";
+
+        let got = Overrides::from_chapter(src).unwrap();
+
+        assert!(got.skip);
+        assert_eq!(
+            got.exclude,
+            vec![
+                HashedRegex::new(r"example\.com").unwrap(),
+                HashedRegex::new(r"^\./downloads/").unwrap(),
+            ]
+        );
+        assert!(got.excludes("https://example.com/"));
+        assert!(got.excludes("./downloads/book.pdf"));
+        assert!(!got.excludes("./chapter_1.md"));
+    }
+
+    #[test]
+    fn chapters_without_frontmatter_dont_have_overrides() {
+        let inputs = vec![
+            "# Chapter 1\n\nlinkcheck: skip\n",
+            "---\n\nlinkcheck: skip\n\nThat was a horizontal rule.",
+            "",
+        ];
+
+        for src in inputs {
+            let got = Overrides::from_chapter(src).unwrap();
+            assert_eq!(got, Overrides::default(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn a_single_exclude_pattern_doesnt_need_a_list() {
+        let src = "---\nlinkcheck-exclude: \"internal\\.example\\.com\"\n---\n";
+
+        let got = Overrides::from_chapter(src).unwrap();
+
+        assert!(!got.skip);
+        assert!(got.excludes("https://internal.example.com/"));
+    }
+
+    #[test]
+    fn invalid_overrides_are_errors() {
+        let inputs = vec![
+            "---\nlinkcheck: sometimes\n---\n",
+            "---\nlinkcheck-exclude:\n  - \"(unclosed\"\n---\n",
+        ];
+
+        for src in inputs {
+            assert!(Overrides::from_chapter(src).is_err(), "{:?}", src);
+        }
+    }
+}
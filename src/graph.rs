@@ -0,0 +1,173 @@
+use crate::{local::rendered_path, validate::as_web_link, ValidationOutcome};
+use codespan::Files;
+use mdbook::book::{Book, BookItem};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+/// Which chapters link to which other chapters (see
+/// [`crate::Config::link_graph_path`]).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkGraph {
+    /// Every chapter in the book, in the order they appear in `SUMMARY.md`.
+    pub chapters: Vec<PathBuf>,
+    /// The links between chapters, in the order they were found. A chapter
+    /// linking to the same chapter more than once only gets one edge, and
+    /// links within a chapter are left out.
+    pub edges: Vec<LinkGraphEdge>,
+}
+
+/// A link from one chapter to another in a [`LinkGraph`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkGraphEdge {
+    /// The chapter containing the link.
+    pub from: PathBuf,
+    /// The chapter it links to.
+    pub to: PathBuf,
+}
+
+impl LinkGraph {
+    /// Build the graph from the valid links between chapters. Broken links,
+    /// web links, and links to other files (e.g. images) aren't included.
+    pub fn new(
+        book: &Book,
+        outcome: &ValidationOutcome,
+        files: &Files<String>,
+    ) -> Self {
+        let chapters: Vec<PathBuf> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(ch) => ch.path.clone(),
+                _ => None,
+            })
+            .collect();
+        let known: HashSet<&Path> =
+            chapters.iter().map(|path| path.as_path()).collect();
+
+        let mut edges = Vec::new();
+        let mut seen = HashSet::new();
+
+        for link in &outcome.valid_links {
+            if link.href.starts_with('#') || as_web_link(link).is_some() {
+                continue;
+            }
+
+            let from = PathBuf::from(files.name(link.file));
+            let to = match rendered_path(link, files)
+                .and_then(|path| find_chapter(&path, &known))
+            {
+                Some(to) if to != from => to,
+                _ => continue,
+            };
+
+            let edge = LinkGraphEdge { from, to };
+            if seen.insert(edge.clone()) {
+                edges.push(edge);
+            }
+        }
+
+        LinkGraph { chapters, edges }
+    }
+
+    /// The chapters which no other chapter links to, apart from the first
+    /// one (the book's landing page).
+    pub fn orphans(&self) -> Vec<PathBuf> {
+        let linked: HashSet<&PathBuf> =
+            self.edges.iter().map(|edge| &edge.to).collect();
+
+        self.chapters
+            .iter()
+            .skip(1)
+            .filter(|chapter| !linked.contains(chapter))
+            .cloned()
+            .collect()
+    }
+
+    /// Render the graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph chapters {\n");
+
+        for chapter in &self.chapters {
+            let _ = writeln!(dot, "    {};", quote(chapter));
+        }
+
+        for edge in &self.edges {
+            let _ = writeln!(
+                dot,
+                "    {} -> {};",
+                quote(&edge.from),
+                quote(&edge.to)
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// The chapter a link's destination (relative to the root of the rendered
+/// book) refers to, if any.
+pub(crate) fn find_chapter(
+    path: &Path,
+    chapters: &HashSet<&Path>,
+) -> Option<PathBuf> {
+    let mut candidates = match path.extension() {
+        Some(ext) if ext == "html" => vec![path.with_extension("md")],
+        Some(_) => vec![path.to_path_buf()],
+        // links to a directory go to its index page
+        None => vec![path.join("README.md"), path.join("index.md")],
+    };
+
+    // mdbook renders README.md as index.html, and the index preprocessor
+    // renames the chapter to index.md
+    for candidate in candidates.clone() {
+        match candidate.file_name().and_then(OsStr::to_str) {
+            Some("index.md") => {
+                candidates.push(candidate.with_file_name("README.md"))
+            },
+            Some("README.md") => {
+                candidates.push(candidate.with_file_name("index.md"))
+            },
+            _ => {},
+        }
+    }
+
+    candidates
+        .into_iter()
+        .find(|candidate| chapters.contains(candidate.as_path()))
+}
+
+fn quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_the_graph_as_dot() {
+        let graph = LinkGraph {
+            chapters: vec![
+                PathBuf::from("README.md"),
+                PathBuf::from("guide/\"quoted\".md"),
+            ],
+            edges: vec![LinkGraphEdge {
+                from: PathBuf::from("README.md"),
+                to: PathBuf::from("guide/\"quoted\".md"),
+            }],
+        };
+
+        let got = graph.to_dot();
+
+        assert_eq!(
+            got,
+            "digraph chapters {\n    \"README.md\";\n    \"guide/\\\"quoted\\\".md\";\n    \"README.md\" -> \"guide/\\\"quoted\\\".md\";\n}\n"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::ValidationOutcome;
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::Diagnostic;
+use mdbook::book::{Book, BookItem};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fs::File,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+/// A hash of each chapter's contents, used by [`crate::Config::incremental`]
+/// to skip chapters which haven't changed since the last successful run.
+///
+/// The hashes are only used as a cache, so it doesn't matter if they change
+/// between compiler versions. That just means every chapter gets checked
+/// again.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ChapterHashes {
+    chapters: HashMap<PathBuf, u64>,
+}
+
+impl ChapterHashes {
+    /// Hash every chapter in the [`Book`].
+    pub(crate) fn for_book(book: &Book) -> Self {
+        let mut chapters = HashMap::new();
+
+        for item in book.iter() {
+            if let BookItem::Chapter(ref ch) = item {
+                if let Some(ref path) = ch.path {
+                    let mut hasher = DefaultHasher::new();
+                    ch.content.hash(&mut hasher);
+                    chapters.insert(path.clone(), hasher.finish());
+                }
+            }
+        }
+
+        ChapterHashes { chapters }
+    }
+
+    /// Which chapters have the same contents as they did in `previous`?
+    pub(crate) fn unchanged_since(
+        &self,
+        previous: &ChapterHashes,
+    ) -> HashSet<PathBuf> {
+        self.chapters
+            .iter()
+            .filter(|(path, hash)| previous.chapters.get(*path) == Some(hash))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Record the current hash for every chapter which was checked (or
+    /// skipped because it was unchanged).
+    pub(crate) fn update<F>(&mut self, current: &ChapterHashes, checked: F)
+    where
+        F: Fn(&Path) -> bool,
+    {
+        for (path, hash) in &current.chapters {
+            if checked(path) {
+                self.chapters.insert(path.clone(), *hash);
+            }
+        }
+    }
+
+    pub(crate) fn load(filename: &Path) -> Self {
+        log::debug!("Loading chapter hashes from {}", filename.display());
+
+        match File::open(filename) {
+            Ok(f) => serde_json::from_reader(f).unwrap_or_else(|e| {
+                log::warn!("Unable to deserialize the chapter hashes: {}", e);
+                ChapterHashes::default()
+            }),
+            Err(e) => {
+                log::debug!("Unable to open the chapter hashes: {}", e);
+                ChapterHashes::default()
+            },
+        }
+    }
+
+    pub(crate) fn save(&self, filename: &Path) {
+        log::debug!("Saving chapter hashes to {}", filename.display());
+
+        match File::create(filename) {
+            Ok(f) => {
+                if let Err(e) = serde_json::to_writer(f, self) {
+                    log::warn!("Saving the chapter hashes failed: {}", e);
+                }
+            },
+            Err(e) => {
+                log::warn!("Unable to create the chapter hashes file: {}", e)
+            },
+        }
+    }
+}
+
+/// Chapters which had a broken link or a diagnostic, so they need to be
+/// checked again next time rather than being remembered as unchanged.
+pub(crate) fn chapters_with_problems(
+    outcome: &ValidationOutcome,
+    diags: &[Diagnostic<FileId>],
+    files: &Files<String>,
+) -> HashSet<PathBuf> {
+    let broken = outcome
+        .invalid_links
+        .iter()
+        .map(|invalid| invalid.link.file);
+    let diagnosed = diags
+        .iter()
+        .flat_map(|diag| diag.labels.iter().map(|label| label.file_id));
+
+    broken
+        .chain(diagnosed)
+        .map(|file| PathBuf::from(files.name(file)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::Span;
+    use linkcheck::{
+        validation::{InvalidLink, Reason},
+        Link,
+    };
+    use mdbook::book::Chapter;
+
+    fn book(first: &str, second: &str) -> Book {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "First",
+            first.to_string(),
+            "first.md",
+            Vec::new(),
+        ))
+        .push_item(Chapter::new(
+            "Second",
+            second.to_string(),
+            "second.md",
+            Vec::new(),
+        ));
+        book
+    }
+
+    #[test]
+    fn only_changed_chapters_need_checking() {
+        let previous =
+            ChapterHashes::for_book(&book("[a](./a.md)", "[b](./b.md)"));
+        let current =
+            ChapterHashes::for_book(&book("[a](./a.md)", "[c](./c.md)"));
+
+        let got = current.unchanged_since(&previous);
+
+        let should_be: HashSet<_> =
+            vec![PathBuf::from("first.md")].into_iter().collect();
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn only_remember_chapters_which_were_checked() {
+        let mut previous = ChapterHashes::for_book(&book("old", "old"));
+        let current = ChapterHashes::for_book(&book("new", "new"));
+
+        previous.update(&current, |path| path == Path::new("first.md"));
+
+        let got = current.unchanged_since(&previous);
+        let should_be: HashSet<_> =
+            vec![PathBuf::from("first.md")].into_iter().collect();
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn chapters_with_broken_links_are_checked_again() {
+        let mut files = Files::new();
+        let first = files.add("first.md", String::new());
+        let second = files.add("second.md", String::new());
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: Link::new("./missing.md", Span::new(0, 0), first),
+                reason: Reason::Io(std::io::ErrorKind::NotFound.into()),
+            }],
+            valid_links: vec![Link::new("./first.md", Span::new(0, 0), second)],
+            ..Default::default()
+        };
+
+        let got = chapters_with_problems(&outcome, &[], &files);
+
+        let should_be: HashSet<_> =
+            vec![PathBuf::from("first.md")].into_iter().collect();
+        assert_eq!(got, should_be);
+    }
+}
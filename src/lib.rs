@@ -2,7 +2,8 @@
 //!
 //! The link-checking process has roughly three stages:
 //!
-//! 1. Find all the links in a body of markdown text (see [`extract_links`])
+//! 1. Find all the links in a body of markdown text (see [`extract_links`], or
+//!    [`find_links`] to list the links in a book without checking them)
 //! 2. Validate all the links we've found, taking into account cached results
 //!    and configuration options
 //! 3. Cache the results in the output directory for reuse by step 2 in the next
@@ -25,33 +26,83 @@ extern crate pretty_assertions;
 /// A semver range specifying which versions of `mdbook` this crate supports.
 pub const COMPATIBLE_MDBOOK_VERSIONS: &str = "^0.4.0";
 
+mod anchors;
+mod cancel;
 mod config;
 mod context;
+mod frontmatter;
+mod graph;
 mod hashed_regex;
+mod incremental;
+mod linked_books;
 mod links;
+mod local;
+mod mailto;
+mod progress;
+mod report;
+mod summary;
+#[cfg(test)]
+mod test_utils;
 mod validate;
+mod web;
 
 pub use crate::{
-    config::{Config, WarningPolicy},
+    anchors::{collect_anchors, DuplicateHeading},
+    cancel::CancellationToken,
+    config::{
+        BasicAuth, BearerToken, Config, TlsVersion, WarningPolicy, WebLinkMode,
+    },
     context::Context,
+    graph::{LinkGraph, LinkGraphEdge},
     hashed_regex::HashedRegex,
-    links::{extract as extract_links, IncompleteLink},
-    validate::{validate, NotInSummary, ValidationOutcome},
+    links::{
+        extract as extract_links, find_links, ChapterLink, IncompleteLink,
+        MismatchedUrl,
+    },
+    local::{CaseMismatch, MalformedLink},
+    mailto::InvalidEmailAddress,
+    report::{
+        BrokenLinks, CheckReport, LinkError, LinkReport, LinkStatus, Report,
+        SkippedLinks,
+    },
+    summary::MissingChapter,
+    validate::{
+        validate, EmptyLink, LinkTiming, MissingAnchor, NotInSummary,
+        RedirectedLink, ValidationOutcome,
+    },
+    web::{
+        CachedFailure, ConnectionFailed, DnsFailure, LinkFetcher, OutdatedTls,
+        Timeout, TlsError, TooManyRedirects, UnsuccessfulStatus,
+        UnsupportedScheme,
+    },
 };
 
+use crate::{
+    cancel::CtrlCHandler, frontmatter::Overrides, incremental::ChapterHashes,
+    linked_books::LinkedBook,
+};
 use anyhow::{Context as _, Error};
 use codespan::{FileId, Files};
 use codespan_reporting::{
-    diagnostic::{Diagnostic, Severity},
-    term::termcolor::{ColorChoice, StandardStream},
+    diagnostic::{Diagnostic, LabelStyle, Severity},
+    term::termcolor::{ColorChoice, StandardStream, WriteColor},
 };
 use linkcheck::validation::Cache;
 use mdbook::{
     book::{Book, BookItem},
     renderer::RenderContext,
 };
+use reqwest::Client;
 use semver::{Version, VersionReq};
-use std::{fs::File, path::Path};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
 /// Run the link checking pipeline.
 ///
@@ -75,16 +126,20 @@ pub fn run(
     log::info!("Started the link checker");
     log::debug!("Selected file: {:?}", selected_files);
 
-    let cfg = crate::get_config(&ctx.config)?;
+    let cfg = load_config(ctx)?;
     crate::version_check(&ctx.version)?;
 
+    if cfg.offline {
+        log::info!("Running in offline mode, so web links won't be checked");
+    }
+
     if log::log_enabled!(log::Level::Trace) {
         for line in format!("{:#?}", cfg).lines() {
             log::trace!("{}", line);
         }
     }
 
-    let file_filter = |fname: &Path| {
+    let is_selected = |fname: &Path| {
         if let Some(ref selected_files) = selected_files {
             selected_files.contains(&fname.display().to_string())
         } else {
@@ -92,32 +147,160 @@ pub fn run(
         }
     };
 
-    let (files, outcome) = check_links(&ctx, &mut cache, &cfg, file_filter)?;
-    let diags = outcome.generate_diagnostics(&files, cfg.warning_policy);
+    // in incremental mode we only check chapters which have changed since the
+    // last successful run
+    let hashes_file = cache_file
+        .filter(|_| cfg.incremental)
+        .map(|f| f.with_file_name("chapter-hashes.json"));
+    let mut chapter_hashes = match hashes_file {
+        Some(ref f) => ChapterHashes::load(f),
+        None => ChapterHashes::default(),
+    };
+    let current_hashes = ChapterHashes::for_book(&ctx.book);
+    let unchanged = current_hashes.unchanged_since(&chapter_hashes);
+    if !unchanged.is_empty() {
+        log::info!("Skipping {} unchanged chapters", unchanged.len());
+    }
+
+    let file_filter =
+        |fname: &Path| is_selected(fname) && !unchanged.contains(fname);
+
+    if cfg.list_only {
+        let links: Vec<_> = crate::find_links(&ctx.book)
+            .into_iter()
+            .filter(|link| {
+                file_filter(&link.chapter)
+                    && !cfg.should_skip_chapter(&link.chapter)
+            })
+            .collect();
+        print!("{}", crate::report::list_links(&links));
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let cancellation = CancellationToken::new();
+    let ctrl_c = CtrlCHandler::install(&cancellation);
+    let (files, outcome) =
+        check_links(ctx, &mut cache, &cfg, &cancellation, file_filter)?;
+    drop(ctrl_c);
+    let elapsed = started.elapsed();
+    let diags = outcome.generate_diagnostics_with_policies(
+        &files,
+        cfg.warning_policy,
+        cfg.broken_link_policy,
+    );
     report_errors(&files, &diags, colour)?;
 
+    if cfg.github_annotations || std::env::var_os("GITHUB_ACTIONS").is_some() {
+        let broken_links = BrokenLinks::new(&outcome, &files);
+        let src_dir = relative_to_current_dir(ctx.source_dir());
+
+        for annotation in
+            broken_links.github_annotations(&src_dir, cfg.broken_link_policy)
+        {
+            println!("{}", annotation);
+        }
+    }
+
+    if let Some(ref report_path) = cfg.report_path {
+        let report = Report::new(&outcome, &files);
+        save_report(&ctx.root.join(report_path), &report)?;
+    }
+
+    if let Some(ref junit_report_path) = cfg.junit_report_path {
+        let report = Report::new(&outcome, &files);
+        save_junit_report(&ctx.root.join(junit_report_path), &report)?;
+    }
+
+    if let Some(ref markdown_report_path) = cfg.markdown_report_path {
+        let report = Report::new(&outcome, &files);
+        save_markdown_report(&ctx.root.join(markdown_report_path), &report)?;
+    }
+
+    if let Some(ref link_graph_path) = cfg.link_graph_path {
+        let graph = LinkGraph::new(&ctx.book, &outcome, &files);
+        save_link_graph(&ctx.root.join(link_graph_path), &graph)?;
+    }
+
     if let Some(cache_file) = cache_file {
         save_cache(cache_file, &cache);
     }
 
-    if diags.iter().any(|diag| diag.severity >= Severity::Error) {
-        log::info!("{} broken links found", outcome.invalid_links.len());
+    let report = CheckReport::new(&outcome, &files).with_elapsed(elapsed);
+    log::info!("{}", report);
+
+    let broken_links_are_errors = report.has_broken_links()
+        && cfg.broken_link_policy == WarningPolicy::Error;
+
+    let result = if cancellation.is_cancelled() {
+        Err(Error::msg(
+            "Link checking was cancelled before every link was checked",
+        ))
+    } else if broken_links_are_errors {
+        Err(Error::new(
+            report.broken.with_colour(crate::report::use_colour(colour)),
+        ))
+    } else if diags.iter().any(|diag| diag.severity >= Severity::Error) {
         Err(Error::msg("One or more incorrect links"))
+    } else if cfg.fail_on_skipped && report.skipped > 0 {
+        Err(Error::new(SkippedLinks::new(&outcome, &files)))
+    } else if report.has_broken_links() {
+        log::warn!(
+            "{} broken links found, but they aren't being treated as errors",
+            report.broken.links.len()
+        );
+        Ok(())
     } else {
         log::info!("No broken links found");
         Ok(())
+    };
+
+    if let (Ok(_), Some(hashes_file)) = (&result, hashes_file) {
+        // chapters with broken links (e.g. when broken-link-policy = "warn")
+        // need to be checked again, otherwise they'd only be reported once
+        let problems =
+            incremental::chapters_with_problems(&outcome, &diags, &files);
+        chapter_hashes.update(&current_hashes, |path| {
+            is_selected(path) && !problems.contains(path)
+        });
+        chapter_hashes.save(&hashes_file);
     }
+
+    result
 }
 
-/// Get the configuration used by `mdbook-linkcheck`.
+/// Check every link in a book using the `output.linkcheck` config from
+/// `book.toml`, returning a [`CheckReport`] instead of printing diagnostics.
+pub fn check(ctx: &RenderContext) -> Result<CheckReport, Error> {
+    let cfg = load_config(ctx)?;
+    let mut cache = Cache::default();
+
+    let started = Instant::now();
+    let cancellation = CancellationToken::new();
+    let (files, outcome) =
+        check_links(ctx, &mut cache, &cfg, &cancellation, |_| true)?;
+
+    Ok(CheckReport::new(&outcome, &files).with_elapsed(started.elapsed()))
+}
+
+/// The config for this book, including any patterns from its ignore file.
+fn load_config(ctx: &RenderContext) -> Result<Config, Error> {
+    crate::get_config(&ctx.config)?.with_ignore_file(&ctx.root)
+}
+
+/// Get the configuration used by `mdbook-linkcheck`, turning on
+/// [`Config::offline`] mode if the [`Config::OFFLINE_ENV_VAR`] is set.
+///
+/// The defaults are used when `book.toml` doesn't have an `output.linkcheck`
+/// table. Only a table which can't be deserialized is an error.
 pub fn get_config(cfg: &mdbook::Config) -> Result<Config, Error> {
     match cfg.get("output.linkcheck") {
         Some(raw) => raw
             .clone()
             .try_into()
-            .context("Unable to deserialize the `output.linkcheck` table.")
-            .map_err(Error::from),
-        None => Ok(Config::default()),
+            .map(Config::with_offline_from_env)
+            .context("Unable to deserialize the `output.linkcheck` table."),
+        None => Ok(Config::default().with_offline_from_env()),
     }
 }
 
@@ -139,6 +322,9 @@ pub fn version_check(version: &str) -> Result<(), Error> {
 
 /// A helper for reading the chapters of a [`Book`] into memory, filtering out
 /// files using the given `filter`.
+///
+/// Draft chapters (e.g. `- [Coming Soon]()`) don't have a source file, so
+/// there are no links to check and they are skipped.
 pub fn load_files_into_memory<F>(
     book: &Book,
     dest: &mut Files<String>,
@@ -153,7 +339,7 @@ where
         match item {
             BookItem::Chapter(ref ch) => {
                 if let Some(ref path) = ch.path {
-                    if filter(&path) {
+                    if filter(path) {
                         let id = dest.add(
                             path.display().to_string(),
                             ch.content.clone(),
@@ -175,30 +361,136 @@ fn report_errors(
     colour: ColorChoice,
 ) -> Result<(), Error> {
     let mut writer = StandardStream::stderr(colour);
+    emit_diagnostics(&mut writer, files, diags)
+}
+
+fn emit_diagnostics(
+    writer: &mut dyn WriteColor,
+    files: &Files<String>,
+    diags: &[Diagnostic<FileId>],
+) -> Result<(), Error> {
     let cfg = codespan_reporting::term::Config::default();
 
     for diag in diags {
-        codespan_reporting::term::emit(&mut writer, &cfg, files, diag)?;
+        codespan_reporting::term::emit(writer, &cfg, files, diag)
+            .with_context(|| match chapter_of(diag, files) {
+                Some(chapter) => format!(
+                    "Unable to report a problem in \"{}\"",
+                    chapter.to_string_lossy()
+                ),
+                None => String::from("Unable to report a problem"),
+            })?;
     }
 
     Ok(())
 }
 
+/// The chapter a [`Diagnostic`] is about.
+fn chapter_of<'a>(
+    diag: &Diagnostic<FileId>,
+    files: &'a Files<String>,
+) -> Option<&'a OsStr> {
+    diag.labels
+        .iter()
+        .find(|label| label.style == LabelStyle::Primary)
+        .or_else(|| diag.labels.first())
+        .map(|label| files.name(label.file_id))
+}
+
 fn check_links<F>(
     ctx: &RenderContext,
     cache: &mut Cache,
     cfg: &Config,
+    cancellation: &CancellationToken,
+    file_filter: F,
+) -> Result<(Files<String>, ValidationOutcome), Error>
+where
+    F: Fn(&Path) -> bool,
+{
+    // the client can't be built if some options are invalid
+    cfg.validate().context("Invalid configuration")?;
+    let client = cfg.client()?;
+
+    crate::check_links_with_fetcher(
+        ctx,
+        cache,
+        cfg,
+        Arc::new(client),
+        cancellation,
+        file_filter,
+    )
+}
+
+/// Scan the chapters accepted by `file_filter` for links and check them,
+/// sending any web requests with the provided [`Client`].
+///
+/// This lets you route requests through a proxy or a mock server when
+/// embedding the link checker or writing tests. The [`Client`] is used as-is,
+/// so options like [`Config::user_agent`], [`Config::timeout`] and
+/// [`Config::proxy`] only apply if the [`Client`] was built with them.
+pub fn check_links_with_client<F>(
+    ctx: &RenderContext,
+    cache: &mut Cache,
+    cfg: &Config,
+    client: Client,
+    file_filter: F,
+) -> Result<(Files<String>, ValidationOutcome), Error>
+where
+    F: Fn(&Path) -> bool,
+{
+    crate::check_links_with_fetcher(
+        ctx,
+        cache,
+        cfg,
+        Arc::new(client),
+        &CancellationToken::new(),
+        file_filter,
+    )
+}
+
+/// The same as [`check_links_with_client()`], except web requests are sent
+/// using a custom [`LinkFetcher`] and any web links which haven't been
+/// checked yet are skipped once `cancellation` is cancelled.
+pub fn check_links_with_fetcher<F>(
+    ctx: &RenderContext,
+    cache: &mut Cache,
+    cfg: &Config,
+    fetcher: Arc<dyn LinkFetcher>,
+    cancellation: &CancellationToken,
     file_filter: F,
 ) -> Result<(Files<String>, ValidationOutcome), Error>
 where
     F: Fn(&Path) -> bool,
 {
+    cfg.validate().context("Invalid configuration")?;
+
     log::info!("Scanning book for links");
     let mut files = Files::new();
+    // every chapter is loaded so links to chapters which aren't being checked
+    // (and their anchors) can still be resolved
     let file_ids =
-        crate::load_files_into_memory(&ctx.book, &mut files, file_filter);
-    let (links, incomplete_links) =
-        crate::extract_links(file_ids.clone(), &files);
+        crate::load_files_into_memory(&ctx.book, &mut files, |_| true);
+    let overrides = chapter_overrides(&files, &file_ids)?;
+    let selected_ids: Vec<_> = file_ids
+        .iter()
+        .copied()
+        .filter(|id| {
+            let path = Path::new(files.name(*id));
+            let skipped_by_frontmatter =
+                matches!(overrides.get(id), Some(o) if o.skip);
+            if cfg.should_skip_chapter(path) || skipped_by_frontmatter {
+                log::debug!("Skipping the links in \"{}\"", path.display());
+                return false;
+            }
+            file_filter(path)
+        })
+        .collect();
+    let checked_every_chapter = selected_ids.len() == file_ids.len();
+    let (links, incomplete_links) = crate::extract_links(selected_ids, &files);
+    let (excluded, links): (Vec<_>, Vec<_>) =
+        links.into_iter().partition(|link| {
+            matches!(overrides.get(&link.file), Some(o) if o.excludes(&link.href))
+        });
     log::info!(
         "Found {} links ({} incomplete links)",
         links.len(),
@@ -206,9 +498,11 @@ where
     );
     let src = dunce::canonicalize(ctx.source_dir())
         .context("Unable to resolve the source directory")?;
-    let outcome = crate::validate(
+    let mut outcome = crate::validate::validate_with_fetcher(
         &links,
-        &cfg,
+        cfg,
+        fetcher,
+        cancellation,
         &src,
         cache,
         &files,
@@ -216,9 +510,67 @@ where
         incomplete_links,
     )?;
 
+    if !excluded.is_empty() {
+        outcome.ignored.extend(excluded);
+        outcome.ignored.sort_by_key(|link| (link.file, link.span));
+    }
+
+    let assets = crate::local::book_assets(ctx);
+    crate::local::accept_links_to_assets(&mut outcome, &assets, &files);
+
+    let linked_books = cfg
+        .linked_books
+        .iter()
+        .map(|path| LinkedBook::load(&ctx.root.join(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    crate::linked_books::accept_links_to_linked_books(
+        &mut outcome,
+        &linked_books,
+        &files,
+    );
+
+    if cfg.check_summary {
+        outcome.missing_chapters =
+            crate::summary::find_missing_chapters(&ctx.book, &src);
+    }
+
+    if cfg.report_orphans && checked_every_chapter {
+        outcome.orphaned_chapters =
+            LinkGraph::new(&ctx.book, &outcome, &files).orphans();
+    } else if cfg.report_orphans {
+        log::info!(
+            "Not looking for orphaned chapters because only some chapters were checked"
+        );
+    }
+
     Ok((files, outcome))
 }
 
+/// Read the [`Overrides`] from the frontmatter of every chapter which has
+/// them.
+fn chapter_overrides(
+    files: &Files<String>,
+    file_ids: &[FileId],
+) -> Result<HashMap<FileId, Overrides>, Error> {
+    let mut overrides = HashMap::new();
+
+    for &id in file_ids {
+        let chapter =
+            Overrides::from_chapter(files.source(id)).with_context(|| {
+                format!(
+                    "Invalid linkcheck frontmatter in \"{}\"",
+                    files.name(id).to_string_lossy()
+                )
+            })?;
+
+        if chapter != Overrides::default() {
+            overrides.insert(id, chapter);
+        }
+    }
+
+    Ok(overrides)
+}
+
 fn load_cache(filename: &Path) -> Cache {
     log::debug!("Loading cache from {}", filename.display());
 
@@ -237,6 +589,98 @@ fn load_cache(filename: &Path) -> Cache {
     }
 }
 
+/// GitHub expects annotations to use paths relative to the repository root,
+/// which is normally where `mdbook` gets run from.
+fn relative_to_current_dir(path: PathBuf) -> PathBuf {
+    match std::env::current_dir() {
+        Ok(current_dir) => match path.strip_prefix(&current_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path,
+        },
+        Err(_) => path,
+    }
+}
+
+fn save_report(filename: &Path, report: &Report) -> Result<(), Error> {
+    log::debug!("Saving the report to {}", filename.display());
+
+    let f = create_report_file(filename)?;
+    serde_json::to_writer_pretty(f, report)
+        .context("Unable to save the report as JSON")?;
+
+    Ok(())
+}
+
+fn save_junit_report(filename: &Path, report: &Report) -> Result<(), Error> {
+    log::debug!("Saving the JUnit report to {}", filename.display());
+
+    let mut f = create_report_file(filename)?;
+    f.write_all(report.to_junit_xml().as_bytes())
+        .context("Unable to save the report as JUnit XML")?;
+
+    Ok(())
+}
+
+fn save_markdown_report(filename: &Path, report: &Report) -> Result<(), Error> {
+    log::debug!("Saving the Markdown report to {}", filename.display());
+
+    // the report is often saved in the source directory, so rewriting it on
+    // every run would make `mdbook serve` rebuild the book forever
+    let written = write_if_changed(filename, report.to_markdown().as_bytes())
+        .context("Unable to save the report as Markdown")?;
+    if !written {
+        log::debug!("The Markdown report hasn't changed");
+    }
+
+    Ok(())
+}
+
+/// Write to a file unless it already has exactly these contents, returning
+/// whether anything was written.
+fn write_if_changed(filename: &Path, contents: &[u8]) -> Result<bool, Error> {
+    if let Ok(existing) = std::fs::read(filename) {
+        if existing == contents {
+            return Ok(false);
+        }
+    }
+
+    let mut f = create_report_file(filename)?;
+    f.write_all(contents)?;
+
+    Ok(true)
+}
+
+fn save_link_graph(filename: &Path, graph: &LinkGraph) -> Result<(), Error> {
+    log::debug!("Saving the link graph to {}", filename.display());
+
+    let mut f = create_report_file(filename)?;
+    let is_dot = matches!(
+        filename.extension().and_then(|ext| ext.to_str()),
+        Some("dot") | Some("gv")
+    );
+
+    if is_dot {
+        f.write_all(graph.to_dot().as_bytes())
+            .context("Unable to save the link graph as DOT")?;
+    } else {
+        serde_json::to_writer_pretty(f, graph)
+            .context("Unable to save the link graph as JSON")?;
+    }
+
+    Ok(())
+}
+
+fn create_report_file(filename: &Path) -> Result<File, Error> {
+    if let Some(parent) = filename.parent() {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!("Unable to create the \"{}\" directory", parent.display())
+        })?;
+    }
+
+    File::create(filename)
+        .with_context(|| format!("Unable to create \"{}\"", filename.display()))
+}
+
 fn save_cache(filename: &Path, cache: &Cache) {
     if let Some(parent) = filename.parent() {
         if let Err(e) = std::fs::create_dir_all(parent) {
@@ -259,6 +703,129 @@ fn save_cache(filename: &Path, cache: &Cache) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use codespan::Span;
+    use codespan_reporting::term::termcolor::{ColorSpec, NoColor};
+    use linkcheck::{
+        validation::{InvalidLink, Reason},
+        Link,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn use_the_defaults_when_there_is_no_linkcheck_table() {
+        let book_toml = "[book]\ntitle = \"My Book\"\n";
+        let cfg = mdbook::Config::from_str(book_toml).unwrap();
+
+        let got = get_config(&cfg).unwrap();
+
+        assert_eq!(got, Config::default().with_offline_from_env());
+    }
+
+    #[test]
+    fn a_malformed_linkcheck_table_is_an_error() {
+        let book_toml =
+            "[output.linkcheck]\nfollow-web-links = \"sometimes\"\n";
+        let cfg = mdbook::Config::from_str(book_toml).unwrap();
+
+        let err = get_config(&cfg).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("Unable to deserialize the `output.linkcheck` table"));
+    }
+
+    #[test]
+    fn draft_chapters_arent_loaded() {
+        let mut book = Book::new();
+        book.push_item(mdbook::book::Chapter::new(
+            "Chapter 1",
+            String::from("[a](./a.md)"),
+            "chapter_1.md",
+            Vec::new(),
+        ))
+        .push_item(mdbook::book::Chapter::new_draft("Coming Soon", Vec::new()));
+        let mut files = Files::new();
+
+        let got = load_files_into_memory(&book, &mut files, |_| true);
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(files.name(got[0]), "chapter_1.md");
+    }
+
+    #[test]
+    fn only_write_files_when_their_contents_change() {
+        let dir = std::env::temp_dir()
+            .join(format!("mdbook-linkcheck-{}", std::process::id()));
+        let filename = dir.join("link-report.md");
+
+        assert!(write_if_changed(&filename, b"first").unwrap());
+        assert!(!write_if_changed(&filename, b"first").unwrap());
+        assert!(write_if_changed(&filename, b"second").unwrap());
+        assert_eq!(std::fs::read(&filename).unwrap(), b"second");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A [`WriteColor`] which always fails (e.g. because stderr was closed).
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    impl WriteColor for BrokenWriter {
+        fn supports_color(&self) -> bool { false }
+
+        fn set_color(&mut self, _: &ColorSpec) -> std::io::Result<()> { Ok(()) }
+
+        fn reset(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    fn link_past_the_end_of_the_chapter() -> (Files<String>, ValidationOutcome)
+    {
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", String::from("[a](./a.md)"));
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: Link::new("./a.md", Span::new(100, 111), file),
+                reason: Reason::Io(std::io::ErrorKind::NotFound.into()),
+            }],
+            ..Default::default()
+        };
+
+        (files, outcome)
+    }
+
+    #[test]
+    fn diagnostics_past_the_end_of_a_chapter_are_still_reported() {
+        let (files, outcome) = link_past_the_end_of_the_chapter();
+        let diags = outcome.generate_diagnostics(&files, WarningPolicy::Warn);
+        let mut writer = NoColor::new(Vec::new());
+
+        emit_diagnostics(&mut writer, &files, &diags).unwrap();
+
+        let got = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(got.contains("File not found: ./a.md"), "{}", got);
+        assert!(got.contains("chapter_1.md"), "{}", got);
+    }
+
+    #[test]
+    fn errors_while_reporting_a_diagnostic_name_the_chapter() {
+        let (files, outcome) = link_past_the_end_of_the_chapter();
+        let diags = outcome.generate_diagnostics(&files, WarningPolicy::Warn);
+
+        let err =
+            emit_diagnostics(&mut BrokenWriter, &files, &diags).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Unable to report a problem in \"chapter_1.md\""
+        );
+    }
 
     #[test]
     fn always_stay_compatible_with_mdbook_dependency() {
@@ -0,0 +1,205 @@
+use crate::{
+    local::{normalise_path, resolve_lexically, split_href},
+    ValidationOutcome,
+};
+use anyhow::{Context as _, Error};
+use codespan::Files;
+use linkcheck::{validation::Reason, Link};
+use mdbook::{book::BookItem, MDBook};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Another book which links can point into (see
+/// [`crate::Config::linked_books`]).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LinkedBook {
+    /// The name of the book's directory, which is where it is deployed
+    /// alongside the current book.
+    pub(crate) name: String,
+    pub(crate) src_dir: PathBuf,
+    /// The chapters in the book's `SUMMARY.md`, relative to
+    /// [`LinkedBook::src_dir`].
+    pub(crate) chapters: HashSet<PathBuf>,
+}
+
+impl LinkedBook {
+    pub(crate) fn load(root: &Path) -> Result<Self, Error> {
+        let root = dunce::canonicalize(root).with_context(|| {
+            format!("Unable to find the linked book at \"{}\"", root.display())
+        })?;
+        let name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .with_context(|| {
+                format!("\"{}\" doesn't have a name", root.display())
+            })?;
+        let md = MDBook::load(&root).with_context(|| {
+            format!("Unable to load the linked book at \"{}\"", root.display())
+        })?;
+
+        let chapters = md
+            .book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(ch) => ch.path.clone(),
+                _ => None,
+            })
+            .collect();
+
+        Ok(LinkedBook {
+            name,
+            src_dir: root.join(&md.config.book.src),
+            chapters,
+        })
+    }
+
+    /// Does `path` (relative to the root of the rendered book) point to a
+    /// chapter or some other file in this book?
+    fn contains(&self, path: &Path) -> bool {
+        let candidates = if path.as_os_str().is_empty()
+            || self.src_dir.join(path).is_dir()
+        {
+            vec![path.join("README.md"), path.join("index.md")]
+        } else if path.extension() == Some(OsStr::new("html")) {
+            let mut candidates = vec![path.with_extension("md")];
+            if path.file_name() == Some(OsStr::new("index.html")) {
+                candidates.push(path.with_file_name("README.md"));
+            }
+            candidates
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        candidates.iter().any(|candidate| {
+            if candidate.extension() == Some(OsStr::new("md")) {
+                // chapters only get rendered if they are in SUMMARY.md
+                self.chapters.contains(candidate)
+            } else {
+                self.src_dir.join(candidate).is_file()
+            }
+        })
+    }
+}
+
+/// Links which go above the root of the book (e.g. `../other-book/page.html`)
+/// can't be resolved on their own, but they work when they point into one of
+/// the [`LinkedBook`]s deployed next to it.
+///
+/// Links into a linked book which don't point at anything are still broken.
+pub(crate) fn accept_links_to_linked_books(
+    outcome: &mut ValidationOutcome,
+    books: &[LinkedBook],
+    files: &Files<String>,
+) {
+    if books.is_empty() {
+        return;
+    }
+
+    let mut still_invalid = Vec::new();
+
+    for mut invalid in std::mem::take(&mut outcome.invalid_links) {
+        let target = linked_book_target(&invalid.link, files).and_then(
+            |(name, path)| {
+                let book = books.iter().find(|book| book.name == name)?;
+                Some((book, path))
+            },
+        );
+
+        match target {
+            Some((book, path)) if book.contains(&path) => {
+                log::debug!(
+                    "\"{}\" links to \"{}\" in the \"{}\" book",
+                    invalid.link.href,
+                    path.display(),
+                    book.name
+                );
+                outcome.valid_links.push(invalid.link);
+            },
+            Some(_) => {
+                invalid.reason = Reason::Io(io::ErrorKind::NotFound.into());
+                still_invalid.push(invalid);
+            },
+            None => still_invalid.push(invalid),
+        }
+    }
+
+    outcome.invalid_links = still_invalid;
+}
+
+/// If a link goes one level above the root of the rendered book, the name of
+/// the directory it goes into and the path inside it.
+fn linked_book_target(
+    link: &Link,
+    files: &Files<String>,
+) -> Option<(String, PathBuf)> {
+    let (path, _) = split_href(&link.href);
+    let path = normalise_path(path).ok()?;
+    let path = Path::new(&path);
+    if path.has_root() {
+        return None;
+    }
+
+    let mut depth = Path::new(files.name(link.file))
+        .parent()
+        .map_or(0, |dir| dir.components().count());
+    let mut components = path.components();
+
+    while let Some(component) = components.next() {
+        match component {
+            Component::ParentDir if depth == 0 => {
+                let name = match components.next()? {
+                    Component::Normal(name) => name.to_string_lossy(),
+                    _ => return None,
+                };
+                let rest =
+                    resolve_lexically(Path::new(""), components.as_path())?;
+                return Some((name.into_owned(), rest));
+            },
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {},
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_links_which_go_into_a_neighbouring_book() {
+        let mut files = Files::new();
+        let chapter = files.add("nested/chapter.md", String::new());
+        let inputs = vec![
+            (
+                "../../other-book/page.html",
+                Some(("other-book", "page.html")),
+            ),
+            (
+                "../../other-book/nested/../page.md#heading",
+                Some(("other-book", "page.md")),
+            ),
+            ("../../other-book/", Some(("other-book", ""))),
+            ("../../../other-book/page.html", None),
+            ("../page.html", None),
+            ("./page.html", None),
+            ("/other-book/page.html", None),
+        ];
+
+        for (href, should_be) in inputs {
+            let link = Link::new(href, codespan::Span::new(0, 0), chapter);
+
+            let got = linked_book_target(&link, &files);
+
+            let should_be = should_be
+                .map(|(name, path)| (name.to_string(), PathBuf::from(path)));
+            assert_eq!(got, should_be, "{}", href);
+        }
+    }
+}
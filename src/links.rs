@@ -1,7 +1,28 @@
 use codespan::{FileId, Files, Span};
 use linkcheck::Link;
-use pulldown_cmark::{BrokenLink, CowStr};
-use std::{cell::RefCell, fmt::Debug};
+use mdbook::book::Book;
+use pulldown_cmark::{
+    BrokenLink, CowStr, Event, LinkType, Options, Parser, Tag,
+};
+use regex::Regex;
+use reqwest::Url;
+use std::{cell::RefCell, fmt::Debug, ops::Range, path::PathBuf};
+
+/// Find every link in a [`Book`] without checking any of them, in the order
+/// they appear.
+///
+/// Draft chapters don't have a source file, so they are skipped.
+pub fn find_links(book: &Book) -> Vec<ChapterLink> {
+    let mut files = Files::new();
+    let file_ids = crate::load_files_into_memory(book, &mut files, |_| true);
+    let (mut links, _) = extract(file_ids, &files);
+    links.sort_by_key(|link| (link.file, link.span));
+
+    links
+        .iter()
+        .map(|link| ChapterLink::new(link, &files))
+        .collect()
+}
 
 /// Search every file in the [`Files`] and collate all the links that are
 /// found.
@@ -19,7 +40,7 @@ where
         let src = files.source(file_id);
         log::debug!("Scanning {}", files.name(file_id).to_string_lossy());
 
-        links.extend(scan_links(file_id, &*src, &mut |broken_link| {
+        links.extend(scan_links(file_id, src, &mut |broken_link| {
             let BrokenLink {
                 reference, span, ..
             } = broken_link;
@@ -36,6 +57,7 @@ where
             });
             None
         }));
+        links.extend(scan_html_links(file_id, src));
     }
 
     (links, broken_links.into_inner())
@@ -49,8 +71,204 @@ fn scan_links<'a, F>(
 where
     F: FnMut(BrokenLink<'_>) -> Option<(CowStr<'a>, CowStr<'a>)> + 'a,
 {
-    linkcheck::scanners::markdown_with_broken_link_callback(src, Some(cb))
-        .map(move |(link, span)| Link::new(link, span, file_id))
+    Parser::new_with_broken_link_callback(src, mdbook_options(), Some(cb))
+        .into_offset_iter()
+        .filter_map(move |(event, range)| {
+            let href = match event {
+                // autolinks like <michael@example.com> are rendered as mailto
+                // links
+                Event::Start(Tag::Link(LinkType::Email, dest, _)) => {
+                    format!("mailto:{}", dest)
+                },
+                Event::Start(Tag::Link(_, dest, _))
+                | Event::Start(Tag::Image(_, dest, _)) => dest.to_string(),
+                _ => return None,
+            };
+            let span = Span::new(range.start as u32, range.end as u32);
+
+            Some(Link::new(href, span, file_id))
+        })
+}
+
+/// The markdown extensions `mdbook` enables when rendering a chapter.
+///
+/// Links are found using the same extensions so anything inside a table or a
+/// footnote is parsed the same way it gets rendered.
+pub(crate) fn mdbook_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+}
+
+/// Get a markdown link's title (e.g. the `"Title"` in
+/// `[text](./page.md "Title")`), if it has one.
+pub(crate) fn title(link: &Link, files: &Files<String>) -> Option<String> {
+    let src = files.source_slice(link.file, link.span).ok()?;
+
+    Parser::new_ext(src, mdbook_options()).find_map(|event| match event {
+        Event::Start(Tag::Link(_, _, title))
+        | Event::Start(Tag::Image(_, _, title))
+            if !title.is_empty() =>
+        {
+            Some(title.to_string())
+        },
+        _ => None,
+    })
+}
+
+/// Find links whose text is a web address pointing somewhere other than the
+/// link itself (e.g. `[https://a.com](https://b.com)`), which is usually a
+/// copy-paste mistake.
+pub(crate) fn find_mismatched_urls(
+    file_id: FileId,
+    src: &str,
+) -> Vec<MismatchedUrl> {
+    let mut mismatches = Vec::new();
+    let mut current: Option<(Link, String)> = None;
+
+    for (event, range) in
+        Parser::new_ext(src, mdbook_options()).into_offset_iter()
+    {
+        match event {
+            Event::Start(Tag::Link(link_type, dest, _))
+                if link_type != LinkType::Autolink
+                    && link_type != LinkType::Email =>
+            {
+                let span = Span::new(range.start as u32, range.end as u32);
+                let link = Link::new(dest.to_string(), span, file_id);
+                current = Some((link, String::new()));
+            },
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, ref mut link_text)) = current {
+                    link_text.push_str(&text);
+                }
+            },
+            Event::End(Tag::Link(..)) => {
+                if let Some((link, text)) = current.take() {
+                    let text = text.trim();
+                    if urls_disagree(text, &link.href) {
+                        mismatches.push(MismatchedUrl {
+                            text: text.to_string(),
+                            link,
+                        });
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    mismatches
+}
+
+fn urls_disagree(text: &str, href: &str) -> bool {
+    let text = match Url::parse(text) {
+        Ok(url) if crate::web::is_http(&url) => url,
+        _ => return false,
+    };
+
+    match crate::web::parse_url(href) {
+        Some(destination) => destination != text,
+        None => true,
+    }
+}
+
+/// Find the `href`, `src`, and `srcset` attributes in any raw HTML embedded
+/// in the markdown (e.g. `<a href="...">` or `<img src="...">`).
+fn scan_html_links(file_id: FileId, src: &str) -> Vec<Link> {
+    let tag = Regex::new(r"<[a-zA-Z][^>]*>").unwrap();
+    let attribute = Regex::new(
+        r#"(?i)\s(href|src|srcset)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+    )
+    .unwrap();
+    let mut links = Vec::new();
+
+    for range in html_ranges(src) {
+        let html = &src[range.clone()];
+
+        for tag in tag.find_iter(html) {
+            for attr in attribute.captures_iter(tag.as_str()) {
+                // the value may be double-quoted, single-quoted, or unquoted
+                let value = match attr.get(2).or(attr.get(3)).or(attr.get(4)) {
+                    Some(value) => value.as_str(),
+                    None => continue,
+                };
+                let hrefs = if attr[1].eq_ignore_ascii_case("srcset") {
+                    srcset_urls(value)
+                } else {
+                    vec![value]
+                };
+                let start = range.start + tag.start();
+                let end = range.start + tag.end();
+
+                for href in hrefs {
+                    links.push(Link::new(
+                        href,
+                        Span::new(start as u32, end as u32),
+                        file_id,
+                    ));
+                }
+            }
+        }
+    }
+
+    links
+}
+
+/// Split a `srcset` attribute (e.g. `"small.png 480w, large.png 1080w"`) into
+/// the URL of each image.
+///
+/// This follows the HTML spec, where a URL may contain commas (e.g. a `data:`
+/// URL) as long as they aren't at the end.
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            return urls;
+        }
+
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = &rest[..end];
+        rest = &rest[end..];
+
+        if url.ends_with(',') {
+            // there are no descriptors (e.g. "2x") after this URL
+            urls.push(url.trim_end_matches(','));
+        } else {
+            urls.push(url);
+            rest = match rest.find(',') {
+                Some(ix) => &rest[ix + 1..],
+                None => "",
+            };
+        }
+    }
+}
+
+/// The locations of every chunk of raw HTML in the markdown source.
+///
+/// HTML blocks are emitted one line at a time, so adjacent ranges are merged
+/// to let us find tags which span multiple lines.
+pub(crate) fn html_ranges(src: &str) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for (event, range) in
+        Parser::new_ext(src, mdbook_options()).into_offset_iter()
+    {
+        if let Event::Html(_) = event {
+            match ranges.last_mut() {
+                Some(previous) if previous.end == range.start => {
+                    previous.end = range.end
+                },
+                _ => ranges.push(range),
+            }
+        }
+    }
+
+    ranges
 }
 
 /// A potential link that has a broken reference (e.g `[foo]` when there is no
@@ -64,3 +282,374 @@ pub struct IncompleteLink {
     /// Where this incomplete link occurred in the source text.
     pub span: Span,
 }
+
+/// A link whose text is a web address, but which goes somewhere else (see
+/// [`crate::Config::lint_mismatched_urls`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MismatchedUrl {
+    /// The web address in the link's text.
+    pub text: String,
+    /// The link, as written.
+    pub link: Link,
+}
+
+/// A link found by [`find_links()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterLink {
+    /// The link's target, as written in the chapter.
+    pub href: String,
+    /// The chapter containing the link, relative to the source directory.
+    pub chapter: PathBuf,
+    /// The (1-based) line the link starts on.
+    pub line: usize,
+    /// The (1-based) column the link starts at.
+    pub column: usize,
+}
+
+impl ChapterLink {
+    fn new(link: &Link, files: &Files<String>) -> Self {
+        let (line, column) = crate::report::location(link, files);
+
+        ChapterLink {
+            href: link.href.clone(),
+            chapter: PathBuf::from(files.name(link.file)),
+            line,
+            column,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdbook::book::Chapter;
+
+    fn link_locations(src: &str) -> Vec<(String, usize, usize)> {
+        let mut files = Files::new();
+        let id = files.add("chapter_1.md", src.to_string());
+        let (links, _) = extract(vec![id], &files);
+
+        links
+            .into_iter()
+            .map(|link| {
+                let location = files.location(id, link.span.start()).unwrap();
+                (
+                    link.href,
+                    location.line.number().to_usize(),
+                    location.column.to_usize() + 1,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spans_start_at_the_beginning_of_each_link() {
+        let src = "[first](./first.md) is on line 1\n\nand ![second](./second.png) is on line 3";
+        let should_be = vec![
+            (String::from("./first.md"), 1, 1),
+            (String::from("./second.png"), 3, 5),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn links_in_a_multi_paragraph_chapter() {
+        let src = r#"# Chapter 1
+
+Some introductory text.
+
+A paragraph with a [link which
+spans multiple lines](https://example.com/) in it.
+
+- a list item with [another link](./other.md)
+"#;
+        let should_be = vec![
+            (String::from("https://example.com/"), 5, 20),
+            (String::from("./other.md"), 8, 20),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn links_in_table_cells() {
+        let src = r#"| Name | Link |
+| ---- | ---- |
+| First | [first](./first.md) |
+| Second | see ![second](./second.png) |
+"#;
+        let should_be = vec![
+            (String::from("./first.md"), 3, 11),
+            (String::from("./second.png"), 4, 16),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn links_in_footnotes() {
+        let src = r#"Some text with a footnote[^1].
+
+[^1]: The footnote links to [another page](./other.md).
+"#;
+        let should_be = vec![(String::from("./other.md"), 3, 29)];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn links_in_inline_html() {
+        let src = r#"Go to <a href="./other.md">the other page</a> next."#;
+        let should_be = vec![(String::from("./other.md"), 1, 7)];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn links_in_html_blocks() {
+        let src = r#"# Chapter 1
+
+<div class="figure">
+  <img alt="A diagram"
+       src='./diagram.png'>
+  <a name="not-a-link"></a>
+  <a id="also-not-a-link" data-src="nope"></a>
+</div>
+"#;
+        let should_be = vec![(String::from("./diagram.png"), 4, 3)];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn every_image_in_a_srcset_is_a_link() {
+        let src = r#"<picture>
+  <source srcset="./large.webp 2x,./small.webp" type="image/webp">
+  <img src="./fallback.png" SRCSET='./small.png 480w, ./large.png 1080w'>
+</picture>
+"#;
+        let should_be = vec![
+            (String::from("./large.webp"), 2, 3),
+            (String::from("./small.webp"), 2, 3),
+            (String::from("./fallback.png"), 3, 3),
+            (String::from("./small.png"), 3, 3),
+            (String::from("./large.png"), 3, 3),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn split_a_srcset_into_urls() {
+        let inputs = vec![
+            ("image.png", vec!["image.png"]),
+            (" a.png 1x , b.png 2x ", vec!["a.png", "b.png"]),
+            ("a.png, b.png 2x", vec!["a.png", "b.png"]),
+            (
+                "data:image/png;base64,iVBOR 1x, b.png",
+                vec!["data:image/png;base64,iVBOR", "b.png"],
+            ),
+            ("", vec![]),
+        ];
+
+        for (srcset, should_be) in inputs {
+            assert_eq!(srcset_urls(srcset), should_be, "{:?}", srcset);
+        }
+    }
+
+    #[test]
+    fn ignore_links_in_code_blocks() {
+        let src = r#"# Chapter 1
+
+```rust
+// see http://example.com/ or [the docs](https://docs.rs/)
+let html = r"<a href='./not-a-link.md'>";
+```
+
+    <https://example.com/indented>
+
+[a real link](./real.md)
+"#;
+        let should_be = vec![(String::from("./real.md"), 10, 1)];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn ignore_links_in_inline_code() {
+        let src = "Run `curl http://example.com/`, `<https://example.com/>` \
+                   or `[x](./x.md)` and then <a href='./real.md'>this</a>.";
+        let should_be = vec![(String::from("./real.md"), 1, 84)];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn autolinks_are_links_too() {
+        let src = "See <https://example.com/> or email <michael@example.com>.";
+        let should_be = vec![
+            (String::from("https://example.com/"), 1, 5),
+            (String::from("mailto:michael@example.com"), 1, 37),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn angle_brackets_are_stripped_from_destinations() {
+        let src =
+            "[x](<path with space.md>) and [x](<https://example.com/a b>)";
+        let should_be = vec![
+            (String::from("path with space.md"), 1, 1),
+            (String::from("https://example.com/a b"), 1, 31),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn find_every_link_in_a_book() {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Chapter 1",
+            String::from(
+                "<img src=\"./flow.png\">\n\n[second](./nested/chapter_2.md)",
+            ),
+            "chapter_1.md",
+            Vec::new(),
+        ))
+        .push_item(Chapter::new_draft("Coming Soon", Vec::new()))
+        .push_item(Chapter::new(
+            "Chapter 2",
+            String::from("See [the web](https://example.com/)."),
+            "nested/chapter_2.md",
+            Vec::new(),
+        ));
+        let link = |href: &str, chapter: &str, line, column| ChapterLink {
+            href: href.to_string(),
+            chapter: PathBuf::from(chapter),
+            line,
+            column,
+        };
+        let should_be = vec![
+            link("./flow.png", "chapter_1.md", 1, 1),
+            link("./nested/chapter_2.md", "chapter_1.md", 3, 1),
+            link("https://example.com/", "nested/chapter_2.md", 1, 5),
+        ];
+
+        let got = find_links(&book);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn flag_links_whose_text_is_a_different_url() {
+        let src = "[https://a.com](https://b.com) and [https://a.com](https://a.com/)\n\n[`http://a.com/x`](./x.md), [see here](https://b.com), and <https://c.com>";
+        let mut files = Files::new();
+        let id = files.add("chapter_1.md", src.to_string());
+
+        let got: Vec<_> = find_mismatched_urls(id, src)
+            .into_iter()
+            .map(|mismatch| (mismatch.text, mismatch.link.href))
+            .collect();
+
+        assert_eq!(
+            got,
+            &[
+                (String::from("https://a.com"), String::from("https://b.com")),
+                (String::from("http://a.com/x"), String::from("./x.md")),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_the_title_of_a_link() {
+        let src = r#"[first](./first.md "The First") and ![img](./img.png 'An Image') but [no title](./none.md)"#;
+        let mut files = Files::new();
+        let id = files.add("chapter_1.md", src.to_string());
+        let (links, _) = extract(vec![id], &files);
+
+        let got: Vec<_> =
+            links.iter().map(|link| title(link, &files)).collect();
+
+        assert_eq!(
+            got,
+            vec![
+                Some(String::from("The First")),
+                Some(String::from("An Image")),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn reference_links_use_the_url_from_their_definition() {
+        let src = r#"A [full reference][first], a [collapsed][] reference, and a
+[shortcut] reference.
+
+[first]: ./first.md
+[collapsed]: https://example.com/
+[Shortcut]: ./shortcut.md#section
+"#;
+        let should_be = vec![
+            (String::from("./first.md"), 1, 3),
+            (String::from("https://example.com/"), 1, 30),
+            (String::from("./shortcut.md#section"), 2, 1),
+        ];
+
+        let got = link_locations(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn undefined_references_are_incomplete_links() {
+        let src =
+            "[some text][missing] and [another]\n\n[defined]: ./defined.md";
+        let mut files = Files::new();
+        let id = files.add("chapter_1.md", src.to_string());
+
+        let (links, incomplete) = extract(vec![id], &files);
+
+        assert!(links.is_empty());
+        let references: Vec<_> = incomplete
+            .iter()
+            .map(|link| {
+                (
+                    link.reference.as_str(),
+                    files.source_slice(id, link.span).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            references,
+            &[
+                ("missing", "[some text][missing]"),
+                ("another", "[another]")
+            ]
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{Context, LinkError, ValidationOutcome};
+use codespan::Files;
+use linkcheck::{
+    validation::{Context as _, InvalidLink, Outcomes, Reason},
+    Link,
+};
+use mdbook::renderer::RenderContext;
+use std::{
+    fmt::{self, Display, Formatter},
+    path::{Component, Path, PathBuf},
+    time::Instant,
+};
+
+/// Does this link to a file in the book need to be normalised before we can
+/// look it up on disk?
+///
+/// linkcheck passes the path through as-is, so `my%20folder/page.md` would
+/// never match `my folder/page.md`, and `subdir\chapter.md` only works on
+/// Windows. It also rejects paths containing spaces, which can be written by
+/// wrapping the destination in angle brackets (e.g.
+/// `[text](<my folder/page.md>)`).
+pub(crate) fn needs_normalising(href: &str) -> bool {
+    let (path, _) = split_href(href);
+    is_windows_path(href)
+        || path.contains('%')
+        || path.contains('\\')
+        || path.contains(' ')
+}
+
+/// Is this an absolute Windows path (e.g. `C:\Users\me\book\chapter.md`)?
+///
+/// These parse as a URL with a one-letter scheme, but they're almost always
+/// the path to a file on the author's computer and shouldn't be skipped like
+/// a `tel:` link would be.
+pub(crate) fn is_windows_path(href: &str) -> bool {
+    let bytes = href.as_bytes();
+
+    bytes.len() >= 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes.get(2), None | Some(b'\\') | Some(b'/'))
+}
+
+/// Check links to files in the book, normalising their paths first.
+///
+/// The [`Link`]s keep their original `href` so users see the link they wrote.
+pub(crate) fn check_local_links(
+    links: Vec<&Link>,
+    ctx: &Context<'_>,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> Outcomes {
+    let mut outcomes = Outcomes::default();
+
+    for link in links {
+        if ctx.should_ignore(link) {
+            outcomes.ignored.push(link.clone());
+            continue;
+        }
+
+        if is_windows_path(&link.href) {
+            let error = LinkError::AbsoluteFilePath {
+                href: link.href.clone(),
+            };
+            outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason: Reason::Io(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    error,
+                )),
+            });
+            continue;
+        }
+
+        let start = Instant::now();
+        let (path, fragment) = split_href(&link.href);
+        let result = normalise_path(path).and_then(|path| {
+            let chapter = src_dir.join(files.name(link.file));
+            let current_dir = chapter.parent().unwrap_or(src_dir);
+            linkcheck::validation::check_filesystem(
+                current_dir,
+                Path::new(&path),
+                fragment,
+                ctx,
+            )
+        });
+        ctx.record_timing(link, start.elapsed());
+
+        match result {
+            Ok(_) => outcomes.valid.push(link.clone()),
+            Err(reason) => outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason,
+            }),
+        }
+    }
+
+    outcomes
+}
+
+/// Does the link only point to a file in the book when its path is compared
+/// case-insensitively?
+///
+/// Links like `Chapter1.md` for `chapter1.md` work on case-insensitive file
+/// systems (the default on macOS and Windows), but break once the book is
+/// deployed to a web server running Linux.
+pub(crate) fn find_case_mismatch(
+    link: &Link,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> Option<CaseMismatch> {
+    let (path, _) = split_href(&link.href);
+    let path = normalise_path(path).ok()?;
+
+    let mut current = if path.starts_with('/') {
+        src_dir.to_path_buf()
+    } else {
+        let chapter = src_dir.join(files.name(link.file));
+        chapter.parent().unwrap_or(src_dir).to_path_buf()
+    };
+    let mut corrected = Vec::new();
+    let mut mismatched = false;
+    let components: Vec<_> = Path::new(&path).components().collect();
+
+    for (i, component) in components.iter().enumerate() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy(),
+            Component::ParentDir => {
+                current.pop();
+                corrected.push(String::from(".."));
+                continue;
+            },
+            Component::CurDir => {
+                corrected.push(String::from("."));
+                continue;
+            },
+            Component::RootDir | Component::Prefix(_) => {
+                corrected.push(String::new());
+                continue;
+            },
+        };
+
+        let is_last = i + 1 == components.len();
+        let actual = match find_entry(&current, &name) {
+            Some(actual) => actual,
+            // linkcheck lets "chapter.html" refer to "chapter.md"
+            None if is_last && name.ends_with(".html") => {
+                let stem = &name[..name.len() - ".html".len()];
+                let md = find_entry(&current, &format!("{}.md", stem))?;
+                format!("{}.html", &md[..md.len() - ".md".len()])
+            },
+            None => return None,
+        };
+
+        mismatched |= actual != name;
+        current.push(&actual);
+        corrected.push(actual);
+    }
+
+    if mismatched {
+        Some(CaseMismatch {
+            link: link.clone(),
+            actual: corrected.join("/"),
+        })
+    } else {
+        None
+    }
+}
+
+/// Find the entry in `dir` called `name`, preferring an exact match over one
+/// which only differs in case.
+fn find_entry(dir: &Path, name: &str) -> Option<String> {
+    let names: Vec<String> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+
+    if names.iter().any(|candidate| candidate == name) {
+        return Some(name.to_string());
+    }
+
+    names
+        .into_iter()
+        .find(|candidate| candidate.to_lowercase() == name.to_lowercase())
+}
+
+/// Files outside the source directory which `mdbook` copies into the rendered
+/// book (i.e. `output.html.additional-css` and `output.html.additional-js`),
+/// relative to the book's root directory. That is also where they end up in
+/// the rendered book.
+pub(crate) fn book_assets(ctx: &RenderContext) -> Vec<PathBuf> {
+    let html = match ctx.config.html_config() {
+        Some(html) => html,
+        None => return Vec::new(),
+    };
+
+    html.additional_css
+        .iter()
+        .chain(&html.additional_js)
+        .filter(|asset| ctx.root.join(asset).is_file())
+        .filter_map(|asset| resolve_lexically(Path::new(""), asset))
+        .collect()
+}
+
+/// Links to [`book_assets()`] (e.g. `<link href="theme/custom.css">`) won't be
+/// found in the source directory, but they work once the book is rendered.
+pub(crate) fn accept_links_to_assets(
+    outcome: &mut ValidationOutcome,
+    assets: &[PathBuf],
+    files: &Files<String>,
+) {
+    if assets.is_empty() {
+        return;
+    }
+
+    let (to_assets, still_invalid) = std::mem::take(&mut outcome.invalid_links)
+        .into_iter()
+        .partition(|invalid| {
+            invalid.reason.file_not_found()
+                && matches!(
+                    rendered_path(&invalid.link, files),
+                    Some(path) if assets.contains(&path)
+                )
+        });
+    outcome.invalid_links = still_invalid;
+
+    for invalid in to_assets {
+        log::debug!(
+            "\"{}\" links to one of the book's assets",
+            invalid.link.href
+        );
+        outcome.valid_links.push(invalid.link);
+    }
+}
+
+/// Where a link to a file in the book points, relative to the root of the
+/// rendered book, or `None` if it goes above the root.
+pub(crate) fn rendered_path(
+    link: &Link,
+    files: &Files<String>,
+) -> Option<PathBuf> {
+    let (path, _) = split_href(&link.href);
+    let path = normalise_path(path).ok()?;
+
+    let current_dir = if path.starts_with('/') {
+        Path::new("")
+    } else {
+        Path::new(files.name(link.file))
+            .parent()
+            .unwrap_or(Path::new(""))
+    };
+
+    resolve_lexically(current_dir, Path::new(&path))
+}
+
+/// Join two paths and resolve any `.` and `..` components without touching
+/// the file system, returning `None` if the result would go above
+/// `current_dir`'s root.
+pub(crate) fn resolve_lexically(
+    current_dir: &Path,
+    path: &Path,
+) -> Option<PathBuf> {
+    let mut resolved = current_dir.to_path_buf();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            },
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {},
+        }
+    }
+
+    Some(resolved)
+}
+
+/// Split a link into its path and `#fragment`, dropping any `?query`.
+pub(crate) fn split_href(href: &str) -> (&str, Option<&str>) {
+    let (rest, fragment) = match href.find('#') {
+        Some(ix) => (&href[..ix], Some(&href[ix + 1..])),
+        None => (href, None),
+    };
+    let path = match rest.find('?') {
+        Some(ix) => &rest[..ix],
+        None => rest,
+    };
+
+    (path, fragment)
+}
+
+/// Percent-decode the path and use `/` as the path separator.
+///
+/// Backslashes are replaced before decoding so an escaped `%5C` still refers
+/// to a literal backslash in the file name.
+pub(crate) fn normalise_path(path: &str) -> Result<String, Reason> {
+    let malformed = || {
+        Reason::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            MalformedLink {
+                path: path.to_string(),
+            },
+        ))
+    };
+
+    // percent_decode_str() passes invalid escapes like "%zz" through
+    // untouched, so we need to check them ourselves
+    let bytes = path.as_bytes();
+    for (ix, _) in path.match_indices('%') {
+        let is_escape = bytes.len() > ix + 2
+            && bytes[ix + 1].is_ascii_hexdigit()
+            && bytes[ix + 2].is_ascii_hexdigit();
+        if !is_escape {
+            return Err(malformed());
+        }
+    }
+
+    let path = path.replace('\\', "/");
+
+    percent_encoding::percent_decode_str(&path)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| malformed())
+}
+
+/// An error that is emitted when a link to a file in the book can't be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MalformedLink {
+    /// The path, as written in the link.
+    pub path: String,
+}
+
+impl Display for MalformedLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Unable to percent-decode \"{}\"", self.path)
+    }
+}
+
+impl std::error::Error for MalformedLink {}
+
+/// A link to a file in the book which only matches the file's name when case
+/// is ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseMismatch {
+    /// The link, as written.
+    pub link: Link,
+    /// The link's path with the same case as the file on disk.
+    pub actual: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalise_local_paths() {
+        let inputs = vec![
+            ("my%20folder/page.md", Some("my folder/page.md")),
+            ("caf%C3%A9.md", Some("café.md")),
+            ("100%25.md", Some("100%.md")),
+            ("plain.md", Some("plain.md")),
+            ("subdir\\chapter.md", Some("subdir/chapter.md")),
+            ("..\\my%20folder\\page.md", Some("../my folder/page.md")),
+            ("back%5Cslash.md", Some("back\\slash.md")),
+            ("my%zzfolder/page.md", None),
+            ("trailing%2", None),
+            ("not-utf8%FF.md", None),
+        ];
+
+        for (path, should_be) in inputs {
+            let got = normalise_path(path).ok();
+            assert_eq!(got.as_deref(), should_be, "{}", path);
+        }
+    }
+
+    #[test]
+    fn detect_links_which_only_match_when_ignoring_case() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let chapter = files.add("nested/README.md", String::new());
+        let inputs = vec![
+            ("../Chapter_1.md", Some("../chapter_1.md")),
+            ("./SIBLING.md#heading", Some("./sibling.md")),
+            ("/Nested/sibling.html", Some("/nested/sibling.html")),
+            ("../chapter_1.md", None),
+            ("../missing.md", None),
+        ];
+
+        for (href, should_be) in inputs {
+            let link = Link::new(href, codespan::Span::new(0, 0), chapter);
+
+            let got = find_case_mismatch(&link, &src_dir, &files);
+
+            assert_eq!(
+                got.as_ref().map(|mismatch| mismatch.actual.as_str()),
+                should_be,
+                "{}",
+                href
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_path_is_normalised() {
+        assert!(needs_normalising("my%20folder/page.md#a-page"));
+        assert!(needs_normalising("subdir\\chapter.md"));
+        assert!(needs_normalising("my folder/page.md"));
+        assert!(needs_normalising("c:/Users/me/notes.md"));
+        assert!(!needs_normalising("chapter_1.md#100%25"));
+        assert!(!needs_normalising("chapter_1.md?q=%20"));
+        assert_eq!(
+            split_href("my%20folder/page.md?q=1#a-page"),
+            ("my%20folder/page.md", Some("a-page"))
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::Context;
+use linkcheck::{
+    validation::{Context as _, InvalidLink, Outcomes, Reason},
+    Link,
+};
+use std::fmt::{self, Display, Formatter};
+
+/// Check `mailto:` links.
+///
+/// There's no way to tell whether an email address actually exists without
+/// sending it an email, so the best we can do is make sure each address looks
+/// like `someone@example.com`.
+pub(crate) fn check_mailto_links(
+    links: Vec<&Link>,
+    ctx: &Context<'_>,
+) -> Outcomes {
+    let mut outcomes = Outcomes::default();
+
+    for link in links {
+        if !ctx.cfg.check_mailto || ctx.should_ignore(link) {
+            outcomes.ignored.push(link.clone());
+            continue;
+        }
+
+        match check_mailto(&link.href) {
+            Ok(_) => outcomes.valid.push(link.clone()),
+            Err(e) => outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason: Reason::Io(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    e,
+                )),
+            }),
+        }
+    }
+
+    outcomes
+}
+
+/// Is this a `mailto:` link?
+pub(crate) fn is_mailto(href: &str) -> bool {
+    matches!(href.get(..7), Some(scheme) if scheme.eq_ignore_ascii_case("mailto:"))
+}
+
+fn check_mailto(href: &str) -> Result<(), InvalidEmailAddress> {
+    let addresses = &href["mailto:".len()..];
+    // the recipients may be followed by a query like "?subject=Hello"
+    let addresses = match addresses.find('?') {
+        Some(question_mark) => &addresses[..question_mark],
+        None => addresses,
+    };
+
+    // RFC 6068 lets you send the email to several comma-separated recipients
+    for address in addresses.split(',') {
+        if !looks_like_an_email_address(address) {
+            return Err(InvalidEmailAddress {
+                address: address.to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn looks_like_an_email_address(address: &str) -> bool {
+    let (local, domain) = match address.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let valid_char = |c: char| !c.is_whitespace() && !c.is_control();
+
+    !local.is_empty()
+        && !domain.is_empty()
+        && local.chars().all(valid_char)
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+/// An error that is emitted when a `mailto:` link contains something which
+/// isn't an email address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidEmailAddress {
+    /// The offending address.
+    pub address: String,
+}
+
+impl Display for InvalidEmailAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" isn't a valid email address", self.address)
+    }
+}
+
+impl std::error::Error for InvalidEmailAddress {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils, Config};
+    use codespan::{Files, Span};
+
+    #[test]
+    fn check_some_email_addresses() {
+        let inputs = vec![
+            ("mailto:michael@example.com", true),
+            ("MAILTO:michael@example.com", true),
+            ("mailto:first.last+tag@sub.example.com", true),
+            ("mailto:a@example.com,b@example.com?subject=Hello", true),
+            ("mailto:localhost@localhost", true),
+            ("mailto:", false),
+            ("mailto:michael", false),
+            ("mailto:@example.com", false),
+            ("mailto:michael@", false),
+            ("mailto:michael@example..com", false),
+            ("mailto:michael bryan@example.com", false),
+            ("mailto:a@example.com,", false),
+        ];
+
+        for (href, should_be) in inputs {
+            let got = check_mailto(href).is_ok();
+            assert_eq!(got, should_be, "{}", href);
+        }
+    }
+
+    #[test]
+    fn malformed_addresses_are_broken_links() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let good =
+            Link::new("mailto:michael@example.com", Span::new(0, 0), file);
+        let bad = Link::new("mailto:michael", Span::new(0, 0), file);
+        let cfg = Config::default();
+        let ctx = test_utils::context(&cfg);
+
+        let got = check_mailto_links(vec![&good, &bad], &ctx);
+
+        assert_eq!(got.valid, vec![good.clone()]);
+        assert_eq!(got.invalid.len(), 1);
+        assert_eq!(got.invalid[0].link, bad);
+        assert_eq!(
+            crate::validate::most_specific_error_message(&got.invalid[0]),
+            "\"michael\" isn't a valid email address"
+        );
+    }
+
+    #[test]
+    fn mailto_links_can_be_skipped() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let bad = Link::new("mailto:michael", Span::new(0, 0), file);
+        let cfg = Config {
+            check_mailto: false,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+
+        let got = check_mailto_links(vec![&bad], &ctx);
+
+        assert_eq!(got.ignored, vec![bad.clone()]);
+        assert!(got.invalid.is_empty());
+    }
+}
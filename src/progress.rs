@@ -0,0 +1,94 @@
+use crate::Config;
+
+/// Keeps track of how many web links have been checked so users know we're
+/// still making progress when there are lots of them.
+pub(crate) struct Progress<F> {
+    total: usize,
+    checked: usize,
+    interval: usize,
+    on_progress: Option<F>,
+}
+
+impl<F> Progress<F>
+where
+    F: FnMut(usize, usize),
+{
+    /// How many links to check between each progress report.
+    pub(crate) const DEFAULT_INTERVAL: usize = 50;
+
+    /// Call `on_progress` with the number of links checked so far (and the
+    /// total) every `interval` links, and once all of them are done.
+    pub(crate) fn new(total: usize, interval: usize, on_progress: F) -> Self {
+        Progress {
+            total,
+            checked: 0,
+            interval: interval.max(1),
+            on_progress: Some(on_progress),
+        }
+    }
+
+    /// Only report progress when somebody is watching (i.e. stderr is a
+    /// terminal) and [`Config::quiet`] isn't set.
+    pub(crate) fn for_web_links(
+        total: usize,
+        cfg: &Config,
+        on_progress: F,
+    ) -> Self {
+        let mut progress =
+            Progress::new(total, Progress::<F>::DEFAULT_INTERVAL, on_progress);
+
+        if cfg.quiet || !atty::is(atty::Stream::Stderr) {
+            progress.on_progress = None;
+        }
+
+        progress
+    }
+
+    /// Record that another link has been checked.
+    pub(crate) fn tick(&mut self) {
+        self.checked += 1;
+
+        let finished = self.checked == self.total;
+
+        if self.checked % self.interval == 0 || finished {
+            if let Some(ref mut on_progress) = self.on_progress {
+                on_progress(self.checked, self.total);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_progress_every_interval_and_at_the_end() {
+        let mut calls = Vec::new();
+        let mut progress = Progress::new(120, 50, |checked, total| {
+            calls.push((checked, total))
+        });
+
+        for _ in 0..120 {
+            progress.tick();
+        }
+
+        assert_eq!(calls, &[(50, 120), (100, 120), (120, 120)]);
+    }
+
+    #[test]
+    fn quiet_mode_never_reports_progress() {
+        let cfg = Config {
+            quiet: true,
+            ..Default::default()
+        };
+        let mut calls = 0;
+        let mut progress = Progress::for_web_links(3, &cfg, |_, _| calls += 1);
+
+        for _ in 0..3 {
+            progress.tick();
+        }
+
+        assert_eq!(calls, 0);
+    }
+}
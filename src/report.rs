@@ -0,0 +1,1415 @@
+use crate::{
+    CachedFailure, ChapterLink, ConnectionFailed, DnsFailure, EmptyLink,
+    InvalidEmailAddress, MalformedLink, MissingAnchor, NotInSummary,
+    OutdatedTls, Timeout, TlsError, TooManyRedirects, UnsuccessfulStatus,
+    UnsupportedScheme, ValidationOutcome, WarningPolicy,
+};
+use codespan::Files;
+use codespan_reporting::term::termcolor::ColorChoice;
+use linkcheck::{
+    validation::{InvalidLink, Reason},
+    Link,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt::{self, Display, Formatter},
+    path::Path,
+    time::Duration,
+};
+
+/// A machine-readable summary of every link that was checked, suitable for
+/// saving as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// Every link in the book, in the same order diagnostics are emitted.
+    pub links: Vec<LinkReport>,
+}
+
+impl Report {
+    /// Summarise a [`ValidationOutcome`].
+    pub fn new(outcome: &ValidationOutcome, files: &Files<String>) -> Self {
+        let mut links: Vec<(&Link, LinkStatus)> = outcome
+            .valid_links
+            .iter()
+            .map(|l| (l, LinkStatus::Valid))
+            .collect();
+        links.extend(outcome.invalid_links.iter().map(|invalid| {
+            let reason = crate::validate::most_specific_error_message(invalid);
+            (&invalid.link, LinkStatus::Broken { reason })
+        }));
+        links.extend(outcome.ignored.iter().map(|l| (l, LinkStatus::Ignored)));
+        links.extend(
+            outcome
+                .unknown_category
+                .iter()
+                .map(|l| (l, LinkStatus::UnknownCategory)),
+        );
+
+        // sort by location so the report doesn't change between runs
+        links.sort_by_key(|(link, _)| (link.file, link.span));
+
+        let timings: HashMap<_, _> = outcome
+            .timings
+            .iter()
+            .map(|timing| {
+                ((timing.link.file, timing.link.span), timing.elapsed)
+            })
+            .collect();
+
+        Report {
+            links: links
+                .into_iter()
+                .map(|(link, status)| {
+                    let elapsed = timings.get(&(link.file, link.span)).copied();
+                    LinkReport::new(link, status, elapsed, files)
+                })
+                .collect(),
+        }
+    }
+
+    /// Format the report as JUnit XML, with each link as a test case, so CI
+    /// systems can show which links are broken.
+    pub fn to_junit_xml(&self) -> String {
+        let failures = self
+            .links
+            .iter()
+            .filter(|link| matches!(link.status, LinkStatus::Broken { .. }))
+            .count();
+        let skipped = self
+            .links
+            .iter()
+            .filter(|link| {
+                matches!(
+                    link.status,
+                    LinkStatus::Ignored | LinkStatus::UnknownCategory
+                )
+            })
+            .count();
+
+        let mut xml =
+            String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites tests=\"{0}\" failures=\"{1}\">\n  <testsuite name=\"mdbook-linkcheck\" tests=\"{0}\" failures=\"{1}\" skipped=\"{2}\">\n",
+            self.links.len(),
+            failures,
+            skipped
+        ));
+
+        for link in &self.links {
+            let testcase = format!(
+                "    <testcase name=\"{}\" classname=\"{}\"",
+                escape_xml(&link.href),
+                escape_xml(&link.file)
+            );
+
+            match link.status {
+                LinkStatus::Valid => {
+                    xml.push_str(&testcase);
+                    xml.push_str("/>\n");
+                },
+                LinkStatus::Broken { ref reason } => {
+                    let message = format!(
+                        "{}:{}:{}: {}",
+                        link.file, link.line, link.column, reason
+                    );
+                    xml.push_str(&format!(
+                        "{}>\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                        testcase,
+                        escape_xml(&message)
+                    ));
+                },
+                LinkStatus::Ignored | LinkStatus::UnknownCategory => {
+                    xml.push_str(&testcase);
+                    xml.push_str(">\n      <skipped/>\n    </testcase>\n");
+                },
+            }
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    /// Format the broken links as a Markdown table, so the report can be
+    /// included in the book as a chapter.
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::from("# Link Report\n\n");
+
+        let broken: Vec<_> = self
+            .links
+            .iter()
+            .filter_map(|link| match link.status {
+                LinkStatus::Broken { ref reason } => Some((link, reason)),
+                _ => None,
+            })
+            .collect();
+
+        if broken.is_empty() {
+            md.push_str("No broken links were found.\n");
+            return md;
+        }
+
+        md.push_str("| Chapter | Line | URL | Reason |\n");
+        md.push_str("| --- | --- | --- | --- |\n");
+
+        for (link, reason) in broken {
+            md.push_str(&format!(
+                "| {} | {} | `{}` | {} |\n",
+                escape_markdown_cell(&link.file),
+                link.line,
+                escape_markdown_cell(&link.href),
+                escape_markdown_cell(reason)
+            ));
+        }
+
+        md
+    }
+}
+
+/// The result of checking a single link.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkReport {
+    /// The link's target, as written in the chapter.
+    pub href: String,
+    /// The chapter containing the link, relative to the source directory.
+    pub file: String,
+    /// The (1-based) line the link starts on.
+    pub line: usize,
+    /// The (1-based) column the link starts at.
+    pub column: usize,
+    /// The link's title (e.g. the `"Title"` in `[text](./page.md "Title")`),
+    /// which helps tell apart links to the same place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// How many milliseconds it took to check the link, or `None` if it
+    /// wasn't checked (e.g. because it was ignored).
+    #[serde(
+        rename = "elapsed-ms",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub elapsed_ms: Option<u64>,
+    /// Whether the link was valid.
+    #[serde(flatten)]
+    pub status: LinkStatus,
+}
+
+impl LinkReport {
+    fn new(
+        link: &Link,
+        status: LinkStatus,
+        elapsed: Option<Duration>,
+        files: &Files<String>,
+    ) -> Self {
+        let (line, column) = location(link, files);
+
+        LinkReport {
+            href: link.href.clone(),
+            file: files.name(link.file).to_string_lossy().into_owned(),
+            line,
+            column,
+            title: crate::links::title(link, files),
+            elapsed_ms: elapsed.map(|elapsed| elapsed.as_millis() as u64),
+            status,
+        }
+    }
+}
+
+/// The (1-based) line and column a [`Link`] starts at.
+pub(crate) fn location(link: &Link, files: &Files<String>) -> (usize, usize) {
+    match files.location(link.file, link.span.start()) {
+        Ok(location) => (
+            location.line.number().to_usize(),
+            location.column.to_usize() + 1,
+        ),
+        Err(_) => (0, 0),
+    }
+}
+
+/// List every link without checking it, one per line, in the order they
+/// appear in the book.
+pub(crate) fn list_links(links: &[ChapterLink]) -> String {
+    let mut listing = String::new();
+
+    for link in links {
+        listing.push_str(&format!(
+            "{}:{}:{} {}\n",
+            link.chapter.display(),
+            link.line,
+            link.column,
+            link.href
+        ));
+    }
+
+    listing
+}
+
+/// The error returned when broken links are found, summarising them by
+/// chapter.
+///
+/// Iterating over a [`BrokenLinks`] gives each broken link along with a
+/// [`LinkError`] saying what is wrong with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokenLinks {
+    /// Every broken link, in the order they appear in the book.
+    pub links: Vec<(Link, LinkError)>,
+    reports: Vec<LinkReport>,
+    colour: bool,
+}
+
+impl BrokenLinks {
+    /// Collect the broken links from a [`ValidationOutcome`].
+    pub fn new(outcome: &ValidationOutcome, files: &Files<String>) -> Self {
+        let mut invalid: Vec<&InvalidLink> =
+            outcome.invalid_links.iter().collect();
+        invalid.sort_by_key(|invalid| (invalid.link.file, invalid.link.span));
+
+        let links = invalid
+            .iter()
+            .map(|&invalid| (invalid.link.clone(), LinkError::from(invalid)))
+            .collect();
+        let reports = invalid
+            .iter()
+            .map(|invalid| {
+                let reason =
+                    crate::validate::most_specific_error_message(invalid);
+                LinkReport::new(
+                    &invalid.link,
+                    LinkStatus::Broken { reason },
+                    None,
+                    files,
+                )
+            })
+            .collect();
+
+        BrokenLinks {
+            links,
+            reports,
+            colour: false,
+        }
+    }
+
+    /// Should the [`Display`] impl highlight what is wrong with each link in
+    /// red (and dim its location) using ANSI escape codes? This is off by
+    /// default so the message can be written anywhere.
+    pub fn with_colour(mut self, colour: bool) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    /// Iterate over every broken link and what is wrong with it.
+    pub fn iter(&self) -> std::slice::Iter<'_, (Link, LinkError)> {
+        self.links.iter()
+    }
+}
+
+impl IntoIterator for BrokenLinks {
+    type IntoIter = std::vec::IntoIter<(Link, LinkError)>;
+    type Item = (Link, LinkError);
+
+    fn into_iter(self) -> Self::IntoIter { self.links.into_iter() }
+}
+
+impl<'a> IntoIterator for &'a BrokenLinks {
+    type IntoIter = std::slice::Iter<'a, (Link, LinkError)>;
+    type Item = &'a (Link, LinkError);
+
+    fn into_iter(self) -> Self::IntoIter { self.iter() }
+}
+
+/// The error returned when [`crate::Config::fail_on_skipped`] is set and some
+/// links weren't checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedLinks {
+    /// Every skipped link, in the order they appear in the book.
+    pub links: Vec<LinkReport>,
+}
+
+impl SkippedLinks {
+    /// Collect the skipped links from a [`ValidationOutcome`].
+    pub fn new(outcome: &ValidationOutcome, files: &Files<String>) -> Self {
+        let mut links: Vec<(&Link, LinkStatus)> = outcome
+            .ignored
+            .iter()
+            .map(|l| (l, LinkStatus::Ignored))
+            .collect();
+        links.extend(
+            outcome
+                .unknown_category
+                .iter()
+                .map(|l| (l, LinkStatus::UnknownCategory)),
+        );
+        links.sort_by_key(|(link, _)| (link.file, link.span));
+
+        SkippedLinks {
+            links: links
+                .into_iter()
+                .map(|(link, status)| {
+                    LinkReport::new(link, status, None, files)
+                })
+                .collect(),
+        }
+    }
+
+    /// Were any links skipped?
+    pub fn is_empty(&self) -> bool { self.links.is_empty() }
+}
+
+impl Display for SkippedLinks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Skipped {}, but fail-on-skipped is set",
+            plural(self.links.len(), "link")
+        )?;
+
+        let mut current_file = None;
+
+        for link in &self.links {
+            if current_file != Some(&link.file) {
+                write!(f, "\n\n{}:", link.file)?;
+                current_file = Some(&link.file);
+            }
+            write!(f, "\n  {}:{}  {}", link.line, link.column, link.href)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for SkippedLinks {}
+
+/// Why a link is broken.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// The link points to a file which doesn't exist.
+    FileNotFound {
+        /// The link, as written.
+        href: String,
+    },
+    /// The link points outside the book's source directory (see
+    /// [`crate::Config::traverse_parent_directories`]).
+    TraversesParentDirectories {
+        /// The link, as written.
+        href: String,
+    },
+    /// The link is an absolute path to a file on someone's computer (e.g.
+    /// `C:\\Users\\me\\book\\chapter.md`), which won't work anywhere else.
+    AbsoluteFilePath {
+        /// The link, as written.
+        href: String,
+    },
+    /// The link's `#fragment` doesn't match anything on the page.
+    MissingAnchor(MissingAnchor),
+    /// The link points to a file which isn't included in `SUMMARY.md`.
+    NotInSummary(NotInSummary),
+    /// The link to a file in the book couldn't be decoded.
+    Malformed(MalformedLink),
+    /// The link's destination is empty (e.g. `[text]()`).
+    Empty(EmptyLink),
+    /// The server responded with an unsuccessful status code.
+    Unsuccessful(UnsuccessfulStatus),
+    /// The web link redirected too many times.
+    TooManyRedirects(TooManyRedirects),
+    /// The cache says the web link was recently broken, so it wasn't checked
+    /// again.
+    CachedFailure(CachedFailure),
+    /// The host name couldn't be looked up.
+    DnsFailure(DnsFailure),
+    /// We couldn't connect to the server.
+    ConnectionFailed(ConnectionFailed),
+    /// The TLS handshake with the server failed.
+    TlsError(TlsError),
+    /// The server didn't respond in time.
+    Timeout(Timeout),
+    /// The link uses a scheme which isn't allowed.
+    UnsupportedScheme(UnsupportedScheme),
+    /// The web server doesn't support a new enough version of TLS.
+    OutdatedTls(OutdatedTls),
+    /// A `mailto:` link doesn't contain a valid email address.
+    InvalidEmailAddress(InvalidEmailAddress),
+    /// Anything else.
+    Other(String),
+}
+
+impl LinkError {
+    pub(crate) fn from_reason(href: &str, reason: &Reason) -> Self {
+        if reason.file_not_found() {
+            return LinkError::FileNotFound { href: href.into() };
+        }
+
+        match *reason {
+            Reason::TraversesParentDirectories => {
+                LinkError::TraversesParentDirectories { href: href.into() }
+            },
+            Reason::Io(ref io) => LinkError::from_io(io),
+            Reason::Web(ref web) if web.is_status() => {
+                let status = web.status().expect(
+                    "Response::error_for_status() always contains a status code",
+                );
+                let url = web.url().expect(
+                    "Response::error_for_status() always contains a URL",
+                );
+
+                LinkError::Unsuccessful(UnsuccessfulStatus {
+                    url: url.clone(),
+                    status,
+                    body: None,
+                    retry_after: None,
+                })
+            },
+            Reason::Web(ref web) if web.is_redirect() => {
+                match too_many_redirects(web) {
+                    Some(redirects) => {
+                        LinkError::TooManyRedirects(redirects.clone())
+                    },
+                    None => LinkError::Other(web.to_string()),
+                }
+            },
+            Reason::Web(ref web) if web.is_timeout() => match web.url() {
+                Some(url) => LinkError::Timeout(Timeout { url: url.clone() }),
+                None => LinkError::Other(web.to_string()),
+            },
+            Reason::Web(ref web) => LinkError::Other(web.to_string()),
+            // fall back to the Reason's Display impl
+            _ => LinkError::Other(reason.to_string()),
+        }
+    }
+
+    /// Our own errors are smuggled through linkcheck inside a
+    /// [`std::io::Error`].
+    fn from_io(error: &std::io::Error) -> Self {
+        let inner = match error.get_ref() {
+            Some(inner) => inner,
+            None => return LinkError::Other(error.to_string()),
+        };
+
+        if let Some(e) = inner.downcast_ref::<LinkError>() {
+            e.clone()
+        } else if let Some(e) = inner.downcast_ref::<MissingAnchor>() {
+            LinkError::MissingAnchor(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<NotInSummary>() {
+            LinkError::NotInSummary(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<MalformedLink>() {
+            LinkError::Malformed(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<EmptyLink>() {
+            LinkError::Empty(*e)
+        } else if let Some(e) = inner.downcast_ref::<UnsuccessfulStatus>() {
+            LinkError::Unsuccessful(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<TooManyRedirects>() {
+            LinkError::TooManyRedirects(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<CachedFailure>() {
+            LinkError::CachedFailure(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<UnsupportedScheme>() {
+            LinkError::UnsupportedScheme(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<DnsFailure>() {
+            LinkError::DnsFailure(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<ConnectionFailed>() {
+            LinkError::ConnectionFailed(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<TlsError>() {
+            LinkError::TlsError(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<Timeout>() {
+            LinkError::Timeout(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<OutdatedTls>() {
+            LinkError::OutdatedTls(e.clone())
+        } else if let Some(e) = inner.downcast_ref::<InvalidEmailAddress>() {
+            LinkError::InvalidEmailAddress(e.clone())
+        } else {
+            LinkError::Other(error.to_string())
+        }
+    }
+}
+
+impl From<&InvalidLink> for LinkError {
+    fn from(invalid: &InvalidLink) -> Self {
+        LinkError::from_reason(&invalid.link.href, &invalid.reason)
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::FileNotFound { href } => {
+                write!(f, "File not found: {}", href)
+            },
+            LinkError::TraversesParentDirectories { href } => write!(
+                f,
+                "\"{}\" links outside the book's source directory (set `traverse-parent-directories = true` to allow this)",
+                href
+            ),
+            LinkError::AbsoluteFilePath { href } => write!(
+                f,
+                "\"{}\" is a path on your computer, link to the chapter with a relative path instead",
+                href
+            ),
+            LinkError::MissingAnchor(e) => e.fmt(f),
+            LinkError::NotInSummary(e) => e.fmt(f),
+            LinkError::Malformed(e) => e.fmt(f),
+            LinkError::Empty(e) => e.fmt(f),
+            LinkError::Unsuccessful(e) => e.fmt(f),
+            LinkError::TooManyRedirects(e) => e.fmt(f),
+            LinkError::CachedFailure(e) => e.fmt(f),
+            LinkError::DnsFailure(e) => e.fmt(f),
+            LinkError::ConnectionFailed(e) => e.fmt(f),
+            LinkError::TlsError(e) => e.fmt(f),
+            LinkError::Timeout(e) => e.fmt(f),
+            LinkError::UnsupportedScheme(e) => e.fmt(f),
+            LinkError::OutdatedTls(e) => e.fmt(f),
+            LinkError::InvalidEmailAddress(e) => e.fmt(f),
+            LinkError::Other(msg) => msg.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Find the [`TooManyRedirects`] error from our redirect policy, if that's
+/// why the request failed.
+fn too_many_redirects(error: &reqwest::Error) -> Option<&TooManyRedirects> {
+    let mut source = std::error::Error::source(error);
+
+    while let Some(err) = source {
+        if let Some(redirects) = err.downcast_ref::<TooManyRedirects>() {
+            return Some(redirects);
+        }
+        source = err.source();
+    }
+
+    None
+}
+
+impl Display for BrokenLinks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let descriptions: Vec<String> =
+            self.reports.iter().map(describe).collect();
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+        for description in &descriptions {
+            *occurrences.entry(description).or_default() += 1;
+        }
+
+        // the same error (e.g. a broken web link used in several chapters) is
+        // only shown once, followed by everywhere it occurs
+        let mut chapters: Vec<(&str, Vec<(&LinkReport, &str)>)> = Vec::new();
+        let mut repeated: Vec<(&str, Vec<&LinkReport>)> = Vec::new();
+
+        for (link, description) in self.reports.iter().zip(&descriptions) {
+            if occurrences[description.as_str()] > 1 {
+                match repeated.iter_mut().find(|(d, _)| d == description) {
+                    Some((_, links)) => links.push(link),
+                    None => repeated.push((description, vec![link])),
+                }
+                continue;
+            }
+
+            match chapters.last_mut() {
+                Some((file, links)) if *file == link.file => {
+                    links.push((link, description))
+                },
+                _ => chapters.push((&link.file, vec![(link, description)])),
+            }
+        }
+
+        let mut chapter_names: Vec<&str> =
+            self.reports.iter().map(|link| link.file.as_str()).collect();
+        chapter_names.dedup();
+
+        write!(
+            f,
+            "Found {} in {}",
+            plural(self.links.len(), "broken link"),
+            plural(chapter_names.len(), "chapter")
+        )?;
+
+        let paint = |style, text: &dyn Display| paint(self.colour, style, text);
+
+        for (file, links) in chapters {
+            write!(f, "\n\n{}:", paint(BOLD, &file))?;
+
+            for (link, description) in links {
+                let location = format!("{}:{}", link.line, link.column);
+                write!(
+                    f,
+                    "\n  {}  {}",
+                    paint(DIM, &location),
+                    paint(RED, &description)
+                )?;
+            }
+        }
+
+        for (description, links) in repeated {
+            write!(
+                f,
+                "\n\n{} ({} links):",
+                paint(RED, &description),
+                links.len()
+            )?;
+
+            for link in links {
+                let location =
+                    format!("{}:{}:{}", link.file, link.line, link.column);
+                write!(f, "\n  {}", paint(DIM, &location))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for BrokenLinks {}
+
+impl BrokenLinks {
+    /// Format each broken link as a [GitHub Actions workflow command][wc] so
+    /// it shows up as an annotation on the offending line.
+    ///
+    /// The `src_dir` is prepended to each chapter's path, so it should be
+    /// relative to the repository root.
+    ///
+    /// [wc]: https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions
+    pub fn github_annotations(
+        &self,
+        src_dir: &Path,
+        policy: WarningPolicy,
+    ) -> Vec<String> {
+        let level = match policy {
+            WarningPolicy::Error => "error",
+            WarningPolicy::Warn => "warning",
+            WarningPolicy::Ignore => return Vec::new(),
+        };
+
+        self.reports
+            .iter()
+            .map(|link| {
+                let path = src_dir.join(&link.file);
+                format!(
+                    "::{} file={},line={},col={}::{}",
+                    level,
+                    escape_property(&path.display().to_string()),
+                    link.line,
+                    link.column,
+                    escape_data(&describe(link))
+                )
+            })
+            .collect()
+    }
+}
+
+/// A summary of how many links were checked, and which ones are broken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckReport {
+    /// The number of links which were checked, including broken ones.
+    pub checked: usize,
+    /// The number of links which were skipped (e.g. because of
+    /// [`crate::Config::exclude`] or [`crate::Config::follow_web_links`]).
+    pub skipped: usize,
+    /// The number of web links which were valid according to the cache,
+    /// rather than being checked again.
+    pub cached: usize,
+    /// The number of web links which were assumed to be valid because of
+    /// [`crate::Config::assume_reachable_hosts`].
+    pub assumed_ok: usize,
+    /// Every broken link.
+    pub broken: BrokenLinks,
+    /// How long checking the links took, if it was measured.
+    pub elapsed: Option<Duration>,
+}
+
+impl CheckReport {
+    /// Summarise a [`ValidationOutcome`].
+    pub fn new(outcome: &ValidationOutcome, files: &Files<String>) -> Self {
+        let cached = outcome.cached.len();
+        let assumed_ok = outcome.assumed_reachable.len();
+
+        CheckReport {
+            checked: (outcome.valid_links.len() + outcome.invalid_links.len())
+                .saturating_sub(cached + assumed_ok),
+            skipped: outcome.ignored.len() + outcome.unknown_category.len(),
+            cached,
+            assumed_ok,
+            broken: BrokenLinks::new(outcome, files),
+            elapsed: None,
+        }
+    }
+
+    /// Record how long checking the links took.
+    pub fn with_elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed = Some(elapsed);
+        self
+    }
+
+    /// Were any broken links found?
+    pub fn has_broken_links(&self) -> bool { !self.broken.links.is_empty() }
+}
+
+impl Display for CheckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let broken = self.broken.links.len();
+        let total = self.checked + self.cached + self.assumed_ok + self.skipped;
+
+        write!(
+            f,
+            "Checked {} ({} ok, {} skipped, {} broken",
+            plural(total, "link"),
+            self.checked + self.cached + self.assumed_ok - broken,
+            self.skipped,
+            broken
+        )?;
+
+        if self.cached > 0 {
+            write!(f, ", {} from the cache", self.cached)?;
+        }
+
+        if self.assumed_ok > 0 {
+            write!(f, ", {} assumed ok", self.assumed_ok)?;
+        }
+
+        write!(f, ")")?;
+
+        if let Some(elapsed) = self.elapsed {
+            write!(f, " in {:.1}s", elapsed.as_secs_f64())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A short description of what is wrong with a link.
+fn describe(link: &LinkReport) -> String {
+    let description = match link.status {
+        // most error messages already mention the link
+        LinkStatus::Broken { ref reason } if reason.contains(&link.href) => {
+            reason.clone()
+        },
+        LinkStatus::Broken { ref reason } => {
+            format!("{}: {}", link.href, reason)
+        },
+        _ => link.href.clone(),
+    };
+
+    match link.title {
+        Some(ref title) => format!("{} (\"{}\")", description, title),
+        None => description,
+    }
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Make sure text can't end a Markdown table cell early.
+fn escape_markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Wrap some text in an ANSI escape code, if colour is enabled.
+fn paint(colour: bool, style: &str, text: &dyn Display) -> String {
+    if colour {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Should [`BrokenLinks`] be printed in colour?
+///
+/// [`ColorChoice::Auto`] only uses colour when stderr (where the error ends
+/// up) is a terminal and the [`NO_COLOR`](https://no-color.org/) environment
+/// variable isn't set.
+pub(crate) fn use_colour(choice: ColorChoice) -> bool {
+    use_colour_with(
+        choice,
+        std::env::var_os("NO_COLOR").as_deref(),
+        atty::is(atty::Stream::Stderr),
+    )
+}
+
+fn use_colour_with(
+    choice: ColorChoice,
+    no_color: Option<&OsStr>,
+    is_terminal: bool,
+) -> bool {
+    match choice {
+        ColorChoice::Always | ColorChoice::AlwaysAnsi => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let disabled = matches!(no_color, Some(value) if !value.is_empty());
+            is_terminal && !disabled
+        },
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("1 {}", word),
+        n => format!("{} {}s", n, word),
+    }
+}
+
+/// What happened when a link was checked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum LinkStatus {
+    /// The link points somewhere valid.
+    Valid,
+    /// The link is broken.
+    Broken {
+        /// A human-readable explanation of what went wrong.
+        reason: String,
+    },
+    /// The link was skipped (e.g. because of [`crate::Config::exclude`]).
+    Ignored,
+    /// We don't know how to check this kind of link.
+    UnknownCategory,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::Span;
+    use linkcheck::validation::{InvalidLink, Reason};
+    use mdbook::book::{Book, Chapter};
+    use reqwest::StatusCode;
+
+    fn not_found(link: Link) -> InvalidLink {
+        InvalidLink {
+            link,
+            reason: Reason::Io(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    #[test]
+    fn serialize_a_mix_of_good_and_bad_links() {
+        let mut files = Files::new();
+        let src =
+            "[good](./good.md)\n\n[bad](./bad.md) [web](https://example.com/)";
+        let file = files.add("chapter_1.md", src.to_string());
+        let good = Link::new("./good.md", Span::new(0, 17), file);
+        let bad = Link::new("./bad.md", Span::new(19, 34), file);
+        let web = Link::new("https://example.com/", Span::new(35, 62), file);
+        let outcome = ValidationOutcome {
+            valid_links: vec![good],
+            invalid_links: vec![not_found(bad)],
+            ignored: vec![web],
+            ..Default::default()
+        };
+
+        let got = serde_json::to_value(Report::new(&outcome, &files)).unwrap();
+
+        let should_be = serde_json::json!({
+            "links": [
+                {
+                    "href": "./good.md",
+                    "file": "chapter_1.md",
+                    "line": 1,
+                    "column": 1,
+                    "status": "valid",
+                },
+                {
+                    "href": "./bad.md",
+                    "file": "chapter_1.md",
+                    "line": 3,
+                    "column": 1,
+                    "status": "broken",
+                    "reason": "File not found: ./bad.md",
+                },
+                {
+                    "href": "https://example.com/",
+                    "file": "chapter_1.md",
+                    "line": 3,
+                    "column": 17,
+                    "status": "ignored",
+                },
+            ],
+        });
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn include_how_long_each_link_took() {
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", "[a](./a.md) [b](./b.md)".into());
+        let checked = Link::new("./a.md", Span::new(0, 11), file);
+        let ignored = Link::new("./b.md", Span::new(12, 23), file);
+        let outcome = ValidationOutcome {
+            valid_links: vec![checked.clone()],
+            ignored: vec![ignored],
+            timings: vec![crate::LinkTiming {
+                link: checked,
+                elapsed: Duration::from_micros(12_345),
+            }],
+            ..Default::default()
+        };
+
+        let got = serde_json::to_value(Report::new(&outcome, &files)).unwrap();
+
+        let elapsed = &got["links"][0]["elapsed-ms"];
+        assert_eq!(elapsed.as_u64(), Some(12));
+        assert!(got["links"][1].get("elapsed-ms").is_none());
+    }
+
+    #[test]
+    fn format_broken_links_as_github_annotations() {
+        let mut files = Files::new();
+        let file = files.add("nested/chapter_1.md", "\n  [a](./a.md)".into());
+        let outcome = ValidationOutcome {
+            invalid_links: vec![
+                not_found(Link::new("./a.md", Span::new(3, 14), file)),
+                InvalidLink {
+                    link: Link::new("./b.md", Span::new(0, 1), file),
+                    reason: Reason::Io(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "100% broken,\nsorry",
+                    )),
+                },
+            ],
+            ..Default::default()
+        };
+        let broken = BrokenLinks::new(&outcome, &files);
+
+        let got = broken
+            .github_annotations(Path::new("book/src"), WarningPolicy::Error);
+
+        assert_eq!(
+            got,
+            &[
+                "::error file=book/src/nested/chapter_1.md,line=1,col=1::./b.md: \
+                 100%25 broken,%0Asorry",
+                "::error file=book/src/nested/chapter_1.md,line=2,col=3::File \
+                 not found: ./a.md",
+            ]
+        );
+        let warnings =
+            broken.github_annotations(Path::new("src"), WarningPolicy::Warn);
+        assert!(warnings[0].starts_with("::warning file=src/nested/"));
+        assert!(broken
+            .github_annotations(Path::new("src"), WarningPolicy::Ignore)
+            .is_empty());
+    }
+
+    #[test]
+    fn count_links_in_a_check_report() {
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", "[a](./a.md)".into());
+        let link = |href: &str| Link::new(href, Span::new(0, 11), file);
+        let outcome = ValidationOutcome {
+            valid_links: vec![
+                link("./b.md"),
+                link("https://example.com/"),
+                link("https://crates.io/"),
+            ],
+            invalid_links: vec![not_found(link("./a.md"))],
+            ignored: vec![link("https://google.com/")],
+            unknown_category: vec![link("")],
+            cached: vec![link("https://crates.io/")],
+            ..Default::default()
+        };
+
+        let got = CheckReport::new(&outcome, &files);
+
+        assert_eq!(got.checked, 3);
+        assert_eq!(got.cached, 1);
+        assert_eq!(got.skipped, 2);
+        assert!(got.has_broken_links());
+        assert_eq!(got.broken.links[0].0.href, "./a.md");
+        assert_eq!(
+            got.to_string(),
+            "Checked 6 links (3 ok, 2 skipped, 1 broken, 1 from the cache)"
+        );
+        assert_eq!(
+            got.with_elapsed(Duration::from_millis(4210)).to_string(),
+            "Checked 6 links (3 ok, 2 skipped, 1 broken, 1 from the cache) in \
+             4.2s"
+        );
+    }
+
+    #[test]
+    fn columns_count_characters_rather_than_bytes() {
+        let mut files = Files::new();
+        let src = "# Émojis 🎉\n\nSee ünïcödé → [here](./here.md)";
+        let file = files.add("chapter_1.md", src.to_string());
+        let start = src.find('[').unwrap();
+        let span = Span::new(start as u32, src.len() as u32);
+        let link = Link::new("./here.md", span, file);
+
+        let got = LinkReport::new(&link, LinkStatus::Valid, None, &files);
+
+        assert_eq!(got.line, 3);
+        assert_eq!(got.column, "See ünïcödé → [".chars().count());
+        assert_ne!(got.column, "See ünïcödé → [".len());
+    }
+
+    #[test]
+    fn links_past_the_end_of_the_file_dont_panic() {
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", "[a](./a.md)".into());
+        let link = Link::new("./a.md", Span::new(100, 111), file);
+
+        assert_eq!(location(&link, &files), (0, 0));
+
+        let got = LinkReport::new(&link, LinkStatus::Valid, None, &files);
+
+        assert_eq!((got.line, got.column), (0, 0));
+    }
+
+    #[test]
+    fn format_a_report_as_junit_xml() {
+        let mut files = Files::new();
+        let src = "[good](./good.md)\n\n[bad](./bad.md?a=1&b=2)";
+        let file = files.add("chapter_1.md", src.to_string());
+        let good = Link::new("./good.md", Span::new(0, 17), file);
+        let bad = Link::new("./bad.md?a=1&b=2", Span::new(19, 43), file);
+        let outcome = ValidationOutcome {
+            valid_links: vec![good],
+            invalid_links: vec![not_found(bad)],
+            ..Default::default()
+        };
+        let should_be = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="2" failures="1">
+  <testsuite name="mdbook-linkcheck" tests="2" failures="1" skipped="0">
+    <testcase name="./good.md" classname="chapter_1.md"/>
+    <testcase name="./bad.md?a=1&amp;b=2" classname="chapter_1.md">
+      <failure message="chapter_1.md:3:1: File not found: ./bad.md?a=1&amp;b=2"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#;
+
+        let got = Report::new(&outcome, &files).to_junit_xml();
+
+        assert_eq!(got, should_be);
+        assert_well_formed(&got);
+    }
+
+    #[test]
+    fn format_the_broken_links_as_a_markdown_table() {
+        let mut files = Files::new();
+        let src = "[good](./good.md)\n\n[bad](./bad.md?a|b)";
+        let file = files.add("chapter_1.md", src.to_string());
+        let good = Link::new("./good.md", Span::new(0, 17), file);
+        let bad = Link::new("./bad.md?a|b", Span::new(19, 38), file);
+        let outcome = ValidationOutcome {
+            valid_links: vec![good],
+            invalid_links: vec![not_found(bad)],
+            ..Default::default()
+        };
+        let should_be = r#"# Link Report
+
+| Chapter | Line | URL | Reason |
+| --- | --- | --- | --- |
+| chapter_1.md | 3 | `./bad.md?a\|b` | File not found: ./bad.md?a\|b |
+"#;
+
+        let got = Report::new(&outcome, &files).to_markdown();
+
+        assert_eq!(got, should_be);
+        let no_broken_links =
+            Report::new(&ValidationOutcome::default(), &files);
+        assert_eq!(
+            no_broken_links.to_markdown(),
+            "# Link Report\n\nNo broken links were found.\n"
+        );
+    }
+
+    /// A crude check that every tag is closed in the right order and there
+    /// are no unescaped ampersands.
+    #[track_caller]
+    fn assert_well_formed(xml: &str) {
+        let mut open_tags = Vec::new();
+
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').expect("Unterminated tag")];
+            let name = tag
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap();
+
+            if tag.starts_with('?') || tag.ends_with('/') {
+                continue;
+            } else if tag.starts_with('/') {
+                assert_eq!(open_tags.pop(), Some(name), "{}", xml);
+            } else {
+                open_tags.push(name);
+            }
+        }
+
+        assert!(open_tags.is_empty(), "Unclosed tags: {:?}", open_tags);
+        for (ix, _) in xml.match_indices('&') {
+            let entity = &xml[ix..xml[ix..].find(';').unwrap() + ix + 1];
+            assert!(
+                ["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"].contains(&entity),
+                "Unknown entity: {}",
+                entity
+            );
+        }
+    }
+
+    #[test]
+    fn list_links_in_a_two_chapter_book() {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Chapter 1",
+            "# Chapter 1\n\n[second](./chapter_2.md) [web](https://example.com/)"
+                .to_string(),
+            "chapter_1.md",
+            Vec::new(),
+        ))
+        .push_item(Chapter::new(
+            "Chapter 2",
+            "See [the first chapter](../chapter_1.md#chapter-1).".to_string(),
+            "nested/chapter_2.md",
+            Vec::new(),
+        ));
+        let links = crate::find_links(&book);
+        let should_be = "\
+chapter_1.md:3:1 ./chapter_2.md
+chapter_1.md:3:26 https://example.com/
+nested/chapter_2.md:1:5 ../chapter_1.md#chapter-1
+";
+
+        let got = list_links(&links);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn mention_the_title_of_broken_links() {
+        let mut files = Files::new();
+        let src = r#"[first](./missing.md "The install guide") and [second](./missing.md)"#;
+        let file = files.add("chapter_1.md", src.to_string());
+        let outcome = ValidationOutcome {
+            invalid_links: vec![
+                not_found(Link::new("./missing.md", Span::new(0, 41), file)),
+                not_found(Link::new("./missing.md", Span::new(46, 69), file)),
+            ],
+            ..Default::default()
+        };
+        let should_be = "\
+Found 2 broken links in 1 chapter
+
+chapter_1.md:
+  1:1  File not found: ./missing.md (\"The install guide\")
+  1:47  File not found: ./missing.md";
+
+        let got = BrokenLinks::new(&outcome, &files).to_string();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn summarise_broken_links_by_chapter() {
+        let mut files = Files::new();
+        let first = files.add("first.md", "[a](./a.md)\n[b](./b.md)".into());
+        let second = files.add("nested/second.md", "\n\n  [c](./c.md)".into());
+        let outcome = ValidationOutcome {
+            valid_links: vec![Link::new("./b.md", Span::new(12, 23), first)],
+            invalid_links: vec![
+                not_found(Link::new("./c.md", Span::new(4, 15), second)),
+                not_found(Link::new("./a.md", Span::new(0, 11), first)),
+            ],
+            ..Default::default()
+        };
+        let should_be = "\
+Found 2 broken links in 2 chapters
+
+first.md:
+  1:1  File not found: ./a.md
+
+nested/second.md:
+  3:3  File not found: ./c.md";
+
+        let got = BrokenLinks::new(&outcome, &files).to_string();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn highlight_broken_links_when_colour_is_enabled() {
+        let mut files = Files::new();
+        let first = files.add("first.md", "[a](./a.md)".into());
+        let outcome = ValidationOutcome {
+            invalid_links: vec![not_found(Link::new(
+                "./a.md",
+                Span::new(0, 11),
+                first,
+            ))],
+            ..Default::default()
+        };
+        let broken = BrokenLinks::new(&outcome, &files);
+        let should_be = "\
+Found 1 broken link in 1 chapter
+
+\x1b[1mfirst.md\x1b[0m:
+  \x1b[2m1:1\x1b[0m  \x1b[31mFile not found: ./a.md\x1b[0m";
+
+        let got = broken.clone().with_colour(true).to_string();
+
+        assert_eq!(got, should_be);
+        let plain = broken.with_colour(false).to_string();
+        assert!(!plain.contains('\x1b'), "{:?}", plain);
+    }
+
+    #[test]
+    fn only_use_colour_when_somebody_is_watching() {
+        let no_color = Some(OsStr::new("1"));
+        let inputs = vec![
+            (ColorChoice::Always, no_color, false, true),
+            (ColorChoice::Never, None, true, false),
+            (ColorChoice::Auto, None, true, true),
+            (ColorChoice::Auto, None, false, false),
+            (ColorChoice::Auto, no_color, true, false),
+            (ColorChoice::Auto, Some(OsStr::new("")), true, true),
+        ];
+
+        for (choice, no_color, is_terminal, should_be) in inputs {
+            let got = use_colour_with(choice, no_color, is_terminal);
+            assert_eq!(
+                got, should_be,
+                "{:?}, NO_COLOR={:?}, terminal: {}",
+                choice, no_color, is_terminal
+            );
+        }
+    }
+
+    #[test]
+    fn group_links_which_are_broken_for_the_same_reason() {
+        let mut files = Files::new();
+        let src = "[a](https://example.com/gone)\n[b](./b.md)";
+        let first = files.add("first.md", src.into());
+        let second =
+            files.add("second.md", "See [a](https://example.com/gone)".into());
+        let third = files.add(
+            "nested/third.md",
+            "\n\n[a](https://example.com/gone)".into(),
+        );
+        let gone = |span, file| InvalidLink {
+            link: Link::new("https://example.com/gone", span, file),
+            reason: Reason::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "404 Not Found",
+            )),
+        };
+        let outcome = ValidationOutcome {
+            invalid_links: vec![
+                gone(Span::new(0, 29), first),
+                not_found(Link::new("./b.md", Span::new(30, 41), first)),
+                gone(Span::new(4, 33), second),
+                gone(Span::new(2, 31), third),
+            ],
+            ..Default::default()
+        };
+        let should_be = "\
+Found 4 broken links in 3 chapters
+
+first.md:
+  2:1  File not found: ./b.md
+
+https://example.com/gone: 404 Not Found (3 links):
+  first.md:1:1
+  second.md:1:5
+  nested/third.md:3:1";
+
+        let got = BrokenLinks::new(&outcome, &files).to_string();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn iterate_over_broken_links_by_kind() {
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", "[a](./a.md)".into());
+        let link = |href: &str, start| {
+            Link::new(href, Span::new(start, start + 1), file)
+        };
+        let gone = UnsuccessfulStatus {
+            url: "https://example.com/gone".parse().unwrap(),
+            status: StatusCode::NOT_FOUND,
+            body: None,
+            retry_after: None,
+        };
+        let outcome = ValidationOutcome {
+            invalid_links: vec![
+                InvalidLink {
+                    link: link("https://example.com/gone", 2),
+                    reason: Reason::Io(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        gone.clone(),
+                    )),
+                },
+                not_found(link("./a.md", 0)),
+                InvalidLink {
+                    link: link("./b.md#missing", 1),
+                    reason: Reason::Io(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        MissingAnchor {
+                            path: "b.md".into(),
+                            fragment: String::from("missing"),
+                            suggestion: None,
+                        },
+                    )),
+                },
+                InvalidLink {
+                    link: link("https://example.com/", 3),
+                    reason: Reason::Io(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "DNS error",
+                    )),
+                },
+            ],
+            ..Default::default()
+        };
+        let broken = BrokenLinks::new(&outcome, &files);
+
+        let mut hrefs = Vec::new();
+        for (link, error) in &broken {
+            match error {
+                LinkError::FileNotFound { href } => assert_eq!(href, "./a.md"),
+                LinkError::MissingAnchor(anchor) => {
+                    assert_eq!(anchor.fragment, "missing")
+                },
+                LinkError::Unsuccessful(status) => assert_eq!(status, &gone),
+                LinkError::Other(msg) => assert_eq!(msg, "DNS error"),
+                other => panic!("Unexpected error: {:?}", other),
+            }
+            hrefs.push(link.href.clone());
+        }
+
+        assert_eq!(
+            hrefs,
+            &[
+                "./a.md",
+                "./b.md#missing",
+                "https://example.com/gone",
+                "https://example.com/",
+            ]
+        );
+        let errors: Vec<String> = broken
+            .into_iter()
+            .map(|(_, error)| error.to_string())
+            .collect();
+        assert_eq!(errors[0], "File not found: ./a.md");
+        assert_eq!(
+            errors[2],
+            "Server returned 404 Not Found for https://example.com/gone"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use mdbook::book::{Book, BookItem};
+use std::{
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+};
+
+/// Find every chapter in `SUMMARY.md` which doesn't have a source file.
+///
+/// Draft chapters (e.g. `- [Coming Soon]()`) are included too, because their
+/// page won't exist in the rendered book.
+pub(crate) fn find_missing_chapters(
+    book: &Book,
+    src_dir: &Path,
+) -> Vec<MissingChapter> {
+    let mut missing = Vec::new();
+
+    for item in book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(ref chapter) => chapter,
+            BookItem::Separator | BookItem::PartTitle(_) => continue,
+        };
+
+        match chapter.source_path {
+            Some(ref path) if src_dir.join(path).is_file() => {},
+            ref path => missing.push(MissingChapter {
+                name: chapter.name.clone(),
+                path: path.clone(),
+            }),
+        }
+    }
+
+    missing
+}
+
+/// A chapter listed in `SUMMARY.md` which doesn't exist on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingChapter {
+    /// The chapter's name.
+    pub name: String,
+    /// The chapter's source file, relative to the source directory, or
+    /// `None` for draft chapters.
+    pub path: Option<PathBuf>,
+}
+
+impl MissingChapter {
+    /// Is this a draft chapter, which doesn't have a source file in the first
+    /// place?
+    pub fn is_draft(&self) -> bool { self.path.is_none() }
+}
+
+impl Display for MissingChapter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.path {
+            Some(ref path) => write!(
+                f,
+                "The \"{}\" chapter in SUMMARY.md points to \"{}\", which doesn't exist",
+                self.name,
+                path.display()
+            ),
+            None => write!(
+                f,
+                "The \"{}\" chapter in SUMMARY.md is a draft and won't be rendered",
+                self.name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MissingChapter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdbook::book::Chapter;
+
+    #[test]
+    fn detect_dangling_summary_entries() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut chapter_1 = Chapter::new(
+            "Chapter 1",
+            String::new(),
+            "chapter_1.md",
+            Vec::new(),
+        );
+        chapter_1.sub_items.push(BookItem::Chapter(Chapter::new(
+            "Missing",
+            String::new(),
+            "nested/missing.md",
+            vec![String::from("Chapter 1")],
+        )));
+        let mut book = Book::new();
+        book.push_item(chapter_1)
+            .push_item(BookItem::Separator)
+            .push_item(Chapter::new_draft("Coming Soon", Vec::new()));
+        let should_be = vec![
+            MissingChapter {
+                name: String::from("Missing"),
+                path: Some(PathBuf::from("nested/missing.md")),
+            },
+            MissingChapter {
+                name: String::from("Coming Soon"),
+                path: None,
+            },
+        ];
+
+        let got = find_missing_chapters(&book, &src_dir);
+
+        assert_eq!(got, should_be);
+        assert!(!got[0].is_draft());
+        assert!(got[1].is_draft());
+    }
+}
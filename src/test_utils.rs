@@ -0,0 +1,171 @@
+//! Helpers shared by the unit tests.
+
+use crate::{web::RateLimiter, CancellationToken, Config, Context};
+use linkcheck::validation::{Cache, Options};
+use once_cell::sync::OnceCell;
+use reqwest::Url;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Create a [`Context`] for checking links with the provided [`Config`].
+pub(crate) fn context(cfg: &Config) -> Context<'_> {
+    Context {
+        cfg,
+        cache: Mutex::new(Cache::default()),
+        client: OnceCell::new(),
+        filesystem_options: Options::default(),
+        interpolated_headers: cfg.interpolate_headers(cfg.warning_policy),
+        rate_limiter: RateLimiter::new(cfg.requests_per_host_per_second)
+            .with_global_delay(Duration::from_millis(cfg.global_delay_ms)),
+        fetcher: Arc::new(cfg.client().unwrap()),
+        deadline: cfg.deadline(),
+        redirects: Mutex::new(HashMap::new()),
+        timings: Mutex::new(HashMap::new()),
+        cancellation: CancellationToken::new(),
+    }
+}
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Get the value of a header, ignoring case.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The canned response a [`MockServer`] sends back.
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub(crate) fn with_body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+
+    pub(crate) fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A tiny HTTP/1.1 server which records every request it receives and replies
+/// using a user-provided handler.
+#[derive(Debug)]
+pub(crate) struct MockServer {
+    base: Url,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub(crate) fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let recorded = Arc::clone(&recorded);
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || {
+                    if let Some(request) = read_request(&stream) {
+                        recorded.lock().unwrap().push(request.clone());
+                        let _ = write_response(&stream, &handler(&request));
+                    }
+                });
+            }
+        });
+
+        MockServer { base, requests }
+    }
+
+    pub(crate) fn url(&self, path: &str) -> Url {
+        self.base.join(path).unwrap()
+    }
+
+    /// Every request received so far, in the order they arrived.
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+
+    let mut words = line.split_whitespace();
+    let method = words.next()?.to_string();
+    let path = words.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+
+    Some(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+fn write_response(
+    mut stream: &TcpStream,
+    response: &Response,
+) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 {} Mock\r\n", response.status)?;
+    for (name, value) in &response.headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
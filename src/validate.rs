@@ -1,32 +1,56 @@
-use crate::{Config, Context, IncompleteLink, WarningPolicy};
-use anyhow::Error;
-use codespan::{FileId, Files};
-use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
+use crate::{
+    anchors::{closest_anchor, find_duplicate_headings, DuplicateHeading},
+    graph::find_chapter,
+    links::{find_mismatched_urls, MismatchedUrl},
+    local::{
+        check_local_links, find_case_mismatch, needs_normalising,
+        rendered_path, split_href, CaseMismatch,
+    },
+    mailto::{check_mailto_links, is_mailto},
+    progress::Progress,
+    web::{LinkFetcher, RateLimiter},
+    CancellationToken, Config, Context, IncompleteLink, LinkError,
+    MissingChapter, UnsupportedScheme, WarningPolicy,
+};
+use anyhow::{Context as _, Error};
+use codespan::{FileId, Files, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use futures::StreamExt;
 use linkcheck::{
     validation::{Cache, InvalidLink, Options, Outcomes, Reason},
     Link,
 };
+use once_cell::sync::OnceCell;
+use reqwest::Url;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::{OsStr, OsString},
     fmt::{self, Display, Formatter},
     path::{Component, Path, PathBuf},
-    sync::Mutex,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use tokio::runtime::Builder;
 
+/// How long it took to check each link, keyed by where the link is.
+type Timings = HashMap<(FileId, Span), Duration>;
+
+#[allow(clippy::too_many_arguments)]
 fn lc_validate(
     links: &[Link],
     cfg: &Config,
+    fetcher: Arc<dyn LinkFetcher>,
+    cancellation: &CancellationToken,
     src_dir: &Path,
     cache: &mut Cache,
     files: &Files<String>,
     file_ids: &[FileId],
-) -> Outcomes {
+) -> (Outcomes, HashMap<Url, Url>, Timings) {
     let file_names = file_ids
         .iter()
         .map(|id| files.name(*id).to_os_string())
         .collect();
+    let anchors = Arc::new(index_anchors(files, file_ids));
 
     let options = Options::default()
         .with_root_directory(src_dir)
@@ -41,32 +65,72 @@ fn lc_validate(
         // take into account the `index` preprocessor which rewrites `README.md`
         // to `index.md` (which tne gets rendered as `index.html`)
         .set_default_file("README.md")
-        .set_custom_validation(ensure_included_in_book(src_dir, file_names));
+        .set_custom_validation(ensure_included_in_book(
+            src_dir,
+            file_names,
+            Arc::clone(&anchors),
+        ));
 
     let interpolated_headers = cfg.interpolate_headers(cfg.warning_policy);
 
     let ctx = Context {
-        client: cfg.client(),
+        client: OnceCell::new(),
         filesystem_options: options,
         cfg,
-        src_dir,
         cache: Mutex::new(cache.clone()),
-        files,
         interpolated_headers,
+        rate_limiter: RateLimiter::new(cfg.requests_per_host_per_second)
+            .with_global_delay(Duration::from_millis(cfg.global_delay_ms)),
+        fetcher,
+        deadline: cfg.deadline(),
+        redirects: Mutex::new(HashMap::new()),
+        timings: Mutex::new(HashMap::new()),
+        cancellation: cancellation.clone(),
     };
-    let links = collate_links(links, src_dir, files);
+    // linkcheck doesn't know how to check links to somewhere else in the
+    // current file, mailto links, or paths which need to be percent-decoded
+    // or use backslashes, and we want more control over how web links are
+    // checked, so we need to handle those ourselves
+    let (links, elsewhere_on_site, mut original_hrefs) =
+        strip_site_root(links, cfg.site_root.as_deref());
+    let (empty_links, links): (Vec<_>, Vec<_>) =
+        links.iter().partition(|link| link.href.trim().is_empty());
+    let (current_file_links, links): (Vec<_>, Vec<_>) = links
+        .into_iter()
+        .partition(|link| link.href.starts_with('#'));
+    let (mailto_links, links): (Vec<_>, Vec<_>) =
+        links.into_iter().partition(|link| is_mailto(&link.href));
+    let (web_links, links): (Vec<_>, Vec<_>) = links
+        .into_iter()
+        .partition(|link| as_web_link(link).is_some());
+    let (local_links, links): (Vec<_>, Vec<_>) = links
+        .into_iter()
+        .partition(|link| needs_normalising(&link.href));
+    let (slashed_files, links): (Vec<_>, Vec<_>) = links
+        .into_iter()
+        .partition(|link| is_file_with_trailing_slash(link, src_dir, files));
+    let (links, index_hrefs) = rewrite_index_links(links, src_dir, files);
+    for (key, href) in index_hrefs {
+        original_hrefs.entry(key).or_insert(href);
+    }
+    let links = collate_links(links.iter().collect(), src_dir, files);
 
     let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
     let got = runtime.block_on(async {
-        let mut outcomes = Outcomes::default();
+        let mut outcomes =
+            check_current_file_links(current_file_links, &ctx, &anchors, files);
+        outcomes.merge(check_empty_links(empty_links, &ctx));
+        outcomes.merge(check_files_with_trailing_slashes(slashed_files, &ctx));
+        outcomes.ignored.extend(elsewhere_on_site);
+        outcomes.merge(check_mailto_links(mailto_links, &ctx));
+        outcomes.merge(check_web_links(web_links, &ctx).await);
+        outcomes.merge(check_local_links(local_links, &ctx, src_dir, files));
 
-        for (current_dir, links) in links {
-            outcomes
-                .merge(linkcheck::validate(&current_dir, links, &ctx).await);
-        }
+        outcomes.extend(check_filesystem_links(links, &ctx).await);
 
         outcomes
     });
+    let got = restore_original_hrefs(got, &original_hrefs);
 
     // move the cache out of ctx. We'd get a borrowing error if anything was
     // using it
@@ -75,16 +139,60 @@ fn lc_validate(
     *cache = updated_cache
         .into_inner()
         .expect("We statically know this isn't used");
-    got
+    let redirects = ctx
+        .redirects
+        .into_inner()
+        .expect("We statically know this isn't used");
+    let timings = ctx
+        .timings
+        .into_inner()
+        .expect("We statically know this isn't used");
+
+    (got, redirects, timings)
+}
+
+/// Check links to files with linkcheck, recording how long each one took.
+///
+/// linkcheck only reports on a batch as a whole, so each link is handed over
+/// on its own, checking as many at once as linkcheck would for the whole
+/// batch.
+async fn check_filesystem_links(
+    links: impl Iterator<Item = (PathBuf, Vec<Link>)>,
+    ctx: &Context<'_>,
+) -> Vec<Outcomes> {
+    use linkcheck::validation::Context as _;
+
+    let links = links.flat_map(|(current_dir, links)| {
+        links
+            .into_iter()
+            .map(move |link| (current_dir.clone(), link))
+    });
+
+    futures::stream::iter(links)
+        .map(|(current_dir, link)| async move {
+            let start = Instant::now();
+            let key = (link.file, link.span);
+            let outcomes =
+                linkcheck::validate(&current_dir, vec![link], ctx).await;
+            ctx.timings
+                .lock()
+                .expect("Lock was poisoned")
+                .insert(key, start.elapsed());
+            outcomes
+        })
+        .buffer_unordered(ctx.concurrency())
+        .collect()
+        .await
 }
 
 fn ensure_included_in_book(
     src_dir: &Path,
     file_names: Vec<OsString>,
+    anchors: Arc<HashMap<PathBuf, HashSet<String>>>,
 ) -> impl Fn(&Path, Option<&str>) -> Result<(), Reason> {
     let src_dir = src_dir.to_path_buf();
 
-    move |resolved_link, _| {
+    move |resolved_link, fragment| {
         let resolved_link = match resolved_link.strip_prefix(&src_dir) {
             Ok(path) => path,
             // Not part of the book.
@@ -108,24 +216,536 @@ fn ensure_included_in_book(
         let ext = resolved_link.extension();
         let is_markdown = ext == Some(OsStr::new("md"));
 
-        if was_included_in_summary || !is_markdown {
-            Ok(())
-        } else {
-            use std::io::{Error, ErrorKind};
-
-            Err(Reason::Io(Error::new(
-                ErrorKind::Other,
+        if !was_included_in_summary && is_markdown {
+            return Err(Reason::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
                 NotInSummary {
                     path: resolved_link.to_path_buf(),
                 },
+            )));
+        }
+
+        match fragment {
+            Some(fragment) => ensure_anchor_exists(
+                &anchors,
+                &index_anchor_key(resolved_link),
+                fragment,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The href each rewritten link had before we touched it.
+type OriginalHrefs = HashMap<(FileId, Span), String>;
+
+/// When the book is served under a [`Config::site_root`] (e.g. `/my-book/`),
+/// rewrite root-relative links like `/my-book/nested/page.md` to be relative
+/// to the source directory (`/nested/page.md`), remembering each original href
+/// so it can be restored afterwards.
+///
+/// Any other root-relative links point somewhere else on the website, so they
+/// are returned separately because we can't check them.
+fn strip_site_root(
+    links: &[Link],
+    site_root: Option<&str>,
+) -> (Vec<Link>, Vec<Link>, OriginalHrefs) {
+    let mut original_hrefs = HashMap::new();
+    let site_root = match site_root.map(|root| root.trim_matches('/')) {
+        Some(root) if !root.is_empty() => format!("/{}", root),
+        _ => return (links.to_vec(), Vec::new(), original_hrefs),
+    };
+
+    let mut in_book = Vec::new();
+    let mut elsewhere_on_site = Vec::new();
+
+    for link in links {
+        if !link.href.starts_with('/') || link.href.starts_with("//") {
+            in_book.push(link.clone());
+            continue;
+        }
+
+        match link.href.strip_prefix(site_root.as_str()) {
+            Some(rest) if rest.starts_with('/') => {
+                original_hrefs
+                    .insert((link.file, link.span), link.href.clone());
+                in_book.push(Link::new(rest, link.span, link.file));
+            },
+            Some(rest) if rest.is_empty() || rest.starts_with(['#', '?']) => {
+                original_hrefs
+                    .insert((link.file, link.span), link.href.clone());
+                in_book.push(Link::new(
+                    format!("/{}", rest),
+                    link.span,
+                    link.file,
+                ));
+            },
+            _ => elsewhere_on_site.push(link.clone()),
+        }
+    }
+
+    (in_book, elsewhere_on_site, original_hrefs)
+}
+
+/// `mdbook` renders a directory's `README.md` (or `index.md`) as
+/// `index.html`, but linkcheck only knows how to swap `index.html` for
+/// `index.md` and always resolves a directory to its `README.md`. Rewrite
+/// links to `dir/index.html` and `dir/` so they point at whichever index
+/// chapter exists, remembering each original href so it can be restored
+/// afterwards.
+///
+/// Trailing slashes are only meaningful for directories, so a link to a file
+/// with one (e.g. `chapter.md/`) isn't rewritten and gets reported as broken.
+///
+/// Directories without an index chapter are left alone so they are still
+/// reported as broken.
+fn rewrite_index_links(
+    links: Vec<&Link>,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> (Vec<Link>, OriginalHrefs) {
+    let mut original_hrefs = HashMap::new();
+
+    let links = links
+        .into_iter()
+        .map(|link| {
+            let (path, suffix, target) = split_target(link, src_dir, files);
+            if path.is_empty() {
+                return link.clone();
+            }
+
+            let rewritten = if Path::new(path).file_name()
+                == Some(OsStr::new("index.html"))
+            {
+                if target.with_file_name("index.md").exists() {
+                    return link.clone();
+                }
+                format!(
+                    "{}README.md{}",
+                    &path[..path.len() - "index.html".len()],
+                    suffix
+                )
+            } else if path.ends_with('/') || target.is_dir() {
+                if target.join("README.md").exists()
+                    || !target.join("index.md").exists()
+                {
+                    return link.clone();
+                }
+                let separator = if path.ends_with('/') { "" } else { "/" };
+                format!("{}{}index.md{}", path, separator, suffix)
+            } else {
+                return link.clone();
+            };
+
+            original_hrefs.insert((link.file, link.span), link.href.clone());
+            Link::new(rewritten, link.span, link.file)
+        })
+        .collect();
+
+    (links, original_hrefs)
+}
+
+/// Split a link to somewhere in the book into its path and any `?query` or
+/// `#fragment`, and find where the path points on disk.
+fn split_target<'a>(
+    link: &'a Link,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> (&'a str, &'a str, PathBuf) {
+    // any ?query stays with the #fragment so neither get in the way
+    let (path, suffix) = match link.href.find(['?', '#']) {
+        Some(ix) => link.href.split_at(ix),
+        None => (link.href.as_str(), ""),
+    };
+
+    let target = if let Some(absolute) = path.strip_prefix('/') {
+        src_dir.join(absolute)
+    } else {
+        let chapter = src_dir.join(files.name(link.file));
+        chapter.parent().unwrap_or(src_dir).join(path)
+    };
+
+    (path, suffix, target)
+}
+
+/// Is this something like `chapter.md/`, with a trailing slash even though it
+/// doesn't point at a directory? linkcheck ignores the slash, but a web
+/// server won't.
+fn is_file_with_trailing_slash(
+    link: &Link,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> bool {
+    let (path, _, target) = split_target(link, src_dir, files);
+    path.ends_with('/') && !target.is_dir()
+}
+
+/// Undo [`rewrite_index_links()`] so users see the links they wrote.
+fn restore_original_hrefs(
+    mut outcomes: Outcomes,
+    original_hrefs: &OriginalHrefs,
+) -> Outcomes {
+    let links = outcomes
+        .valid
+        .iter_mut()
+        .chain(outcomes.invalid.iter_mut().map(|invalid| &mut invalid.link))
+        .chain(outcomes.ignored.iter_mut())
+        .chain(outcomes.unknown_category.iter_mut());
+
+    for link in links {
+        if let Some(href) = original_hrefs.get(&(link.file, link.span)) {
+            link.href = href.clone();
+        }
+    }
+
+    outcomes
+}
+
+/// Build a lookup table of every anchor in each file, keyed by the file's path
+/// relative to the source directory.
+fn index_anchors(
+    files: &Files<String>,
+    file_ids: &[FileId],
+) -> HashMap<PathBuf, HashSet<String>> {
+    file_ids
+        .iter()
+        .map(|&id| {
+            let path = index_anchor_key(Path::new(files.name(id)));
+            (path, crate::collect_anchors(files.source(id)))
+        })
+        .collect()
+}
+
+/// The index preprocessor renames `README.md` to `index.md`, so we need to
+/// normalise the two before doing any lookups.
+fn index_anchor_key(path: &Path) -> PathBuf {
+    if path.file_name() == Some(OsStr::new("README.md")) {
+        path.with_file_name("index.md")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn ensure_anchor_exists(
+    anchors: &HashMap<PathBuf, HashSet<String>>,
+    path: &Path,
+    fragment: &str,
+) -> Result<(), Reason> {
+    let decoded = percent_encoding::percent_decode_str(fragment)
+        .decode_utf8_lossy()
+        .into_owned();
+
+    match anchors.get(path) {
+        // an empty fragment just links to the top of the page
+        Some(known)
+            if !fragment.is_empty()
+                && !known.contains(fragment)
+                && !known.contains(&decoded) =>
+        {
+            Err(Reason::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                MissingAnchor {
+                    path: path.to_path_buf(),
+                    fragment: fragment.to_string(),
+                    suggestion: closest_anchor(fragment, known),
+                },
             )))
+        },
+        Some(_) => Ok(()),
+        None => {
+            log::debug!(
+                "Not checking the \"{}\" fragment because \"{}\" isn't a chapter",
+                fragment,
+                path.display()
+            );
+            Ok(())
+        },
+    }
+}
+
+/// Get the [`Url`] for a link which should be checked on the web.
+pub(crate) fn as_web_link(link: &Link) -> Option<Url> {
+    // mailto links are checked separately
+    if is_mailto(&link.href) {
+        return None;
+    }
+
+    crate::web::parse_url(&link.href)
+}
+
+async fn check_web_links(links: Vec<&Link>, ctx: &Context<'_>) -> Outcomes {
+    use linkcheck::validation::Context as _;
+
+    let mut outcomes = Outcomes::default();
+    // the same URL is often linked to from several chapters, so group links
+    // by URL and make sure each one is only checked once. URLs are kept in
+    // the order they were first seen so the max-links budget is predictable.
+    let mut links_by_url: Vec<(Url, Vec<&Link>)> = Vec::new();
+    let mut indices: HashMap<Url, usize> = HashMap::new();
+
+    for link in links {
+        match as_web_link(link) {
+            _ if ctx.should_ignore(link) => outcomes.ignored.push(link.clone()),
+            Some(url) if crate::web::is_http(&url) => {
+                let ix = *indices.entry(url.clone()).or_insert_with(|| {
+                    links_by_url.push((url, Vec::new()));
+                    links_by_url.len() - 1
+                });
+                links_by_url[ix].1.push(link);
+            },
+            Some(url) => check_other_scheme(link, &url, ctx.cfg, &mut outcomes),
+            None => outcomes.ignored.push(link.clone()),
+        }
+    }
+
+    if let Some(max_links) = ctx.cfg.max_links {
+        if links_by_url.len() > max_links {
+            let over_budget = links_by_url.split_off(max_links);
+            log::warn!(
+                "Skipping {} web links because max-links ({}) was reached",
+                over_budget.len(),
+                max_links
+            );
+            for (_, links) in over_budget {
+                outcomes.ignored.extend(links.into_iter().cloned());
+            }
+        }
+    }
+
+    let mut progress = Progress::for_web_links(
+        links_by_url.len(),
+        ctx.cfg,
+        |checked, total| log::info!("Checked {}/{} web links", checked, total),
+    );
+    let mut results = futures::stream::iter(links_by_url)
+        .map(|(url, links)| async move {
+            let start = Instant::now();
+            let result = check_web_link_before_deadline(&url, ctx).await;
+            (links, result, start.elapsed())
+        })
+        .buffer_unordered(ctx.concurrency());
+
+    let mut not_checked = 0;
+
+    while let Some((links, result, elapsed)) = results.next().await {
+        progress.tick();
+
+        if result.is_some() {
+            for &link in &links {
+                ctx.record_timing(link, elapsed);
+            }
+        }
+
+        match result {
+            Some(Ok(_)) => outcomes.valid.extend(links.into_iter().cloned()),
+            Some(Err(reason)) => {
+                report_broken_web_link(&links, reason, &mut outcomes)
+            },
+            None => {
+                not_checked += links.len();
+                outcomes.ignored.extend(links.into_iter().cloned());
+            },
+        }
+    }
+
+    if not_checked > 0 {
+        let why = if ctx.cancellation.is_cancelled() {
+            "cancelled"
+        } else {
+            "deadline exceeded"
+        };
+        log::warn!("{} web links were not checked ({})", not_checked, why);
+    }
+
+    outcomes
+}
+
+/// Check a web link, returning `None` if the deadline passed (or the run was
+/// cancelled) before we could finish.
+async fn check_web_link_before_deadline(
+    url: &Url,
+    ctx: &Context<'_>,
+) -> Option<Result<(), Reason>> {
+    // don't start any new requests once the run has been cancelled
+    if ctx.cancellation.is_cancelled() {
+        return None;
+    }
+
+    let deadline = match ctx.deadline {
+        Some(deadline) => tokio::time::Instant::from_std(deadline),
+        None => return Some(crate::web::check_web(url, ctx).await),
+    };
+
+    // don't start any new requests once we've run out of time
+    if tokio::time::Instant::now() >= deadline {
+        return None;
+    }
+
+    tokio::time::timeout_at(deadline, crate::web::check_web(url, ctx))
+        .await
+        .ok()
+}
+
+/// Links using schemes like `tel:` or `data:` can't be checked with a HTTP
+/// request, so they are skipped unless the user has restricted which schemes
+/// are allowed.
+fn check_other_scheme(
+    link: &Link,
+    url: &Url,
+    cfg: &Config,
+    outcomes: &mut Outcomes,
+) {
+    let allowed = match cfg.allowed_schemes {
+        Some(ref schemes) => schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme())),
+        None => true,
+    };
+
+    if allowed {
+        log::debug!("Skipping \"{}\" because it isn't a web link", link.href);
+        outcomes.ignored.push(link.clone());
+    } else {
+        let error = UnsupportedScheme {
+            scheme: url.scheme().to_string(),
+        };
+        outcomes.invalid.push(InvalidLink {
+            link: link.clone(),
+            reason: Reason::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                error,
+            )),
+        });
+    }
+}
+
+/// Record every occurrence of a broken web link.
+///
+/// A [`reqwest::Error`] can't be cloned, so only the first occurrence gets the
+/// original [`Reason`] and the rest are given the equivalent [`LinkError`].
+fn report_broken_web_link(
+    links: &[&Link],
+    reason: Reason,
+    outcomes: &mut Outcomes,
+) {
+    let (first, rest) = match links.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let first = InvalidLink {
+        link: (*first).clone(),
+        reason,
+    };
+    let error = LinkError::from(&first);
+    outcomes.invalid.push(first);
+
+    for &link in rest {
+        outcomes.invalid.push(InvalidLink {
+            link: link.clone(),
+            reason: Reason::Io(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                error.clone(),
+            )),
+        });
+    }
+}
+
+/// Check links like `#some-heading` which point to somewhere in the current
+/// file.
+fn check_current_file_links(
+    links: Vec<&Link>,
+    ctx: &Context<'_>,
+    anchors: &HashMap<PathBuf, HashSet<String>>,
+    files: &Files<String>,
+) -> Outcomes {
+    use linkcheck::validation::Context as _;
+
+    let mut outcomes = Outcomes::default();
+
+    for link in links {
+        if ctx.should_ignore(link) {
+            outcomes.ignored.push(link.clone());
+            continue;
+        }
+
+        let path = index_anchor_key(Path::new(files.name(link.file)));
+        match ensure_anchor_exists(anchors, &path, &link.href[1..]) {
+            Ok(_) => outcomes.valid.push(link.clone()),
+            Err(reason) => outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason,
+            }),
+        }
+    }
+
+    outcomes
+}
+
+/// Links with a trailing slash which don't point at a directory (see
+/// [`is_file_with_trailing_slash()`]) are always broken, unless they've been
+/// excluded.
+fn check_files_with_trailing_slashes(
+    links: Vec<&Link>,
+    ctx: &Context<'_>,
+) -> Outcomes {
+    use linkcheck::validation::Context as _;
+
+    let mut outcomes = Outcomes::default();
+
+    for link in links {
+        if ctx.should_ignore(link) {
+            outcomes.ignored.push(link.clone());
+        } else {
+            outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason: Reason::Io(std::io::ErrorKind::NotFound.into()),
+            });
+        }
+    }
+
+    outcomes
+}
+
+/// Links like `[text]()` don't point anywhere, so they are always broken
+/// (unless they've been excluded).
+fn check_empty_links(links: Vec<&Link>, ctx: &Context<'_>) -> Outcomes {
+    use linkcheck::validation::Context as _;
+
+    let mut outcomes = Outcomes::default();
+
+    for link in links {
+        if ctx.should_ignore(link) {
+            outcomes.ignored.push(link.clone());
+        } else {
+            outcomes.invalid.push(InvalidLink {
+                link: link.clone(),
+                reason: Reason::Io(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    EmptyLink,
+                )),
+            });
         }
     }
+
+    outcomes
+}
+
+/// An error that is emitted when a link's destination is empty or only
+/// contains whitespace (e.g. `[text]()`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EmptyLink;
+
+impl Display for EmptyLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The link doesn't have a destination")
+    }
 }
 
+impl std::error::Error for EmptyLink {}
+
 /// An error that is emitted if something links to a file that exists on disk,
 /// but isn't included in the book.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NotInSummary {
     /// The file's full path.
     pub path: PathBuf,
@@ -143,8 +763,41 @@ impl Display for NotInSummary {
 
 impl std::error::Error for NotInSummary {}
 
+/// An error that is emitted when a link's `#fragment` doesn't match any of the
+/// headings in the chapter being linked to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingAnchor {
+    /// The chapter being linked to, relative to the source directory, or the
+    /// web page's URL when [`Config::check_fragments`] is enabled.
+    pub path: PathBuf,
+    /// The fragment which couldn't be found.
+    pub fragment: String,
+    /// An anchor with a similar name (e.g. `introduction` for `#intro`),
+    /// which the link was probably meant to point to.
+    pub suggestion: Option<String>,
+}
+
+impl Display for MissingAnchor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "There is no \"#{}\" anchor in \"{}\"",
+            self.fragment,
+            self.path.display()
+        )?;
+
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, ", did you mean \"#{}\"?", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MissingAnchor {}
+
 fn collate_links<'a>(
-    links: &'a [Link],
+    links: Vec<&'a Link>,
     src_dir: &Path,
     files: &'a Files<String>,
 ) -> impl Iterator<Item = (PathBuf, Vec<linkcheck::Link>)> {
@@ -191,6 +844,17 @@ fn merge_outcomes(
         valid_links: sorted_link(outcomes.valid),
         unknown_category: sorted_link(outcomes.unknown_category),
         incomplete_links,
+        cached: Vec::new(),
+        assumed_reachable: Vec::new(),
+        missing_chapters: Vec::new(),
+        duplicate_headings: Vec::new(),
+        case_mismatches: Vec::new(),
+        redirected_links: Vec::new(),
+        timings: Vec::new(),
+        slow_links: Vec::new(),
+        mismatched_urls: Vec::new(),
+        insecure_links: Vec::new(),
+        orphaned_chapters: Vec::new(),
     }
 }
 
@@ -204,56 +868,596 @@ pub fn validate(
     file_ids: &[FileId],
     incomplete_links: Vec<IncompleteLink>,
 ) -> Result<ValidationOutcome, Error> {
-    let got = lc_validate(links, cfg, src_dir, cache, files, file_ids);
-    Ok(merge_outcomes(got, incomplete_links))
-}
+    cfg.validate().context("Invalid configuration")?;
+    let client = cfg.client()?;
 
-/// The outcome of validating a set of links.
-#[derive(Debug, Default)]
-pub struct ValidationOutcome {
-    /// Valid links.
-    pub valid_links: Vec<Link>,
-    /// Links where validation failed.
-    pub invalid_links: Vec<InvalidLink>,
-    /// Links which have been ignored (e.g. due to
-    /// [`Config::follow_web_links`]).
-    pub ignored: Vec<Link>,
-    /// Links which we don't know how to handle.
-    pub unknown_category: Vec<Link>,
-    /// Potentially incomplete links.
-    pub incomplete_links: Vec<IncompleteLink>,
+    validate_with_fetcher(
+        links,
+        cfg,
+        Arc::new(client),
+        &CancellationToken::new(),
+        src_dir,
+        cache,
+        files,
+        file_ids,
+        incomplete_links,
+    )
 }
 
-impl ValidationOutcome {
-    /// Generate a list of [`Diagnostic`] messages from this
-    /// [`ValidationOutcome`].
-    pub fn generate_diagnostics(
-        &self,
-        files: &Files<String>,
-        warning_policy: WarningPolicy,
-    ) -> Vec<Diagnostic<FileId>> {
-        let mut diags = Vec::new();
-
-        self.add_invalid_link_diagnostics(&mut diags);
-        self.add_incomplete_link_diagnostics(warning_policy, &mut diags);
-        self.warn_on_absolute_links(warning_policy, &mut diags, files);
-
-        diags
-    }
-
-    fn add_incomplete_link_diagnostics(
-        &self,
-        warning_policy: WarningPolicy,
-        diags: &mut Vec<Diagnostic<FileId>>,
-    ) {
-        let severity = match warning_policy {
-            WarningPolicy::Error => Severity::Error,
-            WarningPolicy::Warn => Severity::Warning,
-            WarningPolicy::Ignore => return,
-        };
+/// The same as [`validate()`], except web requests are sent using the
+/// provided [`LinkFetcher`].
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn validate_with_fetcher(
+    links: &[Link],
+    cfg: &Config,
+    fetcher: Arc<dyn LinkFetcher>,
+    cancellation: &CancellationToken,
+    src_dir: &Path,
+    cache: &mut Cache,
+    files: &Files<String>,
+    file_ids: &[FileId],
+    incomplete_links: Vec<IncompleteLink>,
+) -> Result<ValidationOutcome, Error> {
+    // figure out which links will be skipped because of the cache before it
+    // gets updated
+    let cached: Vec<_> = links
+        .iter()
+        .filter(|link| is_cached(link, cfg, cache))
+        .cloned()
+        .collect();
+    let assumed_reachable: Vec<_> = links
+        .iter()
+        .filter(|link| is_assumed_reachable(link, cfg))
+        .cloned()
+        .collect();
 
-        for incomplete in &self.incomplete_links {
-            let IncompleteLink {
+    let (got, redirects, timings) = lc_validate(
+        links,
+        cfg,
+        fetcher,
+        cancellation,
+        src_dir,
+        cache,
+        files,
+        file_ids,
+    );
+    let mut outcome = merge_outcomes(got, incomplete_links);
+    // links can still be skipped after that (e.g. because of max-links or
+    // the deadline), so only keep the ones which actually ended up valid
+    let valid: HashSet<_> = outcome
+        .valid_links
+        .iter()
+        .map(|link| (link.file, link.span, link.href.clone()))
+        .collect();
+    let was_valid = |link: &Link| {
+        valid.contains(&(link.file, link.span, link.href.clone()))
+    };
+    outcome.cached = cached.into_iter().filter(|l| was_valid(l)).collect();
+    outcome.assumed_reachable = assumed_reachable
+        .into_iter()
+        .filter(|l| was_valid(l))
+        .collect();
+    outcome.redirected_links = outcome
+        .valid_links
+        .iter()
+        .filter_map(|link| {
+            let url = as_web_link(link)?;
+            let destination = redirects.get(&url)?;
+            Some(RedirectedLink {
+                link: link.clone(),
+                destination: destination.clone(),
+            })
+        })
+        .collect();
+    outcome.timings = outcome
+        .valid_links
+        .iter()
+        .chain(outcome.invalid_links.iter().map(|invalid| &invalid.link))
+        .filter_map(|link| {
+            let elapsed = timings.get(&(link.file, link.span))?;
+            Some(LinkTiming {
+                link: link.clone(),
+                elapsed: *elapsed,
+            })
+        })
+        .collect();
+    if let Some(threshold) = cfg.slow_threshold_ms {
+        outcome.slow_links =
+            find_slow_links(&outcome, Duration::from_millis(threshold));
+    }
+    outcome.case_mismatches = find_case_mismatches(&outcome, src_dir, files);
+    if cfg.lint_mismatched_urls {
+        let checked: HashSet<_> =
+            links.iter().map(|link| (link.file, link.span)).collect();
+        outcome.mismatched_urls = file_ids
+            .iter()
+            .flat_map(|&id| find_mismatched_urls(id, files.source(id)))
+            .filter(|m| checked.contains(&(m.link.file, m.link.span)))
+            .collect();
+    }
+    if cfg.require_https {
+        outcome.insecure_links = links
+            .iter()
+            .filter(|link| !cfg.should_skip(&link.href))
+            .filter(|link| {
+                matches!(as_web_link(link), Some(url) if url.scheme() == "http")
+            })
+            .cloned()
+            .collect();
+    }
+    if cfg.report_duplicate_headings {
+        outcome.duplicate_headings =
+            find_linked_duplicate_headings(links, files, file_ids);
+    }
+
+    Ok(outcome)
+}
+
+/// Find the valid web links which took longer than `threshold` to check,
+/// ignoring any which were valid because of the cache.
+fn find_slow_links(
+    outcome: &ValidationOutcome,
+    threshold: Duration,
+) -> Vec<LinkTiming> {
+    let valid: HashSet<_> = outcome
+        .valid_links
+        .iter()
+        .map(|link| (link.file, link.span))
+        .collect();
+    let cached: HashSet<_> = outcome
+        .cached
+        .iter()
+        .map(|link| (link.file, link.span))
+        .collect();
+
+    outcome
+        .timings
+        .iter()
+        .filter(|timing| timing.elapsed > threshold)
+        .filter(|timing| {
+            let key = (timing.link.file, timing.link.span);
+            valid.contains(&key) && !cached.contains(&key)
+        })
+        .filter(|timing| as_web_link(&timing.link).is_some())
+        .cloned()
+        .collect()
+}
+
+/// Find duplicate headings which are the target of a link. Duplicates which
+/// nothing links to don't cause any problems, and because `links` only
+/// contains links from the chapters being checked, other chapters won't
+/// generate warnings unless a checked chapter links to them.
+fn find_linked_duplicate_headings(
+    links: &[Link],
+    files: &Files<String>,
+    file_ids: &[FileId],
+) -> Vec<DuplicateHeading> {
+    let chapters: HashSet<&Path> = file_ids
+        .iter()
+        .map(|&id| Path::new(files.name(id)))
+        .collect();
+    let mut duplicates: Vec<_> = file_ids
+        .iter()
+        .flat_map(|&id| find_duplicate_headings(id, files.source(id)))
+        .collect();
+
+    for link in links {
+        let fragment = match split_href(&link.href) {
+            _ if as_web_link(link).is_some() => continue,
+            (_, Some(fragment)) => {
+                percent_encoding::percent_decode_str(fragment)
+                    .decode_utf8_lossy()
+                    .into_owned()
+            },
+            (_, None) => continue,
+        };
+        let target = if link.href.starts_with('#') {
+            Some(PathBuf::from(files.name(link.file)))
+        } else {
+            rendered_path(link, files)
+                .and_then(|path| find_chapter(&path, &chapters))
+        };
+
+        for duplicate in &mut duplicates {
+            let same_chapter = target.as_deref()
+                == Some(Path::new(files.name(duplicate.file)));
+            if same_chapter && duplicate.original_id == fragment {
+                duplicate.links.push(link.clone());
+            }
+        }
+    }
+
+    duplicates.retain(|duplicate| !duplicate.links.is_empty());
+    duplicates
+}
+
+/// Look for links to files in the book which only work because the file
+/// system is case-insensitive, or which would work if it was.
+fn find_case_mismatches(
+    outcome: &ValidationOutcome,
+    src_dir: &Path,
+    files: &Files<String>,
+) -> Vec<CaseMismatch> {
+    let not_found = outcome
+        .invalid_links
+        .iter()
+        .filter(|invalid| invalid.reason.file_not_found())
+        .map(|invalid| &invalid.link);
+
+    outcome
+        .valid_links
+        .iter()
+        .chain(not_found)
+        .filter(|link| {
+            !link.href.starts_with('#')
+                && !is_mailto(&link.href)
+                && as_web_link(link).is_none()
+        })
+        .filter_map(|link| find_case_mismatch(link, src_dir, files))
+        .collect()
+}
+
+/// Will this web link be considered valid without sending a request, because
+/// the cache says it was recently valid?
+fn is_cached(link: &Link, cfg: &Config, cache: &Cache) -> bool {
+    let timeout = Duration::from_secs(cfg.cache_timeout);
+
+    match as_web_link(link) {
+        Some(url) if crate::web::is_http(&url) => {
+            cfg.checks_web_links()
+                && !cfg.should_skip(&link.href)
+                && !cfg.assumes_reachable(&url)
+                && cache.url_is_still_valid(&url, timeout)
+        },
+        _ => false,
+    }
+}
+
+/// Will this web link be considered valid without sending a request, because
+/// of [`Config::assume_reachable_hosts`]?
+fn is_assumed_reachable(link: &Link, cfg: &Config) -> bool {
+    match as_web_link(link) {
+        Some(url) if crate::web::is_http(&url) => {
+            cfg.checks_web_links()
+                && !cfg.should_skip(&link.href)
+                && cfg.assumes_reachable(&url)
+        },
+        _ => false,
+    }
+}
+
+/// The outcome of validating a set of links.
+#[derive(Debug, Default)]
+pub struct ValidationOutcome {
+    /// Valid links.
+    pub valid_links: Vec<Link>,
+    /// Links where validation failed.
+    pub invalid_links: Vec<InvalidLink>,
+    /// Links which have been ignored (e.g. due to
+    /// [`Config::follow_web_links`]).
+    pub ignored: Vec<Link>,
+    /// Links which we don't know how to handle.
+    pub unknown_category: Vec<Link>,
+    /// Potentially incomplete links.
+    pub incomplete_links: Vec<IncompleteLink>,
+    /// Valid web links which weren't checked because the cache says they were
+    /// valid recently. These are also included in
+    /// [`ValidationOutcome::valid_links`].
+    pub cached: Vec<Link>,
+    /// Web links which weren't checked because their host is in
+    /// [`Config::assume_reachable_hosts`]. These are also included in
+    /// [`ValidationOutcome::valid_links`].
+    pub assumed_reachable: Vec<Link>,
+    /// Chapters in `SUMMARY.md` which don't exist on disk (see
+    /// [`Config::check_summary`]).
+    pub missing_chapters: Vec<MissingChapter>,
+    /// Headings which have the same text as an earlier heading in the same
+    /// chapter and are the target of a link (see
+    /// [`Config::report_duplicate_headings`]).
+    pub duplicate_headings: Vec<DuplicateHeading>,
+    /// Links to files in the book which only match the file's name when case
+    /// is ignored, so they will break on case-sensitive file systems.
+    pub case_mismatches: Vec<CaseMismatch>,
+    /// Valid web links which redirected somewhere else (see
+    /// [`Config::warn_on_redirect`]).
+    pub redirected_links: Vec<RedirectedLink>,
+    /// How long it took to check each valid or broken link. Links which
+    /// share a URL with another link were only checked once, so they all get
+    /// the same time.
+    pub timings: Vec<LinkTiming>,
+    /// Valid web links which took longer than [`Config::slow_threshold_ms`]
+    /// to check.
+    pub slow_links: Vec<LinkTiming>,
+    /// Links whose text is a web address which doesn't match where they go
+    /// (see [`Config::lint_mismatched_urls`]).
+    pub mismatched_urls: Vec<MismatchedUrl>,
+    /// Web links which use `http://` instead of `https://` (see
+    /// [`Config::require_https`]).
+    pub insecure_links: Vec<Link>,
+    /// Chapters which no other chapter links to (see
+    /// [`Config::report_orphans`]).
+    pub orphaned_chapters: Vec<PathBuf>,
+}
+
+/// A web link which redirects to another URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectedLink {
+    /// The link, as written.
+    pub link: Link,
+    /// Where the link ended up.
+    pub destination: Url,
+}
+
+/// How long it took to check a link.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkTiming {
+    /// The link, as written.
+    pub link: Link,
+    /// The time spent checking the link.
+    pub elapsed: Duration,
+}
+
+impl ValidationOutcome {
+    /// Generate a list of [`Diagnostic`] messages from this
+    /// [`ValidationOutcome`].
+    pub fn generate_diagnostics(
+        &self,
+        files: &Files<String>,
+        warning_policy: WarningPolicy,
+    ) -> Vec<Diagnostic<FileId>> {
+        self.generate_diagnostics_with_policies(
+            files,
+            warning_policy,
+            WarningPolicy::Error,
+        )
+    }
+
+    /// Generate a list of [`Diagnostic`] messages from this
+    /// [`ValidationOutcome`], using the `broken_link_policy` to decide how
+    /// broken links are reported (see [`Config::broken_link_policy`]).
+    pub fn generate_diagnostics_with_policies(
+        &self,
+        files: &Files<String>,
+        warning_policy: WarningPolicy,
+        broken_link_policy: WarningPolicy,
+    ) -> Vec<Diagnostic<FileId>> {
+        let mut diags = Vec::new();
+
+        self.add_invalid_link_diagnostics(broken_link_policy, &mut diags);
+        self.add_incomplete_link_diagnostics(warning_policy, &mut diags);
+        self.add_missing_chapter_diagnostics(
+            warning_policy,
+            broken_link_policy,
+            &mut diags,
+        );
+        self.add_duplicate_heading_diagnostics(
+            warning_policy,
+            &mut diags,
+            files,
+        );
+        self.add_case_mismatch_diagnostics(warning_policy, &mut diags);
+        self.add_redirect_diagnostics(warning_policy, &mut diags);
+        self.add_slow_link_diagnostics(warning_policy, &mut diags);
+        self.add_mismatched_url_diagnostics(warning_policy, &mut diags);
+        self.add_insecure_link_diagnostics(warning_policy, &mut diags);
+        self.add_orphaned_chapter_diagnostics(warning_policy, &mut diags);
+        self.warn_on_absolute_links(warning_policy, &mut diags, files);
+
+        diags
+    }
+
+    fn add_redirect_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for redirect in &self.redirected_links {
+            let RedirectedLink {
+                ref link,
+                ref destination,
+            } = redirect;
+
+            let msg =
+                format!("\"{}\" redirects to \"{}\"", link.href, destination);
+            let label = Label::primary(link.file, link.span)
+                .with_message("this link redirects");
+            let note = String::from(
+                "hint: link to the final URL so the link doesn't break if the redirect is removed",
+            );
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(vec![label])
+                .with_notes(vec![note]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_slow_link_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for LinkTiming { link, elapsed } in &self.slow_links {
+            let msg = format!(
+                "\"{}\" took {}ms to respond",
+                link.href,
+                elapsed.as_millis()
+            );
+            let label = Label::primary(link.file, link.span)
+                .with_message("this link is slow");
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(vec![label]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_mismatched_url_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for MismatchedUrl { text, link } in &self.mismatched_urls {
+            let msg = format!(
+                "The link's text is \"{}\", but it goes to \"{}\"",
+                text, link.href
+            );
+            let label = Label::primary(link.file, link.span)
+                .with_message("the text doesn't match the destination");
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(vec![label]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_insecure_link_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for link in &self.insecure_links {
+            let msg = format!(
+                "\"{}\" should use https (e.g. \"https{}\")",
+                link.href,
+                &link.href["http".len()..]
+            );
+            let label = Label::primary(link.file, link.span)
+                .with_message("this link isn't secure");
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(vec![label]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_orphaned_chapter_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for chapter in &self.orphaned_chapters {
+            let msg = format!(
+                "Nothing links to \"{}\", so it can only be found using the table of contents",
+                chapter.display()
+            );
+            diags.push(Diagnostic::new(severity).with_message(msg));
+        }
+    }
+
+    fn add_case_mismatch_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for mismatch in &self.case_mismatches {
+            let CaseMismatch {
+                ref link,
+                ref actual,
+            } = mismatch;
+
+            let msg = format!(
+                "The case of \"{}\" doesn't match the file on disk",
+                link.href
+            );
+            let label = Label::primary(link.file, link.span)
+                .with_message(format!("this should be \"{}\"", actual));
+            let note = String::from(
+                "This works on case-insensitive file systems (e.g. macOS and Windows), but breaks on case-sensitive ones like most web servers use",
+            );
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(vec![label])
+                .with_notes(vec![note]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_duplicate_heading_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+        files: &Files<String>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for duplicate in &self.duplicate_headings {
+            let msg = format!(
+                "\"{}\" has more than one \"{}\" heading",
+                files.name(duplicate.file).to_string_lossy(),
+                duplicate.text
+            );
+            let label =
+                Label::primary(duplicate.file, duplicate.span).with_message(
+                    format!("this heading's id is \"{}\"", duplicate.id),
+                );
+            let links = duplicate.links.iter().map(|link| {
+                Label::secondary(link.file, link.span)
+                    .with_message("this link goes to the first one")
+            });
+            let note = format!(
+                "Links to \"#{}\" will always go to the first \"{}\" heading",
+                duplicate.original_id, duplicate.text
+            );
+
+            let diag = Diagnostic::new(severity)
+                .with_message(msg)
+                .with_labels(std::iter::once(label).chain(links).collect())
+                .with_notes(vec![note]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_incomplete_link_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
+        for incomplete in &self.incomplete_links {
+            let IncompleteLink {
                 ref reference,
                 file,
                 span,
@@ -275,14 +1479,45 @@ impl ValidationOutcome {
         }
     }
 
+    /// Missing chapters are treated like broken links, while draft chapters
+    /// are only warnings because they are often intentional.
+    fn add_missing_chapter_diagnostics(
+        &self,
+        warning_policy: WarningPolicy,
+        broken_link_policy: WarningPolicy,
+        diags: &mut Vec<Diagnostic<FileId>>,
+    ) {
+        for missing in &self.missing_chapters {
+            let policy = if missing.is_draft() {
+                warning_policy
+            } else {
+                broken_link_policy
+            };
+            let severity = match policy.severity() {
+                Some(severity) => severity,
+                None => continue,
+            };
+
+            diags.push(
+                Diagnostic::new(severity).with_message(missing.to_string()),
+            );
+        }
+    }
+
     fn add_invalid_link_diagnostics(
         &self,
+        broken_link_policy: WarningPolicy,
         diags: &mut Vec<Diagnostic<FileId>>,
     ) {
+        let severity = match broken_link_policy.severity() {
+            Some(severity) => severity,
+            None => return,
+        };
+
         for broken_link in &self.invalid_links {
             let link = &broken_link.link;
-            let msg = most_specific_error_message(&broken_link);
-            let diag = Diagnostic::error()
+            let msg = most_specific_error_message(broken_link);
+            let diag = Diagnostic::new(severity)
                 .with_message(msg.clone())
                 .with_labels(vec![
                     Label::primary(link.file, link.span).with_message(msg)
@@ -300,7 +1535,7 @@ impl ValidationOutcome {
         diags: &mut Vec<Diagnostic<FileId>>,
         files: &Files<String>,
     ) {
-        const WARNING_MESSAGE: &'static str = r#"When viewing a document directly from the file system and click on an
+        const WARNING_MESSAGE: &str = r#"When viewing a document directly from the file system and click on an
 absolute link (e.g. `/index.md`), the browser will try to navigate to
 `/index.md` on the current file system (i.e. the `index.md` file inside
 `/` or `C:\`) instead of the `index.md` file at book's base directory as
@@ -318,16 +1553,18 @@ To ignore this warning, you can edit `book.toml` and set the warning policy to
 
 For more details, see https://github.com/Michael-F-Bryan/mdbook-linkcheck/issues/33
 "#;
-        let severity = match warning_policy {
-            WarningPolicy::Error => Severity::Error,
-            WarningPolicy::Warn => Severity::Warning,
-            WarningPolicy::Ignore => return,
+        let severity = match warning_policy.severity() {
+            Some(severity) => severity,
+            None => return,
         };
 
         let absolute_links = self
             .valid_links
             .iter()
-            .filter(|link| link.href.starts_with("/"));
+            // "//example.com/page" is a protocol-relative web link
+            .filter(|link| {
+                link.href.starts_with('/') && !link.href.starts_with("//")
+            });
 
         let mut reasoning_emitted = false;
 
@@ -392,8 +1629,8 @@ where
             },
             (None, _) => comps.push(Component::ParentDir),
             (Some(a), Some(b)) if comps.is_empty() && a == b => (),
-            (Some(a), Some(b)) if b == Component::CurDir => comps.push(a),
-            (Some(_), Some(b)) if b == Component::ParentDir => return None,
+            (Some(a), Some(Component::CurDir)) => comps.push(a),
+            (Some(_), Some(Component::ParentDir)) => return None,
             (Some(a), Some(_)) => {
                 comps.push(Component::ParentDir);
                 for _ in itb {
@@ -416,42 +1653,68 @@ where
     Some(path.display().to_string().replace('\\', "/"))
 }
 
-fn most_specific_error_message(link: &InvalidLink) -> String {
-    if link.reason.file_not_found() {
-        return format!("File not found: {}", link.link.href);
-    }
+pub(crate) fn most_specific_error_message(link: &InvalidLink) -> String {
+    describe_reason(&link.link.href, &link.reason)
+}
 
-    match link.reason {
-        Reason::Io(ref io) => io.to_string(),
-        Reason::Web(ref web) if web.is_status() => {
-            let status = web.status().expect(
-                "Response::error_for_status() always contains a status code",
-            );
-            let url = web
-                .url()
-                .expect("Response::error_for_status() always contains a URL");
-
-            match status.canonical_reason() {
-                Some(reason) => format!(
-                    "Server returned {} {} for {}",
-                    status.as_u16(),
-                    reason,
-                    url
-                ),
-                None => {
-                    format!("Server returned {} for {}", status.as_u16(), url)
-                },
-            }
-        },
-        Reason::Web(ref web) => web.to_string(),
-        // fall back to the Reason's Display impl
-        _ => link.reason.to_string(),
-    }
+/// A human-friendly explanation of why the link to `href` is broken.
+pub(crate) fn describe_reason(href: &str, reason: &Reason) -> String {
+    LinkError::from_reason(href, reason).to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        test_utils::{self, MockServer, Response},
+        HashedRegex, TlsVersion, WebLinkMode,
+    };
+    use codespan::Span;
+    use codespan_reporting::diagnostic::Severity;
+    use linkcheck::validation::CacheEntry;
+    use std::{
+        thread,
+        time::{Instant, SystemTime},
+    };
+
+    #[test]
+    fn fragments_are_percent_decoded_before_looking_up_anchors() {
+        let mut anchors = HashMap::new();
+        anchors.insert(
+            PathBuf::from("chapter_1.md"),
+            vec![String::from("caf\u{e9}"), String::from("a b")]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        );
+        let path = Path::new("chapter_1.md");
+
+        assert!(ensure_anchor_exists(&anchors, path, "caf%C3%A9").is_ok());
+        assert!(ensure_anchor_exists(&anchors, path, "a%20b").is_ok());
+        assert!(ensure_anchor_exists(&anchors, path, "caf%C3").is_err());
+    }
+
+    #[test]
+    fn protocol_relative_links_arent_absolute_paths() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let outcome = ValidationOutcome {
+            valid_links: vec![
+                Link::new("//example.com/page", Span::new(0, 0), file),
+                Link::new("/chapter_1.md", Span::new(0, 0), file),
+            ],
+            ..Default::default()
+        };
+
+        let diags = outcome.generate_diagnostics_with_policies(
+            &files,
+            WarningPolicy::Error,
+            WarningPolicy::Error,
+        );
+
+        assert_eq!(diags.len(), 1);
+        let suggestion = "Suggestion: change the link to \"chapter_1.md\"";
+        assert!(diags[0].notes.iter().any(|note| note == suggestion));
+    }
 
     #[test]
     fn check_some_simple_relative_paths() {
@@ -466,4 +1729,1225 @@ mod tests {
             assert_eq!(got, should_be);
         }
     }
+
+    #[test]
+    fn strip_the_site_root_from_root_relative_links() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = vec![
+            "/my-book/nested/page.md",
+            "/my-book#intro",
+            "/my-bookshelf/page.md",
+            "/other/page.md",
+            "./relative.md",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, href)| Link::new(href, Span::new(i as u32, i as u32), file))
+        .collect();
+
+        for site_root in &["/my-book/", "my-book", "/my-book"] {
+            let (in_book, elsewhere, original_hrefs) =
+                strip_site_root(&links, Some(site_root));
+
+            let in_book: Vec<_> =
+                in_book.iter().map(|link| link.href.as_str()).collect();
+            assert_eq!(
+                in_book,
+                &["/nested/page.md", "/#intro", "./relative.md"]
+            );
+            let elsewhere: Vec<_> =
+                elsewhere.iter().map(|link| link.href.as_str()).collect();
+            assert_eq!(elsewhere, &["/my-bookshelf/page.md", "/other/page.md"]);
+            assert_eq!(original_hrefs.len(), 2);
+        }
+    }
+
+    #[test]
+    fn check_web_links_concurrently() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let server = MockServer::start({
+            let peak = Arc::clone(&peak);
+            move |_| {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(current, Ordering::SeqCst);
+
+                // hold on to each request until as many as we allow are in
+                // flight, which only happens if they're sent concurrently
+                let give_up = Instant::now() + Duration::from_secs(5);
+                while peak.load(Ordering::SeqCst) < 4
+                    && Instant::now() < give_up
+                {
+                    thread::sleep(Duration::from_millis(10));
+                }
+
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Response::new(200)
+            }
+        });
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = (0..6)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 4,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.valid.len(), 6);
+        assert_eq!(peak.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn rate_limit_concurrent_requests_to_the_same_host() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = (0..3)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 4,
+            requests_per_host_per_second: Some(5.0),
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let start = Instant::now();
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.valid.len(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn wait_between_every_request_with_a_global_delay() {
+        let arrived = Arc::new(Mutex::new(Vec::new()));
+        let server = {
+            let arrived = Arc::clone(&arrived);
+            MockServer::start(move |_| {
+                arrived.lock().unwrap().push(Instant::now());
+                Response::new(200)
+            })
+        };
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [
+            Link::new(server.url("/first").to_string(), Span::new(0, 0), file),
+            Link::new(server.url("/second").to_string(), Span::new(0, 0), file),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 4,
+            global_delay_ms: 300,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.valid.len(), 2);
+        let mut arrived = arrived.lock().unwrap().clone();
+        assert_eq!(arrived.len(), 2);
+        arrived.sort();
+        // the mock server may take a moment to notice the first request, so
+        // allow a bit of slack
+        let gap = arrived[1].duration_since(arrived[0]);
+        assert!(gap >= Duration::from_millis(200), "{:?}", gap);
+    }
+
+    #[test]
+    fn keep_track_of_links_which_were_cached() {
+        let server = MockServer::start(|_| Response::new(200));
+        let cached = server.url("/cached");
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [
+            Link::new(cached.to_string(), Span::new(0, 0), file),
+            Link::new(server.url("/fresh").to_string(), Span::new(0, 0), file),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+        let mut cache = Cache::default();
+        cache.insert(cached, CacheEntry::new(SystemTime::now(), true));
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &mut cache,
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(got.valid_links.len(), 2);
+        assert_eq!(got.cached, vec![links[0].clone()]);
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["/fresh"]);
+    }
+
+    #[test]
+    fn links_to_hosts_assumed_to_be_reachable_arent_fetched() {
+        let server = MockServer::start(|_| Response::new(404));
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [Link::new(
+            server.url("/internal/wiki").to_string(),
+            Span::new(0, 0),
+            file,
+        )];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            assume_reachable_hosts: vec![String::from("127.0.0.1")],
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert!(server.requests().is_empty());
+        assert_eq!(got.valid_links, links);
+        assert_eq!(got.assumed_reachable, links);
+        assert!(got.cached.is_empty());
+        let report = crate::CheckReport::new(&got, &files);
+        assert_eq!(report.checked, 0);
+        assert_eq!(
+            report.to_string(),
+            "Checked 1 link (1 ok, 0 skipped, 0 broken, 1 assumed ok)"
+        );
+    }
+
+    #[test]
+    fn every_web_link_is_checked_with_the_same_client() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        // a server which keeps connections alive and counts how many were
+        // opened, so a new client per link would show up as extra connections
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        if line == "\r\n" {
+                            let _ = (&stream).write_all(
+                                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+                            );
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = (0..10)
+            .map(|i| {
+                let href = format!("{}page-{}", base, i);
+                Link::new(href, Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 1,
+            requests_per_host_per_second: Some(20.0),
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(got.valid_links.len(), links.len());
+        // the requests are spaced out so each connection has time to go back
+        // to the pool before the next request, which means one client should
+        // only ever need one connection (with some slack for a busy machine)
+        let opened = connections.load(Ordering::SeqCst);
+        assert!(opened <= 2, "{} connections were opened", opened);
+    }
+
+    #[test]
+    fn record_how_long_each_link_took_to_check() {
+        let server = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(100));
+            Response::new(200)
+        });
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [
+            Link::new(server.url("/slow").to_string(), Span::new(0, 1), file),
+            Link::new("./Cargo.toml", Span::new(2, 3), file),
+            Link::new("https://forbidden.com/", Span::new(4, 5), file),
+            Link::new("./LICENSE", Span::new(6, 7), file),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            exclude: vec![HashedRegex::new(r"forbidden\.com").unwrap()],
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(got.timings.len(), 3);
+        assert_eq!(got.timings[0].link, links[0]);
+        assert!(got.timings[0].elapsed >= Duration::from_millis(100));
+        assert_eq!(got.timings[1].link, links[1]);
+        assert_eq!(got.timings[2].link, links[3]);
+        assert_eq!(got.ignored, vec![links[2].clone()]);
+    }
+
+    #[test]
+    fn stop_checking_web_links_once_the_budget_is_used_up() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", String::new());
+        let mut links: Vec<_> = (0..4)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        // links to the same URL only count against the budget once
+        links.insert(1, links[0].clone());
+        links.push(Link::new("./chapter_1.md", Span::new(0, 0), file));
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            max_links: Some(2),
+            ..Default::default()
+        };
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+
+        let got = validate(
+            &links,
+            &cfg,
+            &src_dir,
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        let mut paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        paths.sort();
+        assert_eq!(paths, &["/0", "/1"]);
+        // the intra-book link is still checked
+        assert!(got
+            .valid_links
+            .iter()
+            .any(|link| link.href == "./chapter_1.md"));
+        let report = crate::CheckReport::new(&got, &files);
+        assert_eq!(report.skipped, 2);
+    }
+
+    #[test]
+    fn cached_links_skipped_by_max_links_arent_counted_twice() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", String::new());
+        let links: Vec<_> = (0..3)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            max_links: Some(1),
+            ..Default::default()
+        };
+        let mut cache = Cache::default();
+        for link in &links[1..] {
+            let url: Url = link.href.parse().unwrap();
+            cache.insert(url, CacheEntry::new(SystemTime::now(), true));
+        }
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &mut cache,
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        // only the first link fits in the budget, so the cached ones are
+        // skipped rather than reported as coming from the cache
+        assert_eq!(got.valid_links, &links[..1]);
+        assert!(got.cached.is_empty());
+        let report = crate::CheckReport::new(&got, &files);
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.cached, 0);
+        assert_eq!(report.skipped, 2);
+    }
+
+    #[test]
+    fn give_up_on_web_links_once_the_deadline_passes() {
+        let server = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(1500));
+            Response::new(200)
+        });
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = (0..3)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 1,
+            total_timeout: Some(1),
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        // the first request was cut short by the deadline and the others
+        // were never sent
+        assert!(server.requests().len() <= 1);
+        assert_eq!(got.ignored.len(), 3);
+        assert!(got.valid.is_empty());
+        assert!(got.invalid.is_empty());
+    }
+
+    #[test]
+    fn stop_checking_web_links_once_cancelled() {
+        let cancellation = CancellationToken::new();
+        let server = MockServer::start({
+            let cancellation = cancellation.clone();
+            move |_| {
+                cancellation.cancel();
+                Response::new(200)
+            }
+        });
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links: Vec<_> = (0..3)
+            .map(|i| {
+                let url = server.url(&format!("/{}", i));
+                Link::new(url.to_string(), Span::new(0, 0), file)
+            })
+            .collect();
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            concurrency: 1,
+            ..Default::default()
+        };
+
+        let got = validate_with_fetcher(
+            &links,
+            &cfg,
+            Arc::new(cfg.client().unwrap()),
+            &cancellation,
+            Path::new("."),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(got.valid_links, &links[..1]);
+        assert_eq!(got.ignored, &links[1..]);
+        let report = crate::CheckReport::new(&got, &files);
+        assert_eq!(report.checked, 1);
+        assert_eq!(report.skipped, 2);
+    }
+
+    #[test]
+    fn options_the_client_cant_use_are_errors_instead_of_panics() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links =
+            vec![Link::new("https://example.com/", Span::new(0, 0), file)];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            min_tls_version: Some(TlsVersion::V1_3),
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new("."),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", got).contains("`min-tls-version`"),
+            "{:#}",
+            got
+        );
+    }
+
+    #[test]
+    fn only_check_each_web_link_once() {
+        let server = MockServer::start(|_| Response::new(404));
+        let url = server.url("/missing");
+        let mut files = Files::new();
+        let first = files.add("first.md", String::new());
+        let second = files.add("second.md", String::new());
+        let links = [
+            Link::new(url.to_string(), Span::new(0, 0), first),
+            Link::new(url.to_string(), Span::new(0, 0), second),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(server.requests().len(), 1);
+        let mut broken: Vec<_> = got
+            .invalid
+            .iter()
+            .map(|invalid| invalid.link.file)
+            .collect();
+        broken.sort();
+        assert_eq!(broken, vec![first, second]);
+        let messages: HashSet<_> = got
+            .invalid
+            .iter()
+            .map(most_specific_error_message)
+            .collect();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn excluded_web_links_are_never_requested() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let link = |path: &str| {
+            Link::new(server.url(path).to_string(), Span::new(0, 0), file)
+        };
+        let links = [link("/skip-me"), link("/check-me")];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            exclude: vec![HashedRegex::new("skip-me").unwrap()],
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.ignored, vec![links[0].clone()]);
+        assert_eq!(got.valid, vec![links[1].clone()]);
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["/check-me"]);
+    }
+
+    #[test]
+    fn web_links_are_never_requested_when_turned_off() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [Link::new(
+            server.url("/").to_string(),
+            Span::new(0, 0),
+            file,
+        )];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Off,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.ignored, links.to_vec());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn web_links_are_never_requested_when_offline() {
+        let server = MockServer::start(|_| Response::new(200));
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [Link::new(
+            server.url("/").to_string(),
+            Span::new(0, 0),
+            file,
+        )];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            offline: true,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.ignored, links.to_vec());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn web_links_with_spaces_are_percent_encoded() {
+        let server = MockServer::start(|_| Response::new(200));
+        let href = format!("{}a b", server.url("/"));
+        let mut files = Files::new();
+        let file = files.add("index.md", format!("[x](<{}>)", href));
+        let links = [Link::new(href, Span::new(0, 1), file)];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.valid, links);
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["/a%20b"]);
+    }
+
+    #[test]
+    fn protocol_relative_links_are_web_links() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let link = Link::new("//example.com/foo", Span::new(0, 0), file);
+
+        let got = as_web_link(&link).unwrap();
+
+        assert_eq!(got.as_str(), "https://example.com/foo");
+
+        // without follow-web-links they're skipped instead of being looked up
+        // on disk
+        let cfg = Config::default();
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got = runtime.block_on(check_web_links(vec![&link], &ctx));
+
+        assert_eq!(got.ignored, vec![link.clone()]);
+        assert!(got.invalid.is_empty());
+    }
+
+    #[test]
+    fn skip_links_which_cant_be_checked_on_the_web() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [
+            Link::new("tel:+1234", Span::new(0, 0), file),
+            Link::new("data:text/plain;base64,SGVsbG8=", Span::new(0, 0), file),
+            Link::new("made-up:whatever", Span::new(0, 0), file),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.ignored, links);
+        assert!(got.invalid.is_empty());
+    }
+
+    #[test]
+    fn paths_which_look_like_they_have_a_scheme_arent_web_links() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", String::new());
+        let links = [
+            Link::new(
+                "C:\\Users\\me\\book\\chapter_1.md",
+                Span::new(0, 0),
+                file,
+            ),
+            Link::new("c:/Users/me/notes.md", Span::new(1, 1), file),
+            Link::new("D:", Span::new(2, 2), file),
+            Link::new(
+                "git@github.com:rust-lang/mdBook.git",
+                Span::new(3, 3),
+                file,
+            ),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            &src_dir,
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        let broken: Vec<_> = got
+            .invalid_links
+            .iter()
+            .map(|invalid| {
+                (&invalid.link, most_specific_error_message(invalid))
+            })
+            .collect();
+        let absolute = |href: &str| {
+            format!("\"{}\" is a path on your computer, link to the chapter with a relative path instead", href)
+        };
+        assert_eq!(
+            broken,
+            vec![
+                (&links[0], absolute("C:\\Users\\me\\book\\chapter_1.md")),
+                (&links[1], absolute("c:/Users/me/notes.md")),
+                (&links[2], absolute("D:")),
+                (
+                    &links[3],
+                    String::from(
+                        "File not found: git@github.com:rust-lang/mdBook.git"
+                    )
+                ),
+            ]
+        );
+        assert!(got.ignored.is_empty());
+    }
+
+    #[test]
+    fn flag_schemes_which_arent_allowed() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::new());
+        let links = [
+            Link::new("tel:+1234", Span::new(0, 0), file),
+            Link::new("data:text/plain;base64,SGVsbG8=", Span::new(0, 0), file),
+            Link::new("made-up:whatever", Span::new(0, 0), file),
+        ];
+        let cfg = Config {
+            allowed_schemes: Some(vec![String::from("tel")]),
+            ..Default::default()
+        };
+        let ctx = test_utils::context(&cfg);
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let got =
+            runtime.block_on(check_web_links(links.iter().collect(), &ctx));
+
+        assert_eq!(got.ignored, vec![links[0].clone()]);
+        let messages: Vec<_> = got
+            .invalid
+            .iter()
+            .map(most_specific_error_message)
+            .collect();
+        assert_eq!(
+            messages,
+            &[
+                "Links using the \"data:\" scheme aren't allowed (see the \
+                 `allowed-schemes` option)",
+                "Links using the \"made-up:\" scheme aren't allowed (see the \
+                 `allowed-schemes` option)",
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_links_can_be_downgraded_to_warnings() {
+        let mut files = Files::new();
+        let file = files.add("index.md", String::from("[link](./missing.md)"));
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: Link::new("./missing.md", Span::new(0, 20), file),
+                reason: Reason::Io(std::io::ErrorKind::NotFound.into()),
+            }],
+            ..Default::default()
+        };
+        let severities = |policy| -> Vec<_> {
+            outcome
+                .generate_diagnostics_with_policies(
+                    &files,
+                    WarningPolicy::Warn,
+                    policy,
+                )
+                .into_iter()
+                .map(|diag| diag.severity)
+                .collect()
+        };
+
+        assert_eq!(severities(WarningPolicy::Error), &[Severity::Error]);
+        assert_eq!(severities(WarningPolicy::Warn), &[Severity::Warning]);
+        assert!(severities(WarningPolicy::Ignore).is_empty());
+    }
+
+    #[test]
+    fn draft_chapters_are_only_warnings() {
+        let files = Files::new();
+        let outcome = ValidationOutcome {
+            missing_chapters: vec![
+                MissingChapter {
+                    name: String::from("Missing"),
+                    path: Some(PathBuf::from("missing.md")),
+                },
+                MissingChapter {
+                    name: String::from("Coming Soon"),
+                    path: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let got: Vec<_> = outcome
+            .generate_diagnostics(&files, WarningPolicy::Warn)
+            .into_iter()
+            .map(|diag| diag.severity)
+            .collect();
+
+        assert_eq!(got, &[Severity::Error, Severity::Warning]);
+    }
+
+    #[test]
+    fn warn_about_duplicate_headings() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let file = files.add(
+            "chapter_1.md",
+            String::from(
+                "## Introduction\n\n## Introduction\n\n[intro](#introduction)\n",
+            ),
+        );
+        let other = files.add(
+            "nested/README.md",
+            String::from("[intro](../chapter_1.html#introduction)"),
+        );
+        let links = [
+            Link::new("#introduction", Span::new(35, 57), file),
+            Link::new(
+                "../chapter_1.html#introduction",
+                Span::new(0, 39),
+                other,
+            ),
+        ];
+        let cfg = Config {
+            report_duplicate_headings: true,
+            ..Default::default()
+        };
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            &src_dir,
+            &mut Cache::default(),
+            &files,
+            &[file, other],
+            Vec::new(),
+        )
+        .unwrap();
+        let diags = outcome.generate_diagnostics(&files, WarningPolicy::Warn);
+
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(
+            diags[0].message,
+            "\"chapter_1.md\" has more than one \"Introduction\" heading"
+        );
+        assert_eq!(diags[0].labels[0].range, 17..33);
+        assert_eq!(diags[0].labels[1].range, 35..57);
+        assert_eq!(diags[0].labels[2].file_id, other);
+    }
+
+    #[test]
+    fn duplicate_headings_nothing_links_to_are_fine() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let file = files.add(
+            "chapter_1.md",
+            String::from("## Introduction\n\n## Introduction\n"),
+        );
+        let other = files.add(
+            "nested/README.md",
+            String::from("[second one](../chapter_1.md#introduction-1)"),
+        );
+        let links = [Link::new(
+            "../chapter_1.md#introduction-1",
+            Span::new(0, 45),
+            other,
+        )];
+        let cfg = Config {
+            report_duplicate_headings: true,
+            ..Default::default()
+        };
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            &src_dir,
+            &mut Cache::default(),
+            &files,
+            &[file, other],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert!(outcome.duplicate_headings.is_empty());
+    }
+
+    #[test]
+    fn warn_about_links_with_the_wrong_case() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let file = files.add(
+            "nested/README.md",
+            String::from("[chapter 1](../Chapter_1.md)"),
+        );
+        let links = [Link::new("../Chapter_1.md", Span::new(0, 28), file)];
+        let cfg = Config::default();
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            &src_dir,
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            outcome.case_mismatches,
+            vec![CaseMismatch {
+                link: links[0].clone(),
+                actual: String::from("../chapter_1.md"),
+            }]
+        );
+        let messages: Vec<_> = outcome
+            .generate_diagnostics_with_policies(
+                &files,
+                WarningPolicy::Error,
+                WarningPolicy::Ignore,
+            )
+            .into_iter()
+            .map(|diag| (diag.severity, diag.message))
+            .collect();
+        assert_eq!(
+            messages,
+            &[(
+                Severity::Error,
+                String::from(
+                    "The case of \"../Chapter_1.md\" doesn't match the file on disk"
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn warn_about_links_which_redirect() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/old" => Response::new(301).with_header("Location", "/new"),
+            _ => Response::new(200),
+        });
+        let href = server.url("/old").to_string();
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", format!("[old]({})", href));
+        let links = [Link::new(href.clone(), Span::new(0, 7), file)];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            warn_on_redirect: true,
+            ..Default::default()
+        };
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            Path::new("."),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            outcome.redirected_links,
+            vec![RedirectedLink {
+                link: links[0].clone(),
+                destination: server.url("/new"),
+            }]
+        );
+        let messages: Vec<_> = outcome
+            .generate_diagnostics(&files, WarningPolicy::Warn)
+            .into_iter()
+            .map(|diag| diag.message)
+            .collect();
+        assert_eq!(
+            messages,
+            &[format!(
+                "\"{}\" redirects to \"{}\"",
+                href,
+                server.url("/new")
+            )]
+        );
+    }
+
+    #[test]
+    fn warn_about_links_which_are_slow_to_respond() {
+        let server = MockServer::start(|request| {
+            if request.path == "/slow" {
+                thread::sleep(Duration::from_millis(300));
+            }
+            Response::new(200)
+        });
+        let slow = server.url("/slow").to_string();
+        let fast = server.url("/fast").to_string();
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", String::new());
+        let links = [
+            Link::new(slow.clone(), Span::new(0, 1), file),
+            Link::new(fast, Span::new(2, 3), file),
+        ];
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            slow_threshold_ms: Some(200),
+            ..Default::default()
+        };
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            Path::new("."),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(outcome.slow_links.len(), 1);
+        assert_eq!(outcome.slow_links[0].link, links[0]);
+        let diags = outcome.generate_diagnostics(&files, WarningPolicy::Warn);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        let elapsed = outcome.slow_links[0].elapsed.as_millis();
+        assert_eq!(
+            diags[0].message,
+            format!("\"{}\" took {}ms to respond", slow, elapsed)
+        );
+        assert!(elapsed >= 300, "{}", elapsed);
+    }
+
+    #[test]
+    fn warn_about_links_whose_text_is_a_different_url() {
+        let src =
+            "[https://a.com](https://b.com) and [https://a.com](https://a.com)";
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", src.to_string());
+        let (links, _) = crate::extract_links(vec![file], &files);
+        let check = |cfg: &Config| {
+            validate(
+                &links,
+                cfg,
+                Path::new("."),
+                &mut Cache::default(),
+                &files,
+                &[file],
+                Vec::new(),
+            )
+            .unwrap()
+        };
+        let cfg = Config {
+            lint_mismatched_urls: true,
+            ..Default::default()
+        };
+
+        let outcome = check(&cfg);
+
+        let messages: Vec<_> = outcome
+            .generate_diagnostics(&files, WarningPolicy::Warn)
+            .into_iter()
+            .map(|diag| diag.message)
+            .collect();
+        assert_eq!(
+            messages,
+            &["The link's text is \"https://a.com\", but it goes to \"https://b.com\""]
+        );
+        assert!(check(&Config::default()).mismatched_urls.is_empty());
+    }
+
+    #[test]
+    fn warn_about_http_links_when_https_is_required() {
+        let src = "[a](http://example.com/page) and [b](https://example.com/)";
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", src.to_string());
+        let (links, _) = crate::extract_links(vec![file], &files);
+        let check = |cfg: &Config| {
+            validate(
+                &links,
+                cfg,
+                Path::new("."),
+                &mut Cache::default(),
+                &files,
+                &[file],
+                Vec::new(),
+            )
+            .unwrap()
+        };
+        let cfg = Config {
+            require_https: true,
+            ..Default::default()
+        };
+
+        let outcome = check(&cfg);
+
+        assert_eq!(outcome.insecure_links, &links[..1]);
+        let diags = outcome.generate_diagnostics(&files, WarningPolicy::Warn);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(
+            diags[0].message,
+            "\"http://example.com/page\" should use https (e.g. \"https://example.com/page\")"
+        );
+        assert!(check(&Config::default()).insecure_links.is_empty());
+    }
+
+    #[test]
+    fn links_without_a_destination_are_broken() {
+        let src = "[a]() and [a]( )";
+        let mut files = Files::new();
+        let file = files.add("chapter_1.md", src.to_string());
+        let (links, _) = crate::extract_links(vec![file], &files);
+        let cfg = Config::default();
+
+        let outcome = validate(
+            &links,
+            &cfg,
+            Path::new("."),
+            &mut Cache::default(),
+            &files,
+            &[file],
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert!(outcome.valid_links.is_empty());
+        let errors: Vec<_> = outcome
+            .invalid_links
+            .iter()
+            .map(|invalid| (LinkError::from(invalid), invalid.link.span))
+            .collect();
+        assert_eq!(
+            errors,
+            &[
+                (LinkError::Empty(EmptyLink), Span::new(0, 5)),
+                (LinkError::Empty(EmptyLink), Span::new(10, 16)),
+            ]
+        );
+        let messages: Vec<_> = outcome
+            .generate_diagnostics(&files, WarningPolicy::Warn)
+            .into_iter()
+            .map(|diag| diag.message)
+            .collect();
+        assert_eq!(messages, &["The link doesn't have a destination"; 2]);
+    }
 }
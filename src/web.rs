@@ -0,0 +1,2110 @@
+use crate::{Config, Context, MissingAnchor, TlsVersion, WebLinkMode};
+use futures::future::BoxFuture;
+use http::header::{HeaderValue, AUTHORIZATION, LOCATION, RETRY_AFTER};
+use linkcheck::validation::{CacheEntry, Context as _, Reason};
+use reqwest::{
+    redirect::Policy, Client, Method, Proxy, Request, Response, StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Display, Formatter},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Check whether a [`Url`] points to a valid resource on the internet.
+///
+/// This is a replacement for [`linkcheck::validation::check_web()`] which
+/// gives us more control over how requests are sent.
+pub(crate) async fn check_web(
+    url: &Url,
+    ctx: &Context<'_>,
+) -> Result<(), Reason> {
+    log::debug!("Checking \"{}\" on the web", url);
+
+    if ctx.cfg.assumes_reachable(url) {
+        log::debug!("Assuming \"{}\" is ok without checking it", url);
+        return Ok(());
+    }
+
+    if already_valid(url, ctx) {
+        log::debug!("The cache says \"{}\" is still valid", url);
+        return Ok(());
+    }
+
+    if let Some(age) = recently_broken(url, ctx) {
+        log::debug!("The cache says \"{}\" is still broken", url);
+        return Err(Reason::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            CachedFailure {
+                url: url.clone(),
+                age,
+            },
+        )));
+    }
+
+    let result = match send_request_with_retries(url, ctx).await {
+        Ok(response) => match check_status(response, ctx.cfg).await {
+            Ok(response) if ctx.cfg.warn_on_redirect => {
+                record_redirect(url, &response, ctx);
+                if checks_fragment(url, ctx.cfg) {
+                    ensure_fragment_exists(url, response).await
+                } else {
+                    Ok(())
+                }
+            },
+            Ok(response) if checks_fragment(url, ctx.cfg) => {
+                ensure_fragment_exists(url, response).await
+            },
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(request_failed(e, url, ctx.cfg)),
+    };
+    let result = match ctx.cfg.rewrite_host(url) {
+        Some(rewritten) => {
+            result.map_err(|e| restore_original_url(e, url, &rewritten))
+        },
+        None => result,
+    };
+
+    if let Some(mut cache) = ctx.cache() {
+        let entry = CacheEntry::new(SystemTime::now(), result.is_ok());
+        cache.insert(url.clone(), entry);
+    }
+
+    result
+}
+
+/// Turn a request which failed before we got a response into a [`Reason`]
+/// saying why, so users can tell a typo in the URL apart from a site which is
+/// down.
+fn request_failed(error: reqwest::Error, url: &Url, cfg: &Config) -> Reason {
+    let url = url.clone();
+
+    let err = match cfg.min_tls_version {
+        Some(min_version) if is_tls_version_mismatch(&error) => {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                OutdatedTls { url, min_version },
+            )
+        },
+        _ if error.is_timeout() => {
+            std::io::Error::new(std::io::ErrorKind::Other, Timeout { url })
+        },
+        _ if error.is_connect() => connect_error(&error, url),
+        _ => return Reason::from(error),
+    };
+
+    Reason::Io(err)
+}
+
+/// Work out which step of connecting to the server failed.
+fn connect_error(error: &reqwest::Error, url: Url) -> std::io::Error {
+    let reason = root_cause(error);
+    let mut source = std::error::Error::source(error);
+
+    while let Some(err) = source {
+        // hyper's connector errors are "dns error: ..." and
+        // "tcp connect error: ..."
+        if err.to_string().starts_with("dns error") {
+            return std::io::Error::new(
+                std::io::ErrorKind::Other,
+                DnsFailure { url, reason },
+            );
+        }
+        if err.is::<std::io::Error>() {
+            return std::io::Error::new(
+                std::io::ErrorKind::Other,
+                ConnectionFailed { url, reason },
+            );
+        }
+        source = err.source();
+    }
+
+    // anything else went wrong while setting up the encrypted connection
+    if url.scheme() == "https" {
+        std::io::Error::new(std::io::ErrorKind::Other, TlsError { url, reason })
+    } else {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            ConnectionFailed { url, reason },
+        )
+    }
+}
+
+/// The message from the innermost error, which is usually the only one that
+/// says anything useful.
+fn root_cause(error: &reqwest::Error) -> String {
+    let mut cause: &dyn std::error::Error = error;
+
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+
+    cause.to_string()
+}
+
+/// Did the TLS handshake fail because the client and server couldn't agree
+/// on a protocol version?
+///
+/// The TLS library doesn't give us a proper error type for this, so we have
+/// to go by the messages used by OpenSSL, Secure Transport, and SChannel.
+fn is_tls_version_mismatch(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+
+    while let Some(err) = source {
+        let msg = err.to_string().to_lowercase();
+        if msg.contains("protocol version")
+            || msg.contains("unsupported protocol")
+            || msg.contains("wrong version number")
+            || msg.contains("do not possess a common algorithm")
+        {
+            return true;
+        }
+        source = err.source();
+    }
+
+    false
+}
+
+/// Remember where a link ended up if it was redirected (see
+/// [`Config::warn_on_redirect`]).
+fn record_redirect(url: &Url, response: &Response, ctx: &Context<'_>) {
+    let requested = ctx.cfg.rewrite_host(url).unwrap_or_else(|| url.clone());
+
+    // when redirects aren't followed, the server tells us where it would
+    // have sent us
+    let destination = if response.status().is_redirection() {
+        response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| requested.join(location).ok())
+    } else {
+        Some(response.url().clone())
+    };
+
+    let destination = match destination {
+        Some(destination) if !same_page(&destination, &requested) => {
+            destination
+        },
+        _ => return,
+    };
+
+    log::debug!("\"{}\" redirected to \"{}\"", url, destination);
+    ctx.redirects
+        .lock()
+        .expect("Lock was poisoned")
+        .insert(url.clone(), destination);
+}
+
+/// Do the two [`Url`]s point to the same page, ignoring the `#fragment`?
+fn same_page(first: &Url, second: &Url) -> bool {
+    let mut first = first.clone();
+    first.set_fragment(None);
+    let mut second = second.clone();
+    second.set_fragment(None);
+
+    first == second
+}
+
+/// Should we download the page to make sure the [`Url`]'s `#fragment` exists
+/// (see [`Config::check_fragments`])?
+fn checks_fragment(url: &Url, cfg: &Config) -> bool {
+    cfg.follow_web_links != WebLinkMode::Existence
+        && cfg.check_fragments
+        && matches!(url.fragment(), Some(f) if !f.is_empty())
+}
+
+/// Look through the page for an element whose `id` or `name` matches the
+/// [`Url`]'s `#fragment`.
+async fn ensure_fragment_exists(
+    url: &Url,
+    response: Response,
+) -> Result<(), Reason> {
+    // if we accepted an unsuccessful status code, then we probably didn't get
+    // the actual page
+    if !response.status().is_success() {
+        return Ok(());
+    }
+
+    let fragment = url.fragment().unwrap_or_default();
+    let fragment = percent_encoding::percent_decode_str(fragment)
+        .decode_utf8_lossy()
+        .into_owned();
+    let body = response.text().await?;
+
+    let anchors = crate::anchors::collect_html_anchors(&body);
+
+    if anchors.contains(&fragment) {
+        Ok(())
+    } else {
+        let mut page = url.clone();
+        page.set_fragment(None);
+
+        Err(Reason::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            MissingAnchor {
+                path: page.as_str().into(),
+                suggestion: crate::anchors::closest_anchor(&fragment, &anchors),
+                fragment,
+            },
+        )))
+    }
+}
+
+/// Errors from a request which was sent somewhere else (see
+/// [`Config::host_rewrites`]) mention the rewritten URL, but users need to
+/// know which link in the book is broken.
+fn restore_original_url(
+    reason: Reason,
+    original: &Url,
+    rewritten: &Url,
+) -> Reason {
+    let msg = crate::validate::describe_reason(original.as_str(), &reason)
+        .replace(
+            &rewritten.origin().ascii_serialization(),
+            &original.origin().ascii_serialization(),
+        );
+
+    Reason::Io(std::io::Error::new(std::io::ErrorKind::Other, msg))
+}
+
+/// Parse a link's href as a [`Url`].
+///
+/// Protocol-relative URLs (e.g. `//example.com/page`) use the current page's
+/// scheme when viewed in a browser, so we assume they're served over `https`.
+/// Windows paths like `C:\Users\me\notes.md` aren't URLs, even though they
+/// look like they have a scheme.
+pub(crate) fn parse_url(href: &str) -> Option<Url> {
+    if crate::local::is_windows_path(href) {
+        None
+    } else if href.starts_with("//") {
+        format!("https:{}", href).parse().ok()
+    } else {
+        href.parse().ok()
+    }
+}
+
+/// Can this [`Url`] be checked with a HTTP request?
+pub(crate) fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+fn already_valid(url: &Url, ctx: &Context<'_>) -> bool {
+    match ctx.cache() {
+        Some(cache) => cache.url_is_still_valid(url, ctx.cache_timeout()),
+        None => false,
+    }
+}
+
+/// How long ago was this [`Url`] found to be broken, if that was recent enough
+/// to still trust (see [`Config::negative_cache_timeout`])?
+fn recently_broken(url: &Url, ctx: &Context<'_>) -> Option<Duration> {
+    let timeout = Duration::from_secs(ctx.cfg.negative_cache_timeout);
+    let cache = ctx.cache()?;
+    let entry = cache.lookup(url).filter(|entry| !entry.valid)?;
+    let age = entry.timestamp.elapsed().ok()?;
+
+    if age < timeout {
+        Some(age)
+    } else {
+        None
+    }
+}
+
+/// A redirect policy which follows at most `max_redirects` redirects, failing
+/// with [`TooManyRedirects`] when the limit is exceeded.
+pub(crate) fn redirect_policy(max_redirects: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            let mut chain = attempt.previous().to_vec();
+            chain.push(attempt.url().clone());
+            attempt.error(TooManyRedirects { chain })
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Send every request through `proxy`, except for hosts matching one of the
+/// `no_proxy` domains.
+pub(crate) fn proxy(proxy: Url, no_proxy: Vec<String>) -> Proxy {
+    Proxy::custom(move |url| {
+        if bypasses_proxy(url, &no_proxy) {
+            None
+        } else {
+            Some(proxy.clone())
+        }
+    })
+}
+
+/// Does the [`Url`]'s host match one of the `no_proxy` domains? Like
+/// `NO_PROXY`, a domain also matches its subdomains and `*` matches
+/// everything.
+fn bypasses_proxy(url: &Url, no_proxy: &[String]) -> bool {
+    let host = match url.host_str() {
+        Some(host) => without_brackets(host).to_ascii_lowercase(),
+        None => return false,
+    };
+
+    no_proxy.iter().any(|domain| {
+        let domain = without_brackets(domain.trim().trim_start_matches('.'))
+            .to_ascii_lowercase();
+        domain == "*"
+            || host == domain
+            || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+/// Does a host name from the config (e.g. [`Config::host_rewrites`]) refer
+/// to the [`Url`]'s host? IPv6 addresses match with or without their square
+/// brackets, so `::1` and `[::1]` are both the same as `http://[::1]:8080/`.
+pub(crate) fn is_same_host(name: &str, url: &Url) -> bool {
+    match url.host_str() {
+        Some(host) => without_brackets(name.trim())
+            .eq_ignore_ascii_case(without_brackets(host)),
+        None => false,
+    }
+}
+
+/// Strip the square brackets from an IPv6 address like `[::1]`.
+fn without_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// The error emitted when a web link redirects too many times (e.g. because
+/// of a redirect loop).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TooManyRedirects {
+    /// Every URL that was visited, starting with the original link.
+    pub chain: Vec<Url>,
+}
+
+impl Display for TooManyRedirects {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Gave up after {} redirects: ", self.chain.len() - 1)?;
+
+        for (i, url) in self.chain.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", url)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for TooManyRedirects {}
+
+/// The error emitted when the cache says a web link was broken the last time
+/// it was checked (see [`Config::negative_cache_timeout`]), so it wasn't
+/// requested again.
+///
+/// The cache only remembers whether a link was valid, not why it was broken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedFailure {
+    /// The link's URL.
+    pub url: Url,
+    /// How long ago the link was found to be broken.
+    pub age: Duration,
+}
+
+impl Display for CachedFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" was broken when it was checked {} seconds ago",
+            self.url,
+            self.age.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for CachedFailure {}
+
+/// The error emitted when a server responds to a web link with an
+/// unsuccessful status code (e.g. `404 Not Found`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsuccessfulStatus {
+    /// The URL which was requested (after following any redirects).
+    pub url: Url,
+    /// The status code.
+    pub status: StatusCode,
+    /// The start of the response body, if there was one.
+    pub body: Option<String>,
+    /// How long the server asked us to wait before trying again (from the
+    /// `Retry-After` header), if it said.
+    pub retry_after: Option<Duration>,
+}
+
+impl UnsuccessfulStatus {
+    /// How much of the response body to keep, in bytes.
+    pub const MAX_BODY_LEN: usize = 200;
+}
+
+impl Display for UnsuccessfulStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Server returned {}", self.status.as_u16())?;
+
+        if let Some(reason) = self.status.canonical_reason() {
+            write!(f, " {}", reason)?;
+        }
+
+        write!(f, " for {}", self.url)?;
+
+        if let Some(delay) = self.retry_after {
+            write!(
+                f,
+                " (the server asked us to wait {} seconds before retrying)",
+                delay.as_secs()
+            )?;
+        }
+
+        if let Some(ref body) = self.body {
+            write!(f, ": \"{}\"", body)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnsuccessfulStatus {}
+
+/// The error emitted when a link uses a scheme (e.g. `tel:`) which we can't
+/// check and isn't in [`Config::allowed_schemes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedScheme {
+    /// The link's scheme.
+    pub scheme: String,
+}
+
+impl Display for UnsupportedScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Links using the \"{}:\" scheme aren't allowed (see the \
+             `allowed-schemes` option)",
+            self.scheme
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedScheme {}
+
+/// The error emitted when a web server can't use [`Config::min_tls_version`]
+/// or anything newer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutdatedTls {
+    /// The URL which was requested.
+    pub url: Url,
+    /// The oldest version of TLS which was allowed.
+    pub min_version: TlsVersion,
+}
+
+impl Display for OutdatedTls {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" doesn't support {} or newer (see the `min-tls-version` \
+             option)",
+            self.url, self.min_version
+        )
+    }
+}
+
+impl std::error::Error for OutdatedTls {}
+
+/// The error emitted when the host name in a web link couldn't be looked up,
+/// usually because of a typo in the URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsFailure {
+    /// The URL which was requested.
+    pub url: Url,
+    /// Why the lookup failed.
+    pub reason: String,
+}
+
+impl Display for DnsFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to look up the host for {}: {}",
+            self.url, self.reason
+        )
+    }
+}
+
+impl std::error::Error for DnsFailure {}
+
+/// The error emitted when we couldn't connect to a web link's server (e.g.
+/// the connection was refused because the site is down).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionFailed {
+    /// The URL which was requested.
+    pub url: Url,
+    /// Why the connection failed.
+    pub reason: String,
+}
+
+impl Display for ConnectionFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to connect to the server for {}: {}",
+            self.url, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ConnectionFailed {}
+
+/// The error emitted when the TLS handshake with a web link's server fails
+/// (e.g. because its certificate is invalid).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsError {
+    /// The URL which was requested.
+    pub url: Url,
+    /// Why the handshake failed.
+    pub reason: String,
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to set up a secure connection for {}: {}",
+            self.url, self.reason
+        )
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+/// The error emitted when a server doesn't respond to a web link within
+/// [`Config::timeout`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeout {
+    /// The URL which was requested.
+    pub url: Url,
+}
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out while checking {}", self.url)
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Turn an unsuccessful status code into an [`UnsuccessfulStatus`] error,
+/// unless the user has said it is acceptable.
+async fn check_status(
+    response: Response,
+    cfg: &Config,
+) -> Result<Response, Reason> {
+    let status = response.status();
+
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
+    }
+
+    if is_accepted(status, cfg) {
+        log::log!(
+            cfg.warning_policy.to_log_level(),
+            "Treating \"{}\" as valid even though the server returned {}",
+            response.url(),
+            status
+        );
+        return Ok(response);
+    }
+
+    let url = response.url().clone();
+    let retry_after = retry_after(&response);
+    let body = body_snippet(response).await;
+
+    Err(Reason::Io(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        UnsuccessfulStatus {
+            url,
+            status,
+            body,
+            retry_after,
+        },
+    )))
+}
+
+/// Read the start of an error page (e.g. a maintenance page or a message from
+/// a firewall), which often explains why the request failed.
+async fn body_snippet(mut response: Response) -> Option<String> {
+    let mut bytes = Vec::new();
+
+    while bytes.len() < UnsuccessfulStatus::MAX_BODY_LEN {
+        match response.chunk().await {
+            Ok(Some(chunk)) => bytes.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+
+    truncate_body(&bytes, UnsuccessfulStatus::MAX_BODY_LEN)
+}
+
+/// Decode (at most) the first `max_len` bytes, making sure not to cut a
+/// character in half, and squash all whitespace so it fits on one line.
+fn truncate_body(bytes: &[u8], max_len: usize) -> Option<String> {
+    let mut bytes = &bytes[..bytes.len().min(max_len)];
+
+    if let Err(e) = std::str::from_utf8(bytes) {
+        // an incomplete character at the end is from the truncation
+        if e.error_len().is_none() {
+            bytes = &bytes[..e.valid_up_to()];
+        }
+    }
+
+    let text = String::from_utf8_lossy(bytes);
+    let snippet = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if snippet.is_empty() {
+        None
+    } else {
+        Some(snippet)
+    }
+}
+
+fn is_accepted(status: StatusCode, cfg: &Config) -> bool {
+    cfg.accepted_status_codes.contains(&status.as_u16())
+}
+
+/// Send a request, retrying with an exponential backoff when it fails for a
+/// reason that is probably temporary (a connection error, `429 Too Many
+/// Requests`, etc.).
+async fn send_request_with_retries(
+    url: &Url,
+    ctx: &Context<'_>,
+) -> Result<Response, reqwest::Error> {
+    let mut attempt = 0;
+
+    loop {
+        let result = send_request(url, ctx).await;
+
+        if attempt >= ctx.cfg.max_retries {
+            return result;
+        }
+
+        let delay = match retry_delay(&result, attempt, ctx.cfg) {
+            Some(delay) => delay,
+            None => return result,
+        };
+
+        attempt += 1;
+        log::debug!(
+            "Retrying \"{}\" in {:?} (attempt {} of {})",
+            url,
+            delay,
+            attempt,
+            ctx.cfg.max_retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// How long should we wait before retrying a request, if at all?
+fn retry_delay(
+    result: &Result<Response, reqwest::Error>,
+    attempt: u32,
+    cfg: &Config,
+) -> Option<Duration> {
+    let max_delay = Duration::from_secs(cfg.max_retry_delay);
+    let backoff = Duration::from_millis(cfg.retry_base_delay_ms)
+        .checked_mul(2_u32.saturating_pow(attempt))
+        .map_or(max_delay, |backoff| backoff.min(max_delay));
+
+    match result {
+        // there's no point retrying if we'd accept the response anyway
+        Ok(response) if is_accepted(response.status(), cfg) => None,
+        Ok(response)
+            if cfg
+                .retryable_status_codes
+                .contains(&response.status().as_u16()) =>
+        {
+            match retry_after(response) {
+                Some(delay) if delay > max_delay => {
+                    log::debug!(
+                        "Not retrying \"{}\" because the server asked us to wait {:?}",
+                        response.url(),
+                        delay
+                    );
+                    None
+                },
+                Some(delay) => Some(delay),
+                None => Some(backoff),
+            }
+        },
+        Ok(_) => None,
+        Err(e) if e.is_connect() || e.is_timeout() => Some(backoff),
+        Err(_) => None,
+    }
+}
+
+/// The delay requested by a server's `Retry-After` header, which may be
+/// either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    // a date in the past means we can retry straight away
+    let delay = date.duration_since(SystemTime::now()).unwrap_or_default();
+
+    // round up to whole seconds so we never retry too early
+    Some(Duration::from_secs(
+        delay.as_secs() + u64::from(delay.subsec_nanos() > 0),
+    ))
+}
+
+/// Something which sends the HTTP requests used to check web links.
+///
+/// The default implementation uses a [`reqwest::Client`], but you can provide
+/// your own (e.g. a fake which returns canned responses in tests).
+pub trait LinkFetcher: Debug + Send + Sync {
+    /// Send the [`Request`], returning the server's [`Response`].
+    fn fetch(
+        &self,
+        request: Request,
+    ) -> BoxFuture<'_, Result<Response, reqwest::Error>>;
+}
+
+impl LinkFetcher for Client {
+    fn fetch(
+        &self,
+        request: Request,
+    ) -> BoxFuture<'_, Result<Response, reqwest::Error>> {
+        Box::pin(self.execute(request))
+    }
+}
+
+/// Makes sure requests to the same host are spaced out, so we don't get
+/// rate-limited (or banned) when lots of links point to the same site, and
+/// optionally that there is a minimum delay between any two requests (see
+/// [`Config::global_delay_ms`]).
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Option<Duration>,
+    global_interval: Option<Duration>,
+    /// When the next request to each host may be sent.
+    next_request: Mutex<HashMap<String, Instant>>,
+    /// When the next request to any host may be sent.
+    next_global_request: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: Option<f64>) -> Self {
+        let interval = requests_per_second
+            .filter(|&rate| rate.is_finite() && rate > 0.0)
+            .map(|rate| {
+                let rate = rate.max(Config::MIN_REQUESTS_PER_HOST_PER_SECOND);
+                Duration::from_secs_f64(1.0 / rate)
+            });
+
+        RateLimiter {
+            interval,
+            global_interval: None,
+            next_request: Mutex::new(HashMap::new()),
+            next_global_request: Mutex::new(None),
+        }
+    }
+
+    /// Make sure there is at least `delay` between any two requests, no
+    /// matter which host they are sent to. A delay of zero turns this off.
+    pub(crate) fn with_global_delay(mut self, delay: Duration) -> Self {
+        self.global_interval =
+            Some(delay).filter(|delay| *delay > Duration::from_secs(0));
+        self
+    }
+
+    /// Wait until we're allowed to send another request to the [`Url`]'s
+    /// host.
+    pub(crate) async fn wait(&self, url: &Url) {
+        let slot = match self.reserve(url) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        if slot > Instant::now() {
+            log::debug!(
+                "Waiting before sending another request to {}",
+                url.host_str().unwrap_or_default()
+            );
+            tokio::time::sleep_until(slot.into()).await;
+        }
+    }
+
+    /// Reserve the next slot for sending a request to the [`Url`]'s host,
+    /// returning when the request may be sent. There's nothing to reserve if
+    /// rate limiting is turned off.
+    fn reserve(&self, url: &Url) -> Option<Instant> {
+        let host = url.host_str()?;
+        if self.interval.is_none() && self.global_interval.is_none() {
+            return None;
+        }
+
+        // reserve a slot while holding the locks (always taken in the same
+        // order) so concurrent requests each get their own turn
+        let slot = {
+            let mut next_request =
+                self.next_request.lock().expect("Lock was poisoned");
+            let mut next_global_request =
+                self.next_global_request.lock().expect("Lock was poisoned");
+            let now = Instant::now();
+            let mut slot = now;
+
+            if self.interval.is_some() {
+                if let Some(&next) = next_request.get(host) {
+                    slot = slot.max(next);
+                }
+            }
+            if self.global_interval.is_some() {
+                if let Some(next) = *next_global_request {
+                    slot = slot.max(next);
+                }
+            }
+
+            if let Some(interval) = self.interval {
+                next_request.insert(host.to_string(), slot + interval);
+            }
+            if let Some(interval) = self.global_interval {
+                *next_global_request = Some(slot + interval);
+            }
+            slot
+        };
+
+        Some(slot)
+    }
+}
+
+/// Send a `HEAD` request, falling back to `GET` if the server doesn't support
+/// `HEAD`.
+async fn send_request(
+    url: &Url,
+    ctx: &Context<'_>,
+) -> Result<Response, reqwest::Error> {
+    ctx.rate_limiter.wait(url).await;
+
+    let use_head_requests = ctx.cfg.use_head_requests
+        || ctx.cfg.follow_web_links == WebLinkMode::Existence;
+
+    // a HEAD request doesn't give us the page to look for the fragment in
+    if use_head_requests && !checks_fragment(url, ctx.cfg) {
+        let response = send(request(Method::HEAD, url, ctx), ctx).await?;
+
+        match response.status() {
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+                log::debug!(
+                    "The server doesn't support HEAD requests for \"{}\", falling back to GET",
+                    url
+                );
+                // the HEAD request already used up our slot
+                ctx.rate_limiter.wait(url).await;
+            },
+            _ => return Ok(response),
+        }
+    }
+
+    send(request(Method::GET, url, ctx), ctx).await
+}
+
+async fn send(
+    request: Request,
+    ctx: &Context<'_>,
+) -> Result<Response, reqwest::Error> {
+    ctx.fetcher.fetch(request).await
+}
+
+/// Build the [`Request`] for checking a [`Url`]. This doesn't go through a
+/// [`Client`], so whatever the [`LinkFetcher`] does with the request (e.g.
+/// adding a user-agent or a timeout) is up to it.
+fn request(method: Method, url: &Url, ctx: &Context<'_>) -> Request {
+    let mut request = Request::new(
+        method,
+        ctx.cfg.rewrite_host(url).unwrap_or_else(|| url.clone()),
+    );
+    *request.headers_mut() = ctx.url_specific_headers(url);
+
+    if let Some(auth) = ctx.cfg.basic_auth_for(url) {
+        match auth.interpolate_password() {
+            Ok(password) => {
+                let credentials = format!("{}:{}", auth.username, password);
+                request.headers_mut().insert(
+                    AUTHORIZATION,
+                    authorization(&format!(
+                        "Basic {}",
+                        base64::encode(credentials)
+                    )),
+                );
+            },
+            Err(e) => log::warn!(
+                "Unable to interpolate the password for \"{}\", sending the request without basic auth: {}",
+                url.host_str().unwrap_or_default(),
+                e
+            ),
+        }
+    }
+
+    if let Some(token) = ctx.cfg.bearer_token_for(url) {
+        match token.interpolate() {
+            Ok(token) => {
+                request.headers_mut().insert(
+                    AUTHORIZATION,
+                    authorization(&format!("Bearer {}", token)),
+                );
+            },
+            Err(e) => log::warn!(
+                "Unable to interpolate the bearer token for \"{}\", sending the request without it: {}",
+                url.host_str().unwrap_or_default(),
+                e
+            ),
+        }
+    }
+
+    request
+}
+
+/// An `Authorization` header, marked as sensitive so it never ends up in the
+/// logs.
+fn authorization(credentials: &str) -> HeaderValue {
+    // base64 and interpolated header values are always valid header values
+    let mut value = HeaderValue::from_str(credentials)
+        .expect("The credentials should be a valid header value");
+    value.set_sensitive(true);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{self, MockServer, Response},
+        BasicAuth, BearerToken, HashedRegex, LinkError,
+    };
+    use std::{
+        convert::TryInto,
+        iter::FromIterator,
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::runtime::Builder;
+
+    /// The status code from an [`UnsuccessfulStatus`] error.
+    fn status_of(reason: &Reason) -> Option<StatusCode> {
+        match reason {
+            Reason::Io(e) => e
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<UnsuccessfulStatus>())
+                .map(|e| e.status),
+            _ => None,
+        }
+    }
+
+    fn check(url: &Url, cfg: &Config) -> Result<(), Reason> {
+        check_with_cache(url, cfg, None)
+    }
+
+    /// Check a [`Url`], pretending it was found to be valid at `last_checked`.
+    fn check_with_cache(
+        url: &Url,
+        cfg: &Config,
+        last_checked: Option<SystemTime>,
+    ) -> Result<(), Reason> {
+        let entry =
+            last_checked.map(|timestamp| CacheEntry::new(timestamp, true));
+        check_with_cache_entry(url, cfg, entry)
+    }
+
+    /// Check a [`Url`], starting with this [`CacheEntry`] in the cache.
+    fn check_with_cache_entry(
+        url: &Url,
+        cfg: &Config,
+        entry: Option<CacheEntry>,
+    ) -> Result<(), Reason> {
+        let ctx = test_utils::context(cfg);
+        if let Some(entry) = entry {
+            ctx.cache().unwrap().insert(url.clone(), entry);
+        }
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime.block_on(check_web(url, &ctx))
+    }
+
+    #[test]
+    fn fall_back_to_get_when_head_is_not_allowed() {
+        let server =
+            MockServer::start(|request| match request.method.as_str() {
+                "HEAD" => Response::new(405),
+                _ => Response::new(200),
+            });
+        let url = server.url("/page");
+
+        check(&url, &Config::default()).unwrap();
+
+        let requests = server.requests();
+        let methods: Vec<_> = requests.iter().map(|r| &r.method).collect();
+        assert_eq!(methods, &["HEAD", "GET"]);
+        assert!(requests.iter().all(|r| r.path == "/page"));
+    }
+
+    #[test]
+    fn only_send_get_requests_when_head_is_disabled() {
+        let server = MockServer::start(|_| Response::new(404));
+        let url = server.url("/missing");
+        let cfg = Config {
+            use_head_requests: false,
+            ..Default::default()
+        };
+
+        let got = check(&url, &cfg).unwrap_err();
+
+        assert_eq!(status_of(&got), Some(StatusCode::NOT_FOUND));
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, &["GET"]);
+    }
+
+    #[test]
+    fn look_for_fragments_on_web_pages() {
+        let server = MockServer::start(|_| {
+            Response::new(200)
+                .with_body(r#"<html><h1 id="section">Section</h1></html>"#)
+        });
+        let cfg = Config {
+            check_fragments: true,
+            ..Default::default()
+        };
+
+        check(&server.url("/page#section"), &cfg).unwrap();
+        let got = check(&server.url("/page#missing"), &cfg).unwrap_err();
+
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, &["GET", "GET"]);
+        let missing = match got {
+            Reason::Io(ref e) => e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<MissingAnchor>())
+                .unwrap(),
+            other => panic!("Unexpected reason: {:?}", other),
+        };
+        assert_eq!(missing.fragment, "missing");
+        assert_eq!(missing.path, Path::new(server.url("/page").as_str()));
+    }
+
+    #[test]
+    fn fragments_on_web_pages_are_ignored_by_default() {
+        let server = MockServer::start(|_| Response::new(200));
+
+        check(&server.url("/page#missing"), &Config::default()).unwrap();
+
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, &["HEAD"]);
+    }
+
+    #[test]
+    fn skip_links_which_were_recently_found_to_be_valid() {
+        let server = MockServer::start(|_| Response::new(500));
+        let url = server.url("/cached");
+        let cfg = Config {
+            cache_timeout: 60 * 60,
+            ..Default::default()
+        };
+
+        check_with_cache(&url, &cfg, Some(SystemTime::now())).unwrap();
+
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn recheck_links_once_the_cache_timeout_has_elapsed() {
+        let server = MockServer::start(|_| Response::new(200));
+        let url = server.url("/stale");
+        let cfg = Config {
+            cache_timeout: 60 * 60,
+            ..Default::default()
+        };
+        let two_hours_ago =
+            SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+
+        check_with_cache(&url, &cfg, Some(two_hours_ago)).unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn remember_links_which_were_recently_broken() {
+        let server = MockServer::start(|_| Response::new(200));
+        let url = server.url("/dead");
+        let cfg = Config {
+            negative_cache_timeout: 10 * 60,
+            ..Default::default()
+        };
+        let a_minute_ago = SystemTime::now() - Duration::from_secs(60);
+
+        let reason = check_with_cache_entry(
+            &url,
+            &cfg,
+            Some(CacheEntry::new(a_minute_ago, false)),
+        )
+        .unwrap_err();
+
+        assert!(server.requests().is_empty());
+        match crate::LinkError::from_reason(url.as_str(), &reason) {
+            crate::LinkError::CachedFailure(e) => {
+                assert_eq!(e.url, url);
+                assert!(e.age >= Duration::from_secs(60), "{:?}", e.age);
+            },
+            other => panic!("Expected a cached failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recheck_broken_links_once_the_negative_cache_timeout_has_elapsed() {
+        let server = MockServer::start(|_| Response::new(200));
+        let url = server.url("/recovered");
+        let half_an_hour_ago = SystemTime::now() - Duration::from_secs(30 * 60);
+        let entry = Some(CacheEntry::new(half_an_hour_ago, false));
+
+        for &negative_cache_timeout in &[0, 10 * 60] {
+            let cfg = Config {
+                negative_cache_timeout,
+                ..Default::default()
+            };
+
+            check_with_cache_entry(&url, &cfg, entry).unwrap();
+        }
+
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn retry_temporary_failures() {
+        let attempts = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Response::new(503),
+                _ => Response::new(200),
+            }
+        });
+        let url = server.url("/flaky");
+        let cfg = Config {
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn give_up_after_the_maximum_number_of_retries() {
+        let server = MockServer::start(|_| Response::new(429));
+        let url = server.url("/busy");
+        let cfg = Config {
+            max_retries: 2,
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        };
+
+        let got = check(&url, &cfg).unwrap_err();
+
+        assert_eq!(status_of(&got), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn respect_the_retry_after_header() {
+        let response = http::Response::builder()
+            .status(503)
+            .header(RETRY_AFTER, "30")
+            .body("")
+            .unwrap();
+        let result = Ok(reqwest::Response::from(response));
+
+        let got = retry_delay(&result, 0, &Config::default());
+
+        assert_eq!(got, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn give_up_when_the_server_wants_us_to_wait_too_long() {
+        let response = http::Response::builder()
+            .status(503)
+            .header(RETRY_AFTER, "120")
+            .body("")
+            .unwrap();
+        let result = Ok(reqwest::Response::from(response));
+        let cfg = Config {
+            max_retry_delay: 60,
+            ..Default::default()
+        };
+
+        let got = retry_delay(&result, 0, &cfg);
+
+        assert_eq!(got, None);
+    }
+
+    #[test]
+    fn the_backoff_never_exceeds_the_max_retry_delay() {
+        let response = http::Response::builder().status(503).body("").unwrap();
+        let result = Ok(reqwest::Response::from(response));
+        let cfg = Config {
+            max_retries: 10,
+            max_retry_delay: 5,
+            ..Default::default()
+        };
+
+        let got = retry_delay(&result, 9, &cfg);
+
+        assert_eq!(got, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn retry_after_can_be_an_http_date() {
+        let later = SystemTime::now() + Duration::from_secs(30);
+        let response = http::Response::builder()
+            .status(429)
+            .header(RETRY_AFTER, httpdate::fmt_http_date(later))
+            .body("")
+            .unwrap();
+        let result = Ok(reqwest::Response::from(response));
+
+        let got = retry_delay(&result, 0, &Config::default()).unwrap();
+
+        // HTTP dates only have a resolution of 1 second
+        assert!(got <= Duration::from_secs(30), "{:?}", got);
+        assert!(got >= Duration::from_secs(29), "{:?}", got);
+    }
+
+    #[test]
+    fn retry_after_a_date_in_the_past_straight_away() {
+        let response = http::Response::builder()
+            .status(429)
+            .header(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT")
+            .body("")
+            .unwrap();
+        let result = Ok(reqwest::Response::from(response));
+
+        let got = retry_delay(&result, 0, &Config::default());
+
+        assert_eq!(got, Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn wait_as_long_as_the_server_asks_before_retrying() {
+        let attempts = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Response::new(429).with_header("Retry-After", "1"),
+                _ => Response::new(200),
+            }
+        });
+        let url = server.url("/rate-limited");
+        let cfg = Config {
+            max_retries: 1,
+            retry_base_delay_ms: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        check(&url, &cfg).unwrap();
+
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn mention_the_requested_delay_when_giving_up() {
+        let later = httpdate::fmt_http_date(
+            SystemTime::now() + Duration::from_secs(3600),
+        );
+        // HTTP dates only have a resolution of 1 second
+        let inputs: Vec<(&str, &[&str])> = vec![
+            ("120", &["wait 120 seconds"]),
+            (&later, &["wait 3599 seconds", "wait 3600 seconds"]),
+        ];
+
+        for (retry_after, should_mention) in inputs {
+            let retry_after = retry_after.to_string();
+            let server = MockServer::start(move |_| {
+                Response::new(429).with_header("Retry-After", &retry_after)
+            });
+            let url = server.url("/rate-limited");
+            let cfg = Config {
+                max_retries: 0,
+                ..Default::default()
+            };
+
+            let got = check(&url, &cfg).unwrap_err();
+
+            let msg = crate::validate::describe_reason(url.as_str(), &got);
+            assert!(msg.starts_with("Server returned 429"), "{}", msg);
+            assert!(
+                should_mention.iter().any(|delay| msg.contains(delay)),
+                "{}",
+                msg
+            );
+        }
+    }
+
+    #[test]
+    fn accept_unsuccessful_status_codes_from_the_allowlist() {
+        let server = MockServer::start(|_| Response::new(403));
+        let url = server.url("/no-bots-allowed");
+        let cfg = Config {
+            accepted_status_codes: vec![403],
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+        assert!(check(&url, &Config::default()).is_err());
+    }
+
+    #[test]
+    fn send_requests_through_the_configured_proxy() {
+        let proxy = MockServer::start(|_| Response::new(200));
+        let url: Url = "http://example.invalid/page".parse().unwrap();
+        let cfg = Config {
+            proxy: Some(proxy.url("/")),
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+
+        let paths: Vec<_> =
+            proxy.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["http://example.invalid/page"]);
+    }
+
+    #[test]
+    fn skip_the_proxy_for_hosts_in_no_proxy() {
+        let proxy = MockServer::start(|_| Response::new(502));
+        let server = MockServer::start(|_| Response::new(200));
+        let cfg = Config {
+            proxy: Some(proxy.url("/")),
+            no_proxy: vec![String::from("127.0.0.1")],
+            ..Default::default()
+        };
+
+        check(&server.url("/page"), &cfg).unwrap();
+
+        assert!(proxy.requests().is_empty());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn match_no_proxy_domains() {
+        let no_proxy =
+            vec![String::from("example.com"), String::from(".internal")];
+        let inputs = vec![
+            ("https://example.com/", true),
+            ("https://docs.Example.com/", true),
+            ("http://wiki.internal:8080/page", true),
+            ("https://notexample.com/", false),
+            ("https://example.com.au/", false),
+            ("https://crates.io/", false),
+        ];
+
+        for (url, should_be) in inputs {
+            let got = bypasses_proxy(&url.parse().unwrap(), &no_proxy);
+            assert_eq!(got, should_be, "{}", url);
+        }
+
+        let everything = vec![String::from("*")];
+        assert!(bypasses_proxy(
+            &"https://crates.io/".parse().unwrap(),
+            &everything
+        ));
+    }
+
+    #[test]
+    fn send_basic_auth_credentials_to_the_matching_host() {
+        std::env::set_var("WIKI_PASSWORD", "open sesame");
+        let server = MockServer::start(|request| {
+            match request.header("Authorization") {
+                Some(_) => Response::new(200),
+                None => Response::new(401),
+            }
+        });
+        let url = server.url("/wiki");
+        let auth = BasicAuth {
+            username: String::from("aladdin"),
+            password: String::from("$WIKI_PASSWORD"),
+        };
+        let cfg = Config {
+            basic_auth: vec![(String::from("127.0.0.1"), auth.clone())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let other_host = Config {
+            basic_auth: vec![(String::from("example.com"), auth)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+        assert!(check(&url, &other_host).is_err());
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].header("Authorization"),
+            Some("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ==")
+        );
+        assert_eq!(requests[1].header("Authorization"), None);
+    }
+
+    #[test]
+    fn send_bearer_tokens_to_the_matching_host() {
+        std::env::set_var("DOCS_API_TOKEN", "abc123");
+        let server = MockServer::start(|request| {
+            match request.header("Authorization") {
+                Some("Bearer abc123") => Response::new(200),
+                _ => Response::new(401),
+            }
+        });
+        let url = server.url("/api/docs");
+        let token = BearerToken(String::from("$DOCS_API_TOKEN"));
+        let cfg = Config {
+            bearer_tokens: vec![(String::from("127.0.0.1"), token.clone())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let other_host = Config {
+            bearer_tokens: vec![(String::from("example.com"), token)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+        assert!(check(&url, &other_host).is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer abc123"));
+        assert_eq!(requests[1].header("Authorization"), None);
+    }
+
+    #[test]
+    fn send_the_configured_headers_with_every_request() {
+        let server = MockServer::start(|_| Response::new(200));
+        let cfg = Config {
+            headers: HashMap::from_iter(vec![
+                (String::from("Accept-Language"), String::from("fr-CA")),
+                (String::from("X-Api-Version"), String::from("2")),
+            ]),
+            http_headers: HashMap::from_iter(vec![(
+                HashedRegex::new("/v3/").unwrap(),
+                vec!["X-Api-Version: 3".try_into().unwrap()],
+            )]),
+            ..Default::default()
+        };
+
+        check(&server.url("/v2/page"), &cfg).unwrap();
+        check(&server.url("/v3/page"), &cfg).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Accept-Language"), Some("fr-CA"));
+        assert_eq!(requests[0].header("X-Api-Version"), Some("2"));
+        // headers for a particular site take precedence
+        assert_eq!(requests[1].header("Accept-Language"), Some("fr-CA"));
+        assert_eq!(requests[1].header("X-Api-Version"), Some("3"));
+    }
+
+    #[test]
+    fn send_requests_to_the_rewritten_host() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/docs" => Response::new(200),
+            _ => Response::new(404),
+        });
+        let cfg = Config {
+            host_rewrites: vec![(
+                String::from("production.example.com"),
+                server.url("/"),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let docs: Url = "https://production.example.com/docs".parse().unwrap();
+        let missing: Url =
+            "https://production.example.com/missing".parse().unwrap();
+
+        check(&docs, &cfg).unwrap();
+        let reason = check(&missing, &cfg).unwrap_err();
+
+        let got = crate::validate::describe_reason(missing.as_str(), &reason);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].path, "/missing");
+        assert!(
+            got.contains("https://production.example.com/missing"),
+            "{}",
+            got
+        );
+        assert!(!got.contains("127.0.0.1"), "{}", got);
+    }
+
+    #[test]
+    fn check_links_to_ipv6_hosts() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/page" => Response::new(200),
+            _ => Response::new(404),
+        });
+        let cfg = Config {
+            host_rewrites: vec![(String::from("::1"), server.url("/"))]
+                .into_iter()
+                .collect(),
+            requests_per_host_per_second: Some(100.0),
+            no_proxy: vec![String::from("[::1]")],
+            ..Default::default()
+        };
+        let page: Url = "http://[::1]:8080/page".parse().unwrap();
+        let missing: Url = "http://[::1]:8080/missing".parse().unwrap();
+
+        check(&page, &cfg).unwrap();
+        let reason = check(&missing, &cfg).unwrap_err();
+
+        let got = crate::validate::describe_reason(missing.as_str(), &reason);
+        assert!(got.contains("http://[::1]:8080/missing"), "{}", got);
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["/page", "/missing"]);
+        assert!(bypasses_proxy(&page, &cfg.no_proxy));
+    }
+
+    #[test]
+    fn space_out_requests_to_the_same_host() {
+        let limiter = RateLimiter::new(Some(5.0));
+        let first: Url = "https://example.com/first".parse().unwrap();
+        let second: Url = "https://example.com/second".parse().unwrap();
+        let elsewhere: Url = "https://crates.io/".parse().unwrap();
+
+        let first = limiter.reserve(&first).unwrap();
+        let elsewhere = limiter.reserve(&elsewhere).unwrap();
+        let second = limiter.reserve(&second).unwrap();
+
+        let now = Instant::now();
+        assert!(first <= now);
+        assert!(elsewhere <= now);
+        assert!(second - first >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn space_out_requests_to_any_host_with_a_global_delay() {
+        let limiter = RateLimiter::new(None)
+            .with_global_delay(Duration::from_millis(100));
+        let first: Url = "https://example.com/".parse().unwrap();
+        let second: Url = "https://crates.io/".parse().unwrap();
+
+        let slots = [
+            limiter.reserve(&first).unwrap(),
+            limiter.reserve(&second).unwrap(),
+            limiter.reserve(&first).unwrap(),
+        ];
+
+        assert!(slots[0] <= Instant::now());
+        assert!(slots[1] - slots[0] >= Duration::from_millis(100));
+        assert!(slots[2] - slots[1] >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn wait_until_the_reserved_slot() {
+        let limiter = RateLimiter::new(Some(5.0));
+        let url: Url = "https://example.com/".parse().unwrap();
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let start = Instant::now();
+        runtime.block_on(async {
+            limiter.wait(&url).await;
+            limiter.wait(&url).await;
+        });
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn rate_limiting_is_disabled_by_default() {
+        let limiter = RateLimiter::new(None);
+        let url: Url = "https://example.com/".parse().unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(limiter.reserve(&url), None);
+        }
+    }
+
+    #[test]
+    fn absurdly_slow_rates_dont_overflow() {
+        for &rate in &[1e-20, f64::MIN_POSITIVE, f64::INFINITY] {
+            let limiter = RateLimiter::new(Some(rate));
+
+            if let Some(interval) = limiter.interval {
+                assert!(interval <= Duration::from_secs(3600), "{}", rate);
+            }
+        }
+    }
+
+    /// A [`LinkFetcher`] which returns a canned status code for each path,
+    /// without touching the network.
+    #[derive(Debug)]
+    struct FakeFetcher {
+        responses: Mutex<HashMap<String, Vec<u16>>>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl FakeFetcher {
+        fn new(responses: &[(&str, &[u16])]) -> Self {
+            let responses = responses
+                .iter()
+                .map(|(path, statuses)| (path.to_string(), statuses.to_vec()))
+                .collect();
+
+            FakeFetcher {
+                responses: Mutex::new(responses),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl LinkFetcher for FakeFetcher {
+        fn fetch(
+            &self,
+            request: Request,
+        ) -> BoxFuture<'_, Result<reqwest::Response, reqwest::Error>> {
+            let path = request.url().path().to_string();
+            self.requests.lock().unwrap().push(path.clone());
+
+            // each path responds with its statuses in order, repeating the
+            // last one forever
+            let mut responses = self.responses.lock().unwrap();
+            let statuses = responses.get_mut(&path).unwrap();
+            let status = if statuses.len() > 1 {
+                statuses.remove(0)
+            } else {
+                statuses[0]
+            };
+            let response =
+                http::Response::builder().status(status).body("").unwrap();
+
+            Box::pin(futures::future::ready(Ok(reqwest::Response::from(
+                response,
+            ))))
+        }
+    }
+
+    fn check_with_fetcher(
+        url: &str,
+        cfg: &Config,
+        fetcher: &Arc<FakeFetcher>,
+    ) -> Result<(), Reason> {
+        let mut ctx = test_utils::context(cfg);
+        ctx.fetcher = Arc::clone(fetcher) as Arc<dyn LinkFetcher>;
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime.block_on(check_web(&url.parse().unwrap(), &ctx))
+    }
+
+    #[test]
+    fn a_get_request_on_its_own_only_takes_one_slot() {
+        let fetcher = Arc::new(FakeFetcher::new(&[("/page", &[200])]));
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            use_head_requests: false,
+            requests_per_host_per_second: Some(0.1),
+            ..Default::default()
+        };
+        let mut ctx = test_utils::context(&cfg);
+        ctx.fetcher = Arc::clone(&fetcher) as Arc<dyn LinkFetcher>;
+        let url: Url = "https://example.com/page".parse().unwrap();
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        let start = Instant::now();
+        runtime.block_on(check_web(&url, &ctx)).unwrap();
+
+        // the next request is due one 10 second interval later, not two
+        let next = ctx.rate_limiter.reserve(&url).unwrap();
+        assert!(next - start < Duration::from_secs(20));
+        assert_eq!(*fetcher.requests.lock().unwrap(), &["/page"]);
+    }
+
+    #[test]
+    fn requests_are_only_sent_through_the_fetcher() {
+        let fetcher = Arc::new(FakeFetcher::new(&[("/page", &[200])]));
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..Default::default()
+        };
+        let mut ctx = test_utils::context(&cfg);
+        ctx.fetcher = Arc::clone(&fetcher) as Arc<dyn LinkFetcher>;
+        let url: Url = "https://example.com/page".parse().unwrap();
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime.block_on(check_web(&url, &ctx)).unwrap();
+
+        assert_eq!(*fetcher.requests.lock().unwrap(), &["/page"]);
+        assert!(ctx.client.get().is_none(), "A second client was built");
+    }
+
+    #[test]
+    fn check_links_with_a_fake_fetcher() {
+        let fetcher = Arc::new(FakeFetcher::new(&[
+            ("/ok", &[200]),
+            ("/missing", &[404]),
+            ("/flaky", &[503, 503, 200]),
+            ("/forbidden", &[403]),
+        ]));
+        let cfg = Config {
+            retry_base_delay_ms: 1,
+            accepted_status_codes: vec![403],
+            ..Default::default()
+        };
+
+        check_with_fetcher("https://example.com/ok", &cfg, &fetcher).unwrap();
+        let err =
+            check_with_fetcher("https://example.com/missing", &cfg, &fetcher)
+                .unwrap_err();
+        check_with_fetcher("https://example.com/flaky", &cfg, &fetcher)
+            .unwrap();
+        check_with_fetcher("https://example.com/forbidden", &cfg, &fetcher)
+            .unwrap();
+
+        assert_eq!(status_of(&err), Some(StatusCode::NOT_FOUND));
+        assert_eq!(
+            *fetcher.requests.lock().unwrap(),
+            &[
+                "/ok",
+                "/missing",
+                "/flaky",
+                "/flaky",
+                "/flaky",
+                "/forbidden"
+            ]
+        );
+    }
+
+    /// Start a server where `/redirect/N` redirects `N` times before landing
+    /// on `/done`, `/loop` redirects to itself forever, and `/moved` redirects
+    /// to a page that doesn't exist.
+    fn redirecting_server() -> MockServer {
+        fn redirect(location: &str) -> Response {
+            Response::new(302).with_header("Location", location)
+        }
+
+        MockServer::start(|request| match request.path.as_str() {
+            "/loop" => redirect("/loop"),
+            "/moved" => redirect("/missing"),
+            "/done" => Response::new(200),
+            path => {
+                let remaining = path
+                    .strip_prefix("/redirect/")
+                    .and_then(|n| n.parse::<usize>().ok());
+
+                match remaining {
+                    Some(0) => redirect("/done"),
+                    Some(n) => redirect(&format!("/redirect/{}", n - 1)),
+                    None => Response::new(404),
+                }
+            },
+        })
+    }
+
+    fn too_many_redirects(reason: Reason) -> TooManyRedirects {
+        match reason {
+            Reason::Web(e) if e.is_redirect() => {
+                let inner = std::error::Error::source(&e)
+                    .and_then(|e| e.downcast_ref::<TooManyRedirects>())
+                    .expect("Should be from our redirect policy");
+                inner.clone()
+            },
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn follow_a_small_number_of_redirects() {
+        let server = redirecting_server();
+
+        check(&server.url("/redirect/0"), &Config::default()).unwrap();
+        check(&server.url("/redirect/2"), &Config::default()).unwrap();
+    }
+
+    #[test]
+    fn give_up_after_too_many_redirects() {
+        let server = redirecting_server();
+        let url = server.url("/redirect/2");
+        let cfg = Config {
+            max_redirects: 2,
+            ..Default::default()
+        };
+
+        let got = too_many_redirects(check(&url, &cfg).unwrap_err());
+
+        let should_be: Vec<_> =
+            ["/redirect/2", "/redirect/1", "/redirect/0", "/done"]
+                .iter()
+                .map(|path| server.url(path))
+                .collect();
+        assert_eq!(got.chain, should_be);
+    }
+
+    #[test]
+    fn detect_redirect_loops() {
+        let server = redirecting_server();
+        let url = server.url("/loop");
+
+        let got =
+            too_many_redirects(check(&url, &Config::default()).unwrap_err());
+
+        assert_eq!(got.chain.len(), Config::default().max_redirects + 2);
+        assert!(got.chain.iter().all(|u| *u == url));
+    }
+
+    #[test]
+    fn redirecting_to_a_missing_page_is_an_error() {
+        let server = redirecting_server();
+        let url = server.url("/moved");
+
+        let got = check(&url, &Config::default()).unwrap_err();
+
+        assert_eq!(status_of(&got), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn existence_checks_accept_redirects_without_following_them() {
+        let server = redirecting_server();
+        let url = server.url("/moved");
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Existence,
+            ..Default::default()
+        };
+
+        check(&url, &cfg).unwrap();
+        assert!(check(&url, &Config::default()).is_err());
+
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, &["/moved", "/moved", "/missing"]);
+    }
+
+    #[test]
+    fn remember_where_redirected_links_ended_up() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/old" => Response::new(301).with_header("Location", "/new"),
+            _ => Response::new(200),
+        });
+        let url = server.url("/old");
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+
+        for &mode in &[WebLinkMode::Full, WebLinkMode::Existence] {
+            let cfg = Config {
+                follow_web_links: mode,
+                warn_on_redirect: true,
+                ..Default::default()
+            };
+            let ctx = test_utils::context(&cfg);
+
+            runtime.block_on(check_web(&url, &ctx)).unwrap();
+
+            let redirects = ctx.redirects.into_inner().unwrap();
+            assert_eq!(redirects.get(&url), Some(&server.url("/new")));
+        }
+    }
+
+    #[test]
+    fn existence_checks_only_send_head_requests() {
+        let server =
+            MockServer::start(|_| Response::new(200).with_body("<html/>"));
+        let url = server.url("/page#missing-section");
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Existence,
+            use_head_requests: false,
+            check_fragments: true,
+            ..Default::default()
+        };
+        let full = Config {
+            follow_web_links: WebLinkMode::Full,
+            ..cfg.clone()
+        };
+
+        check(&url, &cfg).unwrap();
+        assert!(check(&url, &full).is_err());
+
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|r| r.method).collect();
+        assert_eq!(methods, &["HEAD", "GET"]);
+    }
+
+    #[test]
+    fn include_the_start_of_the_error_page() {
+        let server = MockServer::start(|_| {
+            Response::new(503).with_body("<h1>Down for maintenance</h1>\n")
+        });
+        let url = server.url("/page");
+        let cfg = Config {
+            use_head_requests: false,
+            max_retries: 0,
+            ..Default::default()
+        };
+
+        let got = check(&url, &cfg).unwrap_err();
+
+        assert_eq!(status_of(&got), Some(StatusCode::SERVICE_UNAVAILABLE));
+        let msg = crate::validate::describe_reason(url.as_str(), &got);
+        assert_eq!(
+            msg,
+            format!(
+                "Server returned 503 Service Unavailable for {}: \"<h1>Down for maintenance</h1>\"",
+                url
+            )
+        );
+    }
+
+    #[test]
+    fn truncate_error_pages_between_characters() {
+        let body = "ab\u{e9}";
+
+        assert_eq!(truncate_body(body.as_bytes(), 3), Some(String::from("ab")));
+        assert_eq!(truncate_body(body.as_bytes(), 4), Some(String::from(body)));
+        assert_eq!(
+            truncate_body(b"  line 1\n\tline 2  ", 200),
+            Some(String::from("line 1 line 2"))
+        );
+        assert_eq!(truncate_body(b" \n", 200), None);
+    }
+
+    #[test]
+    fn servers_which_only_support_old_tls_versions_are_reported() {
+        use std::{
+            io::{Read, Write},
+            net::TcpListener,
+        };
+
+        // a "server" which rejects every ClientHello with a protocol_version
+        // alert, the same as one which doesn't speak our minimum version
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("https://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buffer = [0; 1024];
+                let _ = stream.read(&mut buffer);
+                let _ = stream
+                    .write_all(&[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x46]);
+            }
+        });
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            min_tls_version: Some(TlsVersion::V1_2),
+            max_retries: 0,
+            ..Default::default()
+        };
+
+        let got = check(&url, &cfg).unwrap_err();
+
+        let msg = crate::validate::describe_reason(url.as_str(), &got);
+        assert_eq!(
+            msg,
+            format!(
+                "\"{}\" doesn't support TLS 1.2 or newer (see the `min-tls-version` option)",
+                url
+            )
+        );
+    }
+
+    /// Check a link which fails before the server sends a response.
+    fn link_error(url: &Url, cfg: &Config) -> LinkError {
+        let cfg = Config {
+            follow_web_links: WebLinkMode::Full,
+            max_retries: 0,
+            ..cfg.clone()
+        };
+
+        let got = check(url, &cfg).unwrap_err();
+
+        LinkError::from_reason(url.as_str(), &got)
+    }
+
+    #[test]
+    fn unknown_hosts_are_dns_failures() {
+        let url: Url =
+            "http://this-host-does-not-exist.invalid/".parse().unwrap();
+
+        match link_error(&url, &Config::default()) {
+            LinkError::DnsFailure(e) => assert_eq!(e.url, url),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refused_connections_are_connection_failures() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: Url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        drop(listener);
+
+        match link_error(&url, &Config::default()) {
+            LinkError::ConnectionFailed(e) => assert_eq!(e.url, url),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_handshakes_are_tls_errors() {
+        // the server speaks plain HTTP, so the TLS handshake can't work
+        let server = MockServer::start(|_| Response::new(200));
+        let mut url = server.url("/");
+        url.set_scheme("https").unwrap();
+
+        match link_error(&url, &Config::default()) {
+            LinkError::TlsError(e) => assert_eq!(e.url, url),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slow_servers_are_timeouts() {
+        let server = MockServer::start(|_| {
+            std::thread::sleep(Duration::from_secs(3));
+            Response::new(200)
+        });
+        let url = server.url("/");
+        let cfg = Config {
+            timeout: 1,
+            ..Default::default()
+        };
+
+        match link_error(&url, &cfg) {
+            LinkError::Timeout(e) => assert_eq!(e, Timeout { url }),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+}
@@ -3,10 +3,14 @@ extern crate pretty_assertions;
 
 use anyhow::Error;
 use codespan::{FileId, Files};
+use codespan_reporting::term::termcolor::ColorChoice;
 use linkcheck::validation::{Cache, Reason};
 use mdbook::{renderer::{RenderContext, Renderer}, MDBook};
-use mdbook_linkcheck::{Config, HashedRegex, ValidationOutcome, WarningPolicy};
-use std::{cell::Cell, collections::HashMap, convert::TryInto, iter::FromIterator, path::{Path, PathBuf}};
+use mdbook_linkcheck::{
+    Config, HashedRegex, LinkError, LinkGraph, LinkGraphEdge, MissingAnchor,
+    SkippedLinks, ValidationOutcome, WarningPolicy, WebLinkMode,
+};
+use std::{cell::Cell, collections::HashMap, convert::TryInto, io::{Read, Write}, iter::FromIterator, net::TcpListener, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread};
 
 fn test_dir() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("tests") }
 
@@ -15,12 +19,12 @@ fn check_all_links_in_a_valid_book() {
     let root = test_dir().join("all-green");
     let expected_valid = &[
         "../chapter_1.md",
-        "../chapter_1.md#Subheading",
+        "../chapter_1.md#subheading",
         "./chapter_1.html",
         "./chapter_1.md",
         "./sibling.md",
         "/chapter_1.md",
-        "/chapter_1.md#Subheading",
+        "/chapter_1.md#subheading",
         "https://crates.io/crates/mdbook-linkcheck",
         "https://www.google.com/",
         "nested/",
@@ -69,6 +73,82 @@ fn correctly_find_broken_links() {
     assert_eq!(output.incomplete_links[0].reference, "incomplete link");
 }
 
+#[test]
+fn check_intra_book_links_without_the_network() {
+    let root = test_dir().join("broken-links");
+    let config = Config {
+        follow_web_links: WebLinkMode::Off,
+        ..Default::default()
+    };
+    let expected_valid = &[
+        "../../chapter_1.md",
+        "../../second/directory.md",
+        "./chapter_1.md",
+    ];
+    let expected_broken = &[
+        "./foo/bar/baz.html",
+        "../../../../../../../../../../../../etc/shadow",
+        "./asdf.png",
+        "./chapter_1.md",
+        "./second/directory.md",
+        "sibling.md",
+    ];
+
+    let output = TestRun::new_with_config(root, config)
+        .after_validation(|files, outcome, _| {
+            let diags =
+                outcome.generate_diagnostics(files, WarningPolicy::Ignore);
+
+            assert!(diags.iter().any(
+                |diag| diag.message == "File not found: ./foo/bar/baz.html"
+            ));
+        })
+        .execute()
+        .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn links_may_not_leave_the_source_directory() {
+    let root = test_dir().join("broken-links");
+    let escapes = "../../../../../../../../../../../../etc/shadow";
+    let in_bounds = "../../chapter_1.md";
+    let config = Config {
+        traverse_parent_directories: false,
+        ..Default::default()
+    };
+
+    let output = run_link_checker_with_config(&root, config).unwrap();
+
+    assert!(output.valid_links.iter().any(|link| link.href == in_bounds));
+    let broken = output
+        .invalid_links
+        .iter()
+        .find(|invalid| invalid.link.href == escapes)
+        .unwrap();
+    assert!(matches!(broken.reason, Reason::TraversesParentDirectories));
+
+    let config = Config {
+        traverse_parent_directories: true,
+        ..Default::default()
+    };
+    let output = run_link_checker_with_config(&root, config).unwrap();
+
+    assert!(output.invalid_links.iter().all(|invalid| !matches!(
+        invalid.reason,
+        Reason::TraversesParentDirectories
+    )));
+}
+
 #[test]
 fn detect_when_a_linked_file_isnt_in_summary_md() {
     let root = test_dir().join("broken-links");
@@ -86,6 +166,660 @@ fn detect_when_a_linked_file_isnt_in_summary_md() {
     ));
 }
 
+#[test]
+fn resolve_links_to_chapters() {
+    let root = test_dir().join("chapter-targets");
+    let expected_valid = &[
+        "./chapter_2.md",
+        "./chapter_2.html",
+        "./chapter_3/",
+        "./chapter_3/index.html",
+        "./guide/",
+        "./guide/index.html",
+    ];
+    let expected_broken =
+        &["./not_in_summary.html", "./missing.html", "./no_index/"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+    let not_in_summary = &output
+        .invalid_links
+        .iter()
+        .find(|invalid| invalid.link.href == "./not_in_summary.html")
+        .unwrap()
+        .reason;
+    assert!(is_specific_error::<mdbook_linkcheck::NotInSummary>(
+        not_in_summary
+    ));
+}
+
+#[test]
+fn links_to_assets_copied_into_the_book_are_valid() {
+    let root = test_dir().join("assets");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let expected_valid = &["theme/custom.css", "../theme/custom.css"];
+    let expected_broken = &["theme/missing.css"];
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &Config::default(),
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn links_to_chapters_which_arent_being_checked_are_still_valid() {
+    let root = test_dir().join("chapter-targets");
+    let cfg = Config::default();
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let expected_valid = &[
+        "./chapter_2.md",
+        "./chapter_2.html",
+        "./chapter_3/",
+        "./chapter_3/index.html",
+        "./guide/",
+        "./guide/index.html",
+    ];
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &cfg,
+        reqwest::Client::new(),
+        |chapter| chapter == Path::new("chapter_1.md"),
+    )
+    .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+}
+
+#[test]
+fn query_strings_on_links_to_chapters_are_ignored() {
+    let root = test_dir().join("query-strings");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let expected_valid = &[
+        "./chapter_2.html?foo=bar#installation",
+        "./guide/?tab=install",
+        "./guide/index.html?tab=install#guide",
+    ];
+    let expected_broken = &[
+        "./chapter_2.md?foo=bar#missing",
+        "./missing.md?foo=bar",
+        "./chapter_2.md/?foo=bar",
+    ];
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &Config::default(),
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn send_web_requests_with_a_custom_client() {
+    let root = test_dir().join("http-links");
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy_url = format!("http://{}/", proxy.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&requests);
+    // pretend every web page exists
+    thread::spawn(move || {
+        for mut stream in proxy.incoming().flatten() {
+            let mut buffer = [0; 1024];
+            let len = stream.read(&mut buffer).unwrap_or(0);
+            let request = String::from_utf8_lossy(&buffer[..len]);
+            if let Some(line) = request.lines().next() {
+                recorded.lock().unwrap().push(line.to_string());
+            }
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            );
+        }
+    });
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(&proxy_url).unwrap())
+        .build()
+        .unwrap();
+    let cfg = Config {
+        follow_web_links: WebLinkMode::Full,
+        ..Default::default()
+    };
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let mut cache = Cache::default();
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut cache,
+        &cfg,
+        client,
+        |_| true,
+    )
+    .unwrap();
+
+    let expected_valid = &[
+        "http://example.invalid/",
+        "http://example.invalid/nested/page.html",
+    ];
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    assert!(output.invalid_links.is_empty());
+    let mut requests = requests.lock().unwrap().clone();
+    requests.sort();
+    assert_eq!(
+        requests,
+        &[
+            "HEAD http://example.invalid/ HTTP/1.1",
+            "HEAD http://example.invalid/nested/page.html HTTP/1.1",
+        ]
+    );
+}
+
+#[test]
+fn summarise_a_book_with_a_check_report() {
+    let root = test_dir().join("chapter-targets");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+
+    let expected_broken =
+        &["./not_in_summary.html", "./missing.html", "./no_index/"];
+
+    let report = mdbook_linkcheck::check(&ctx).unwrap();
+
+    assert_eq!(report.checked, 9);
+    assert_eq!(report.skipped, 0);
+    assert_eq!(report.cached, 0);
+    let broken: Vec<_> =
+        report.broken.iter().map(|(link, _)| &link.href).collect();
+    assert_same_links(expected_broken, broken);
+    let summary = report.to_string();
+    assert!(
+        summary.starts_with("Checked 9 links (6 ok, 0 skipped, 3 broken) in "),
+        "{}",
+        summary
+    );
+    assert!(summary.ends_with('s'), "{}", summary);
+}
+
+#[test]
+fn check_images_exist() {
+    let root = test_dir().join("images");
+    let expected_valid = &[
+        "images/flow.png",
+        "./nested/diagram.svg#icon",
+        "./images/flow.png",
+        "../images/flow.png",
+        "diagram.svg",
+    ];
+    let expected_broken = &["images/missing.png", "images/flow.png"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn check_every_image_in_a_srcset() {
+    let root = test_dir().join("responsive-images");
+    let expected_valid = &[
+        "./images/diagram.svg",
+        "./images/small.png",
+        "./images/small.png",
+        "./images/large.png",
+    ];
+    let expected_broken = &["./images/huge.png"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn only_check_links_with_the_configured_extensions() {
+    let root = test_dir().join("checked-extensions");
+    let config = Config {
+        checked_extensions: Some(vec![String::from("md"), String::from("png")]),
+        ..Default::default()
+    };
+
+    let output = run_link_checker_with_config(&root, config).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(["./images/flow.png"], valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(["./images/missing.png"], broken);
+    let ignored: Vec<_> =
+        output.ignored.iter().map(|link| &link.href).collect();
+    assert_same_links(["./downloads/examples.zip"], ignored);
+}
+
+#[test]
+fn find_the_links_in_a_book_without_checking_them() {
+    let md = MDBook::load(test_dir().join("images")).unwrap();
+
+    let got: Vec<_> = mdbook_linkcheck::find_links(&md.book)
+        .into_iter()
+        .map(|link| {
+            format!("{}:{} {}", link.chapter.display(), link.line, link.href)
+        })
+        .collect();
+
+    assert_eq!(
+        got,
+        &[
+            "chapter_1.md:3 images/flow.png",
+            "chapter_1.md:5 ./nested/diagram.svg#icon",
+            "chapter_1.md:7 ./images/flow.png",
+            "chapter_1.md:9 images/missing.png",
+            "nested/chapter_2.md:3 ../images/flow.png",
+            "nested/chapter_2.md:5 diagram.svg",
+            "nested/chapter_2.md:9 images/flow.png",
+        ]
+    );
+}
+
+#[test]
+fn percent_decode_local_links() {
+    let root = test_dir().join("local-paths");
+    let expected_valid = &[
+        "my%20folder/page.md",
+        "./my%20folder/page.md#a-page",
+        "my folder/page.md",
+        "./my folder/page.md#a-page",
+        "../chapter_1.md",
+    ];
+    let expected_broken = &["my%20folder/missing.md", "my%zzfolder/page.md"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+    let malformed = &output
+        .invalid_links
+        .iter()
+        .find(|invalid| invalid.link.href == "my%zzfolder/page.md")
+        .unwrap()
+        .reason;
+    assert!(is_specific_error::<mdbook_linkcheck::MalformedLink>(
+        malformed
+    ));
+}
+
+#[test]
+fn backslashes_are_path_separators() {
+    let root = test_dir().join("backslash-paths");
+    let expected_valid = &[
+        "subdir\\chapter.md",
+        ".\\subdir\\chapter.md#nested",
+        "..\\chapter_1.md",
+    ];
+    let expected_broken = &["..\\missing.md"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn root_relative_links_start_at_the_source_directory() {
+    let root = test_dir().join("site-root");
+    let expected_valid =
+        &["/index.html", "/nested/page.md", "/nested/page.md#a-page"];
+    let expected_broken =
+        &["/nested/missing.md", "/my-book/nested/page.md", "/my-book/"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn root_relative_links_can_include_the_site_root() {
+    let root = test_dir().join("site-root");
+    let expected_valid = &["/my-book/nested/page.md", "/my-book/"];
+    let expected_ignored = &[
+        "/index.html",
+        "/nested/page.md",
+        "/nested/page.md#a-page",
+        "/nested/missing.md",
+    ];
+    let config = Config {
+        site_root: Some(String::from("/my-book/")),
+        ..Default::default()
+    };
+
+    let output = run_link_checker_with_config(&root, config).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let ignored: Vec<_> =
+        output.ignored.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_ignored, ignored);
+    assert!(
+        output.invalid_links.is_empty(),
+        "Found invalid links: {:?}",
+        output.invalid_links
+    );
+}
+
+#[test]
+fn skip_links_listed_in_the_ignore_file() {
+    let root = test_dir().join("ignore-file");
+    let expected_valid = &["./chapter_1.md"];
+    let expected_ignored =
+        &["https://intranet.example.com/wiki", "./drafts/chapter_2.md"];
+    let config = Config {
+        follow_web_links: WebLinkMode::Full,
+        ..Default::default()
+    }
+    .with_ignore_file(&root)
+    .unwrap();
+
+    let output = run_link_checker_with_config(&root, config).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let ignored: Vec<_> =
+        output.ignored.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_ignored, ignored);
+    assert!(
+        output.invalid_links.is_empty(),
+        "Found invalid links: {:?}",
+        output.invalid_links
+    );
+}
+
+#[test]
+fn check_uses_the_ignore_file_too() {
+    let root = test_dir().join("ignore-file");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+
+    let report = mdbook_linkcheck::check(&ctx).unwrap();
+
+    // without the ignore file, the link to the unwritten chapter is broken
+    assert!(!report.has_broken_links(), "{}", report);
+    assert_eq!(report.skipped, 2);
+}
+
+#[test]
+fn draft_chapters_are_skipped() {
+    let root = test_dir().join("draft-chapters");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+
+    let report = mdbook_linkcheck::check(&ctx).unwrap();
+
+    assert_eq!(report.checked, 2);
+    assert!(
+        !report.has_broken_links(),
+        "Found broken links: {}",
+        report.broken
+    );
+}
+
+#[test]
+fn fail_when_links_are_skipped_if_asked_to() {
+    let root = test_dir().join("fail-on-skipped");
+    let md = MDBook::load(&root).unwrap();
+    let mut ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+
+    let err = mdbook_linkcheck::run(None, ColorChoice::Never, &ctx, None)
+        .unwrap_err();
+
+    let skipped = err.downcast_ref::<SkippedLinks>().unwrap();
+    let hrefs: Vec<_> = skipped.links.iter().map(|link| &link.href).collect();
+    assert_eq!(hrefs, &["https://forbidden.com/"]);
+    assert!(skipped.to_string().contains("chapter_1.md:\n  4:1"));
+
+    ctx.config
+        .set("output.linkcheck.fail-on-skipped", false)
+        .unwrap();
+    mdbook_linkcheck::run(None, ColorChoice::Never, &ctx, None).unwrap();
+}
+
+#[test]
+fn chapters_can_override_the_config_in_their_frontmatter() {
+    let root = test_dir().join("frontmatter");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let config = Config {
+        follow_web_links: WebLinkMode::Full,
+        ..Default::default()
+    };
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &config,
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(["./dead_links.md"], valid);
+    assert!(
+        output.invalid_links.is_empty(),
+        "{:?}",
+        output.invalid_links
+    );
+    let ignored: Vec<_> =
+        output.ignored.iter().map(|link| &link.href).collect();
+    assert_same_links(["./generated/api.md"], ignored);
+}
+
+#[test]
+fn skip_the_links_in_ignored_chapters() {
+    let root = test_dir().join("ignore-chapters");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+
+    let report = mdbook_linkcheck::check(&ctx).unwrap();
+
+    // only the link from chapter 1 to the API docs gets checked
+    assert_eq!(report.checked, 1);
+    assert!(
+        !report.has_broken_links(),
+        "Found broken links: {}",
+        report.broken
+    );
+}
+
+#[test]
+fn detect_links_to_missing_anchors() {
+    let root = test_dir().join("anchors");
+    let expected_valid = &[
+        "#",
+        "#my-section",
+        "#my-id",
+        "#note",
+        "./chapter_1.md#chapter-1",
+        "other.md#my-section",
+        "other.html#my-section",
+    ];
+    let expected_broken = &[
+        "#nonexistent",
+        "other.md#nonexistent",
+        "other.md#my-sectoin",
+    ];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+    assert!(output.invalid_links.iter().all(|invalid| {
+        is_specific_error::<mdbook_linkcheck::MissingAnchor>(&invalid.reason)
+    }));
+    let other_chapter = output
+        .invalid_links
+        .iter()
+        .find(|invalid| invalid.link.href == "other.md#nonexistent")
+        .unwrap();
+    assert_eq!(
+        LinkError::from(other_chapter),
+        LinkError::MissingAnchor(MissingAnchor {
+            path: PathBuf::from("other.md"),
+            fragment: String::from("nonexistent"),
+            suggestion: None,
+        })
+    );
+    let typo = output
+        .invalid_links
+        .iter()
+        .find(|invalid| invalid.link.href == "other.md#my-sectoin")
+        .unwrap();
+    assert_eq!(
+        LinkError::from(typo).to_string(),
+        "There is no \"#my-sectoin\" anchor in \"other.md\", did you mean \"#my-section\"?"
+    );
+}
+
 #[test]
 fn emit_valid_suggestions_on_absolute_links() {
     let root = test_dir().join("absolute-links");
@@ -103,7 +837,7 @@ fn emit_valid_suggestions_on_absolute_links() {
             assert_eq!(suggestions.len(), diags.len());
 
             for (diag, suggestion) in
-                diags.into_iter().zip(suggestions.into_iter())
+                diags.into_iter().zip(suggestions)
             {
                 assert!(
                     diag.notes.iter().any(|note| note.contains(suggestion)),
@@ -122,12 +856,12 @@ fn skip_web_links() {
     let root = test_dir().join("external-links");
     let expected_valid = &[
         "../chapter_1.md",
-        "../chapter_1.md#Subheading",
+        "../chapter_1.md#subheading",
         "./chapter_1.html",
         "./chapter_1.md",
         "./sibling.md",
         "/chapter_1.md",
-        "/chapter_1.md#Subheading",
+        "/chapter_1.md#subheading",
         "nested/",
         "nested/README.md",
         "sibling.md",
@@ -140,7 +874,7 @@ fn skip_web_links() {
     ];
 
     let config = Config {
-        follow_web_links: false,
+        follow_web_links: WebLinkMode::Off,
         ..Default::default()
     };
 
@@ -198,11 +932,13 @@ where
     assert_eq!(left, right);
 }
 
+type AfterValidation =
+    Box<dyn Fn(&Files<String>, &ValidationOutcome, &Vec<FileId>)>;
+
 struct TestRun {
     config: Config,
     root: PathBuf,
-    after_validation:
-        Box<dyn Fn(&Files<String>, &ValidationOutcome, &Vec<FileId>)>,
+    after_validation: AfterValidation,
     validation_outcome: Cell<Option<ValidationOutcome>>,
 }
 
@@ -211,7 +947,7 @@ impl TestRun {
         TestRun {
             root: root.into(),
             config: Config {
-                follow_web_links: true,
+                follow_web_links: WebLinkMode::Full,
                 traverse_parent_directories: false,
                 exclude: vec![r"forbidden\.com".parse().unwrap()],
                 http_headers: HashMap::from_iter(vec![(
@@ -313,3 +1049,159 @@ fn run_link_checker_with_config(
 ) -> Result<ValidationOutcome, Error> {
     TestRun::new_with_config(root, config).execute()
 }
+
+#[test]
+fn trailing_slashes_dont_change_the_target() {
+    let root = test_dir().join("trailing-slashes");
+    let expected_valid = &[
+        "guide",
+        "guide/",
+        "guide//",
+        "./guide/#guide",
+        "reference",
+        "reference/",
+        "./reference//#reference",
+        "../chapter_1.md",
+        "../guide/",
+        "..//guide",
+    ];
+    // only directories can have a trailing slash
+    let expected_broken = &[
+        "guide/installation.md/",
+        "guide/installation.html/",
+        "missing/",
+    ];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn links_can_point_into_linked_books() {
+    let root = test_dir().join("linked-books").join("first");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let cfg = mdbook_linkcheck::get_config(&md.config).unwrap();
+    let expected_valid = &["../second/", "../second/guide/installation.html"];
+    let expected_broken = &[
+        "../second/missing.html",
+        "../second/draft.md",
+        "../third/chapter_1.html",
+    ];
+
+    let (_, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &cfg,
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+
+    let valid: Vec<_> =
+        output.valid_links.iter().map(|link| &link.href).collect();
+    assert_same_links(expected_valid, valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| &invalid.link.href)
+        .collect();
+    assert_same_links(expected_broken, broken);
+}
+
+#[test]
+fn build_the_graph_of_links_between_chapters() {
+    let root = test_dir().join("link-graph");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let edge = |from: &str, to: &str| LinkGraphEdge {
+        from: PathBuf::from(from),
+        to: PathBuf::from(to),
+    };
+
+    let (files, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &Config::default(),
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+    let got = LinkGraph::new(&ctx.book, &output, &files);
+
+    assert_eq!(
+        got.chapters,
+        vec![
+            PathBuf::from("README.md"),
+            PathBuf::from("chapter_1.md"),
+            PathBuf::from("chapter_2.md"),
+            PathBuf::from("nested/README.md"),
+        ]
+    );
+    assert_eq!(
+        got.edges,
+        vec![
+            edge("README.md", "chapter_1.md"),
+            edge("chapter_1.md", "chapter_2.md"),
+            edge("chapter_1.md", "nested/README.md"),
+            edge("chapter_1.md", "README.md"),
+            edge("chapter_2.md", "chapter_1.md"),
+        ]
+    );
+}
+
+#[test]
+fn report_chapters_nothing_links_to() {
+    let root = test_dir().join("orphans");
+    let md = MDBook::load(&root).unwrap();
+    let ctx = RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        root.join("book"),
+    );
+    let cfg = Config {
+        report_orphans: true,
+        ..Default::default()
+    };
+
+    let (files, output) = mdbook_linkcheck::check_links_with_client(
+        &ctx,
+        &mut Cache::default(),
+        &cfg,
+        reqwest::Client::new(),
+        |_| true,
+    )
+    .unwrap();
+
+    assert_eq!(output.orphaned_chapters, vec![PathBuf::from("orphan.md")]);
+    let diags = output.generate_diagnostics_with_policies(
+        &files,
+        WarningPolicy::Warn,
+        WarningPolicy::Error,
+    );
+    assert_eq!(diags.len(), 1);
+    assert_eq!(
+        diags[0].message,
+        "Nothing links to \"orphan.md\", so it can only be found using the table of contents"
+    );
+}